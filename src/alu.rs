@@ -0,0 +1,479 @@
+//! The arithmetic and logic unit. Every operation is a pure function of its
+//! operands (and the incoming carry where relevant) returning the result
+//! together with the complete flag state it leaves behind, so the register,
+//! (HL) and immediate forms of an instruction all share one implementation.
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Flags {
+    pub z: bool,
+    pub n: bool,
+    pub h: bool,
+    pub c: bool,
+}
+
+impl Flags {
+    pub fn to_byte(self) -> u8 {
+        (self.z as u8) << 7 | (self.n as u8) << 6 | (self.h as u8) << 5 | (self.c as u8) << 4
+    }
+
+    /// The low nibble of F doesn't exist and is dropped.
+    pub fn from_byte(value: u8) -> Flags {
+        Flags {
+            z: value & 0x80 != 0,
+            n: value & 0x40 != 0,
+            h: value & 0x20 != 0,
+            c: value & 0x10 != 0,
+        }
+    }
+}
+
+/// ADD and ADC.
+pub fn add(a: u8, b: u8, carry: bool) -> (u8, Flags) {
+    let sum = a as u16 + b as u16 + carry as u16;
+    let result = sum as u8;
+    let flags = Flags {
+        z: result == 0,
+        n: false,
+        // Bit 4 of the result differs from a ^ b exactly when bit 3 carried
+        h: (a ^ b ^ result) & 0x10 != 0,
+        c: sum > 0xFF,
+    };
+    (result, flags)
+}
+
+/// SUB, SBC and CP.
+pub fn sub(a: u8, b: u8, carry: bool) -> (u8, Flags) {
+    let difference = (a as u16).wrapping_sub(b as u16).wrapping_sub(carry as u16);
+    let result = difference as u8;
+    let flags = Flags {
+        z: result == 0,
+        n: true,
+        h: (a ^ b ^ result) & 0x10 != 0,
+        c: difference > 0xFF,
+    };
+    (result, flags)
+}
+
+pub fn and(a: u8, b: u8) -> (u8, Flags) {
+    let result = a & b;
+    let flags = Flags {
+        z: result == 0,
+        n: false,
+        h: true,
+        c: false,
+    };
+    (result, flags)
+}
+
+pub fn xor(a: u8, b: u8) -> (u8, Flags) {
+    let result = a ^ b;
+    let flags = Flags {
+        z: result == 0,
+        ..Flags::default()
+    };
+    (result, flags)
+}
+
+pub fn or(a: u8, b: u8) -> (u8, Flags) {
+    let result = a | b;
+    let flags = Flags {
+        z: result == 0,
+        ..Flags::default()
+    };
+    (result, flags)
+}
+
+/// INC leaves the carry alone.
+pub fn inc(value: u8, carry: bool) -> (u8, Flags) {
+    let result = value.wrapping_add(1);
+    let flags = Flags {
+        z: result == 0,
+        n: false,
+        h: value & 0x0F == 0x0F,
+        c: carry,
+    };
+    (result, flags)
+}
+
+/// DEC leaves the carry alone.
+pub fn dec(value: u8, carry: bool) -> (u8, Flags) {
+    let result = value.wrapping_sub(1);
+    let flags = Flags {
+        z: result == 0,
+        n: true,
+        h: value & 0x0F == 0x00,
+        c: carry,
+    };
+    (result, flags)
+}
+
+/// ADD HL,rr: the carries come out of bits 11 and 15 and Z is untouched.
+pub fn add16(a: u16, b: u16, zero: bool) -> (u16, Flags) {
+    let sum = a as u32 + b as u32;
+    let result = sum as u16;
+    let flags = Flags {
+        z: zero,
+        n: false,
+        h: (a ^ b ^ result) & 0x1000 != 0,
+        c: sum > 0xFFFF,
+    };
+    (result, flags)
+}
+
+/// ADD SP,e and LD HL,SP+e. The offset is signed, but the flags come from
+/// an unsigned add of the low bytes.
+pub fn add_sp(sp: u16, offset: u8) -> (u16, Flags) {
+    let offset = offset as i8 as u16;
+    let result = sp.wrapping_add(offset);
+    let carries = sp ^ offset ^ result;
+    let flags = Flags {
+        z: false,
+        n: false,
+        h: carries & 0x010 != 0,
+        c: carries & 0x100 != 0,
+    };
+    (result, flags)
+}
+
+/// Adjusts A back into BCD after an addition or subtraction.
+pub fn daa(a: u8, flags: Flags) -> (u8, Flags) {
+    let mut correction = 0;
+    let mut carry = flags.c;
+    if flags.h || (!flags.n && a & 0x0F > 0x09) {
+        correction |= 0x06;
+    }
+    if flags.c || (!flags.n && a > 0x99) {
+        correction |= 0x60;
+        carry = true;
+    }
+
+    let result = if flags.n {
+        a.wrapping_sub(correction)
+    } else {
+        a.wrapping_add(correction)
+    };
+    let flags = Flags {
+        z: result == 0,
+        n: flags.n,
+        h: false,
+        c: carry,
+    };
+    (result, flags)
+}
+
+// Rotates and shifts set Z from the result, clear N and H, and put the bit
+// shifted out into C. The accumulator forms clear Z on top of this.
+
+fn shifted(result: u8, carry: bool) -> (u8, Flags) {
+    let flags = Flags {
+        z: result == 0,
+        n: false,
+        h: false,
+        c: carry,
+    };
+    (result, flags)
+}
+
+pub fn rlc(value: u8) -> (u8, Flags) {
+    shifted(value.rotate_left(1), value & 0x80 != 0)
+}
+
+pub fn rrc(value: u8) -> (u8, Flags) {
+    shifted(value.rotate_right(1), value & 0x01 != 0)
+}
+
+pub fn rl(value: u8, carry: bool) -> (u8, Flags) {
+    shifted(value << 1 | carry as u8, value & 0x80 != 0)
+}
+
+pub fn rr(value: u8, carry: bool) -> (u8, Flags) {
+    shifted(value >> 1 | (carry as u8) << 7, value & 0x01 != 0)
+}
+
+pub fn sla(value: u8) -> (u8, Flags) {
+    shifted(value << 1, value & 0x80 != 0)
+}
+
+/// Keeps the sign bit.
+pub fn sra(value: u8) -> (u8, Flags) {
+    shifted(value >> 1 | value & 0x80, value & 0x01 != 0)
+}
+
+pub fn srl(value: u8) -> (u8, Flags) {
+    shifted(value >> 1, value & 0x01 != 0)
+}
+
+pub fn swap(value: u8) -> (u8, Flags) {
+    shifted(value.rotate_left(4), false)
+}
+
+/// BIT leaves the carry alone.
+pub fn bit(bit: u8, value: u8, carry: bool) -> Flags {
+    Flags {
+        z: value & (1 << bit) == 0,
+        n: false,
+        h: true,
+        c: carry,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pairs() -> impl Iterator<Item = (u8, u8)> {
+        (0..=0xFFFF).map(|i: u32| ((i >> 8) as u8, i as u8))
+    }
+
+    // Reference models written the long way round, nibble by nibble
+
+    fn reference_add(a: u8, b: u8, carry: bool) -> (u8, Flags) {
+        let c = carry as u32;
+        let sum = a as u32 + b as u32 + c;
+        let result = (sum % 0x100) as u8;
+        let flags = Flags {
+            z: result == 0,
+            n: false,
+            h: (a as u32 % 0x10) + (b as u32 % 0x10) + c >= 0x10,
+            c: sum >= 0x100,
+        };
+        (result, flags)
+    }
+
+    fn reference_sub(a: u8, b: u8, carry: bool) -> (u8, Flags) {
+        let c = carry as i32;
+        let difference = a as i32 - b as i32 - c;
+        let result = difference.rem_euclid(0x100) as u8;
+        let flags = Flags {
+            z: result == 0,
+            n: true,
+            h: (a as i32 % 0x10) - (b as i32 % 0x10) - c < 0,
+            c: difference < 0,
+        };
+        (result, flags)
+    }
+
+    #[test]
+    fn add_and_adc_match_reference() {
+        for (a, b) in pairs() {
+            for carry in [false, true] {
+                assert_eq!(
+                    add(a, b, carry),
+                    reference_add(a, b, carry),
+                    "{:#04x} + {:#04x} + {}",
+                    a,
+                    b,
+                    carry
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn sub_and_sbc_match_reference() {
+        for (a, b) in pairs() {
+            for carry in [false, true] {
+                assert_eq!(
+                    sub(a, b, carry),
+                    reference_sub(a, b, carry),
+                    "{:#04x} - {:#04x} - {}",
+                    a,
+                    b,
+                    carry
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn logic_ops_match_reference() {
+        for (a, b) in pairs() {
+            let expect = |result: u8, h: bool| {
+                let flags = Flags {
+                    z: result == 0,
+                    n: false,
+                    h,
+                    c: false,
+                };
+                (result, flags)
+            };
+            assert_eq!(and(a, b), expect(a & b, true));
+            assert_eq!(xor(a, b), expect(a ^ b, false));
+            assert_eq!(or(a, b), expect(a | b, false));
+        }
+    }
+
+    #[test]
+    fn inc_and_dec_match_add_and_sub_except_carry() {
+        for value in 0..=0xFF {
+            for carry in [false, true] {
+                let (result, mut flags) = reference_add(value, 1, false);
+                flags.c = carry;
+                assert_eq!(inc(value, carry), (result, flags));
+
+                let (result, mut flags) = reference_sub(value, 1, false);
+                flags.c = carry;
+                assert_eq!(dec(value, carry), (result, flags));
+            }
+        }
+    }
+
+    #[test]
+    fn add_sp_matches_reference() {
+        // Every low byte of SP against every offset, under a few high bytes
+        for high in [0x00, 0x7F, 0xFF] {
+            for (low, offset) in pairs() {
+                let sp = (high as u16) << 8 | low as u16;
+                let (_, expected) = reference_add(low, offset, false);
+                let result = (sp as i32 + offset as i8 as i32).rem_euclid(0x10000) as u16;
+                let flags = Flags {
+                    z: false,
+                    n: false,
+                    ..expected
+                };
+                assert_eq!(add_sp(sp, offset), (result, flags));
+            }
+        }
+    }
+
+    #[test]
+    fn add16_carries_out_of_bits_11_and_15() {
+        // Every pair of low 12 bits, which is all bit 11 depends on, with
+        // the top nibbles varying alongside so bit 15 carries both ways
+        for low_a in 0..0x1000u16 {
+            for low_b in 0..0x1000u16 {
+                let a = (low_a >> 8) << 12 | low_a;
+                let b = (0x0F - (low_b >> 8)) << 12 | low_b;
+                let (result, flags) = add16(a, b, true);
+                assert_eq!(result, a.wrapping_add(b));
+                assert!(flags.z && !flags.n);
+                assert_eq!(flags.h, low_a + low_b > 0x0FFF);
+                assert_eq!(flags.c, a as u32 + b as u32 > 0xFFFF);
+            }
+        }
+    }
+
+    #[test]
+    fn daa_produces_bcd_sums_and_differences() {
+        let bcd = |n: u8| (n / 10) << 4 | (n % 10);
+        for x in 0..100 {
+            for y in 0..100 {
+                let (sum, flags) = add(bcd(x), bcd(y), false);
+                let (result, flags) = daa(sum, flags);
+                assert_eq!(result, bcd((x + y) % 100));
+                assert_eq!(flags.c, x + y >= 100);
+                assert_eq!(flags.z, (x + y) % 100 == 0);
+
+                let (difference, flags) = sub(bcd(x), bcd(y), false);
+                let (result, flags) = daa(difference, flags);
+                assert_eq!(result, bcd((100 + x - y) % 100));
+                assert_eq!(flags.c, x < y);
+            }
+        }
+    }
+
+    #[test]
+    fn rotates_and_shifts() {
+        for value in 0..=0xFF {
+            let wide = value as u16;
+            assert_eq!(rlc(value).0, (wide << 1 | wide >> 7) as u8);
+            assert_eq!(rrc(value).0, (wide >> 1 | wide << 7) as u8);
+            assert_eq!(
+                rl(value, true),
+                shifted((wide << 1 | 1) as u8, value >= 0x80)
+            );
+            assert_eq!(rr(value, true), shifted(value / 2 + 0x80, value % 2 == 1));
+            assert_eq!(sla(value), shifted((wide * 2) as u8, value >= 0x80));
+            assert_eq!(sra(value).0, ((value as i8) >> 1) as u8);
+            assert_eq!(srl(value), shifted(value / 2, value % 2 == 1));
+            assert_eq!(swap(value).0, (value % 0x10) * 0x10 + value / 0x10);
+            assert_eq!(Flags::from_byte(value).to_byte(), value & 0xF0);
+        }
+    }
+
+    // Vectors below are results observed on DMG hardware, as recorded by
+    // blargg's cpu_instrs and the SameBoy test tables, rather than derived
+    // from a model. Flags are given as F: Z=0x80, N=0x40, H=0x20, C=0x10.
+
+    #[test]
+    fn daa_matches_hardware() {
+        let vectors: [(u8, u8, u8, u8); 13] = [
+            // A, F in -> A, F out
+            (0x00, 0x00, 0x00, 0x80),
+            (0x0A, 0x00, 0x10, 0x00),
+            (0x99, 0x00, 0x99, 0x00),
+            (0x9A, 0x00, 0x00, 0x90),
+            (0xFA, 0x00, 0x60, 0x10),
+            (0x12, 0x20, 0x18, 0x00),
+            (0x00, 0x10, 0x60, 0x10),
+            (0x00, 0x30, 0x66, 0x10),
+            // After a subtraction only the flags decide the adjustment
+            (0x9A, 0x40, 0x9A, 0x40),
+            (0x0F, 0x60, 0x09, 0x40),
+            (0x70, 0x50, 0x10, 0x50),
+            (0xFF, 0x70, 0x99, 0x50),
+            (0x66, 0x70, 0x00, 0xD0),
+        ];
+        for (a, f, result, flags) in vectors {
+            assert_eq!(
+                daa(a, Flags::from_byte(f)),
+                (result, Flags::from_byte(flags)),
+                "DAA with A={:#04x} F={:#04x}",
+                a,
+                f
+            );
+        }
+    }
+
+    #[test]
+    fn half_carry_edges_match_hardware() {
+        let f = Flags::from_byte;
+        // Operation, result, flags
+        let vectors: [(&str, (u8, Flags), u8, u8); 14] = [
+            ("ADD 0F+01", add(0x0F, 0x01, false), 0x10, 0x20),
+            ("ADD FF+01", add(0xFF, 0x01, false), 0x00, 0xB0),
+            ("ADD 80+80", add(0x80, 0x80, false), 0x00, 0x90),
+            ("ADC 0E+01+1", add(0x0E, 0x01, true), 0x10, 0x20),
+            ("ADC 0F+F0+1", add(0x0F, 0xF0, true), 0x00, 0xB0),
+            ("ADC 00+0F+1", add(0x00, 0x0F, true), 0x10, 0x20),
+            ("SUB 10-01", sub(0x10, 0x01, false), 0x0F, 0x60),
+            ("SUB 00-01", sub(0x00, 0x01, false), 0xFF, 0x70),
+            ("SUB 3E-3E", sub(0x3E, 0x3E, false), 0x00, 0xC0),
+            ("SBC 10-00-1", sub(0x10, 0x00, true), 0x0F, 0x60),
+            ("SBC 00-FF-1", sub(0x00, 0xFF, true), 0x00, 0xF0),
+            ("SBC 01-00-1", sub(0x01, 0x00, true), 0x00, 0xC0),
+            ("INC 0F", inc(0x0F, false), 0x10, 0x20),
+            ("DEC 10", dec(0x10, true), 0x0F, 0x70),
+        ];
+        for (name, actual, result, flags) in vectors {
+            assert_eq!(actual, (result, f(flags)), "{}", name);
+        }
+
+        let wide: [(&str, (u16, Flags), u16, u8); 6] = [
+            (
+                "ADD HL 0FFF+0001",
+                add16(0x0FFF, 0x0001, false),
+                0x1000,
+                0x20,
+            ),
+            (
+                "ADD HL 8000+8000",
+                add16(0x8000, 0x8000, true),
+                0x0000,
+                0x90,
+            ),
+            (
+                "ADD HL FFFF+0001",
+                add16(0xFFFF, 0x0001, false),
+                0x0000,
+                0x30,
+            ),
+            ("ADD SP 00FF+01", add_sp(0x00FF, 0x01), 0x0100, 0x30),
+            ("ADD SP 0000-01", add_sp(0x0000, 0xFF), 0xFFFF, 0x00),
+            ("ADD SP FFFF+01", add_sp(0xFFFF, 0x01), 0x0000, 0x30),
+        ];
+        for (name, actual, result, flags) in wide {
+            assert_eq!(actual, (result, f(flags)), "{}", name);
+        }
+    }
+}
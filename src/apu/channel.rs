@@ -0,0 +1,160 @@
+/// Silences a channel a fixed time after it was triggered, when enabled.
+#[derive(Copy, Clone)]
+pub struct Length {
+    counter: u16,
+    max: u16,
+    enabled: bool,
+}
+
+impl Length {
+    pub fn new(max: u16) -> Length {
+        Length {
+            counter: 0,
+            max,
+            enabled: false,
+        }
+    }
+
+    /// Loads the length field of NRx1.
+    pub fn load(&mut self, value: u8) {
+        self.counter = self.max - value as u16;
+    }
+
+    /// Returns whether the counter ran out and the channel must stop.
+    pub fn clock(&mut self) -> bool {
+        if !self.enabled || self.counter == 0 {
+            return false;
+        }
+        self.counter -= 1;
+        self.counter == 0
+    }
+
+    /// Handles the length enable and trigger bits of NRx4, returning whether
+    /// the channel must stop. `extra_clock` is set when the frame sequencer's
+    /// next step won't clock lengths: enabling the counter then clocks it
+    /// once straight away.
+    pub fn write_control(&mut self, enable: bool, trigger: bool, extra_clock: bool) -> bool {
+        let was_enabled = self.enabled;
+        self.enabled = enable;
+
+        let mut stop = false;
+        if extra_clock && enable && !was_enabled && self.counter != 0 {
+            self.counter -= 1;
+            stop = self.counter == 0 && !trigger;
+        }
+
+        if trigger && self.counter == 0 {
+            self.counter = if extra_clock && enable {
+                self.max - 1
+            } else {
+                self.max
+            };
+        }
+        stop
+    }
+
+    /// Powering the APU off leaves the counter itself alone on DMG.
+    pub fn power_off(&mut self) {
+        self.enabled = false;
+    }
+}
+
+/// Volume envelope of NRx2.
+#[derive(Copy, Clone, Default)]
+pub struct Envelope {
+    initial: u8,
+    increase: bool,
+    period: u8,
+    volume: u8,
+    timer: u8,
+}
+
+impl Envelope {
+    pub fn write(&mut self, value: u8) {
+        self.initial = value >> 4;
+        self.increase = value & 0x08 != 0;
+        self.period = value & 0x07;
+    }
+
+    /// The DAC is on unless the top five bits of NRx2 are all clear.
+    pub fn dac_enabled(&self) -> bool {
+        self.initial != 0 || self.increase
+    }
+
+    pub fn volume(&self) -> u8 {
+        self.volume
+    }
+
+    pub fn trigger(&mut self) {
+        self.volume = self.initial;
+        self.timer = self.period;
+    }
+
+    pub fn clock(&mut self) {
+        // A period of 0 stops the envelope
+        if self.period == 0 {
+            return;
+        }
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer > 0 {
+            return;
+        }
+        self.timer = self.period;
+
+        if self.increase && self.volume < 15 {
+            self.volume += 1;
+        } else if !self.increase && self.volume > 0 {
+            self.volume -= 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn envelope(nrx2: u8) -> Envelope {
+        let mut envelope = Envelope::default();
+        envelope.write(nrx2);
+        envelope.trigger();
+        envelope
+    }
+
+    fn volumes(envelope: &mut Envelope, clocks: usize) -> Vec<u8> {
+        (0..clocks)
+            .map(|_| {
+                envelope.clock();
+                envelope.volume()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn envelope_steps_once_per_period() {
+        // Volume 5, decreasing every 3 clocks
+        let mut down = envelope(0x53);
+        assert_eq!(down.volume(), 5);
+        assert_eq!(
+            volumes(&mut down, 18),
+            [5, 5, 4, 4, 4, 3, 3, 3, 2, 2, 2, 1, 1, 1, 0, 0, 0, 0]
+        );
+
+        // Volume 13, increasing every clock, stopping at 15
+        let mut up = envelope(0xD9);
+        assert_eq!(volumes(&mut up, 4), [14, 15, 15, 15]);
+    }
+
+    #[test]
+    fn envelope_period_0_holds_the_volume() {
+        let mut held = envelope(0x90);
+        assert_eq!(volumes(&mut held, 32), [9; 32]);
+    }
+
+    #[test]
+    fn dac_needs_a_volume_or_increase() {
+        assert!(!envelope(0x00).dac_enabled());
+        assert!(!envelope(0x07).dac_enabled());
+        assert!(envelope(0x08).dac_enabled());
+        assert!(envelope(0x10).dac_enabled());
+    }
+}
@@ -0,0 +1,406 @@
+mod channel;
+mod noise;
+mod square;
+mod wave;
+
+use noise::Noise;
+use square::Square;
+use wave::Wave;
+
+const M_CYCLES_PER_SECOND: u32 = 1_048_576;
+
+// The frame sequencer steps on each falling edge of this DIV bit, 512 Hz
+const FRAME_SEQUENCER_BIT: u16 = 1 << 12;
+
+// NR52
+const POWER: u8 = 0x80;
+
+// Bits that read back as 1 in FF10-FF25, unused and write-only ones included
+const READ_MASKS: [u8; 0x16] = [
+    0x80, 0x3F, 0x00, 0xFF, 0xBF, // NR10-NR14
+    0xFF, 0x3F, 0x00, 0xFF, 0xBF, // NR20-NR24
+    0x7F, 0xFF, 0x9F, 0xFF, 0xBF, // NR30-NR34
+    0xFF, 0xFF, 0x00, 0x00, 0xBF, // NR40-NR44
+    0x00, 0x00, // NR50-NR51
+];
+
+// What the DMG boot ROM writes to play its chime, in order
+const BOOT_WRITES: [(u16, u8); 6] = [
+    (0xFF11, 0x80),
+    (0xFF12, 0xF3),
+    (0xFF25, 0xF3),
+    (0xFF24, 0x77),
+    (0xFF13, 0xC1),
+    (0xFF14, 0x87),
+];
+
+pub struct Apu {
+    square1: Square,
+    square2: Square,
+    wave: Wave,
+    noise: Noise,
+    powered: bool,
+    // FF10-FF25 as last written
+    registers: [u8; 0x16],
+    // Next step of the frame sequencer, 0-7
+    frame_step: u8,
+    frame_sequencer_input: bool,
+    // Samples per second for the host, 0 for none
+    sample_rate: u32,
+    // Counts up by sample_rate each M-cycle; a sample is due on wrapping
+    sample_clock: u32,
+    // DC-blocking capacitors, left and right
+    capacitors: [f32; 2],
+    charge_factor: f32,
+    // Interleaved left and right samples
+    samples: Vec<f32>,
+}
+
+impl Apu {
+    /// An APU in the state the DMG boot ROM leaves it in: powered, with
+    /// channel 1 still on after the chime.
+    pub fn new() -> Apu {
+        let mut apu = Apu {
+            square1: Square::with_sweep(),
+            square2: Square::new(),
+            wave: Wave::new(),
+            noise: Noise::new(),
+            powered: true,
+            registers: [0; 0x16],
+            frame_step: 0,
+            frame_sequencer_input: false,
+            sample_rate: 0,
+            sample_clock: 0,
+            capacitors: [0.0; 2],
+            charge_factor: 1.0,
+            samples: Vec::new(),
+        };
+        for (address, value) in BOOT_WRITES {
+            apu.write_register(address, value);
+        }
+        // By the time the game starts the chime has faded out: 15 volume
+        // steps, each three envelope clocks long
+        for _ in 0..15 * 3 {
+            apu.square1.clock_envelope();
+        }
+        apu
+    }
+
+    pub fn set_sample_rate(&mut self, rate: u32) {
+        self.sample_rate = rate;
+        self.sample_clock = 0;
+        if rate > 0 {
+            // How much charge the capacitor keeps over one host sample
+            let m_cycles = M_CYCLES_PER_SECOND as f32 / rate as f32;
+            self.charge_factor = 0.999958f32.powf(m_cycles * 4.0);
+        }
+    }
+
+    /// Stereo samples produced since the last call, left first.
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        std::mem::take(&mut self.samples)
+    }
+
+    pub fn read_register(&self, address: u16) -> u8 {
+        match address {
+            0xFF10..=0xFF25 => {
+                let index = address as usize - 0xFF10;
+                self.registers[index] | READ_MASKS[index]
+            }
+            0xFF26 => {
+                let channels = [
+                    self.square1.enabled(),
+                    self.square2.enabled(),
+                    self.wave.enabled(),
+                    self.noise.enabled(),
+                ];
+                let active = channels
+                    .iter()
+                    .enumerate()
+                    .fold(0, |bits, (i, &on)| bits | (on as u8) << i);
+                (self.powered as u8) << 7 | 0x70 | active
+            }
+            0xFF30..=0xFF3F => self.wave.read_ram(address),
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write_register(&mut self, address: u16, value: u8) {
+        match address {
+            0xFF26 => self.set_power(value & POWER != 0),
+            0xFF30..=0xFF3F => self.wave.write_ram(address, value),
+            // Length counters stay writable while powered off
+            0xFF11 | 0xFF16 | 0xFF1B | 0xFF20 if !self.powered => match address {
+                0xFF11 => self.square1.load_length(value),
+                0xFF16 => self.square2.load_length(value),
+                0xFF1B => self.wave.load_length(value),
+                _ => self.noise.load_length(value),
+            },
+            0xFF10..=0xFF25 if self.powered => {
+                self.registers[address as usize - 0xFF10] = value;
+
+                // Each channel has five registers, NRx0-NRx4
+                let offset = address - 0xFF10;
+                let (channel, register) = (offset / 5, offset % 5);
+                // Enabling a length counter clocks it when the frame
+                // sequencer's next step won't
+                let extra_length_clock = self.frame_step % 2 == 1;
+                match channel {
+                    0 => self.square1.write(register, value, extra_length_clock),
+                    1 => self.square2.write(register, value, extra_length_clock),
+                    2 => self.wave.write(register, value, extra_length_clock),
+                    3 => self.noise.write(register, value, extra_length_clock),
+                    // NR50 and NR51 are only read back when mixing
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn set_power(&mut self, on: bool) {
+        if on && !self.powered {
+            self.frame_step = 0;
+        } else if !on && self.powered {
+            // Everything but wave RAM and the DMG length counters is cleared
+            self.registers = [0; 0x16];
+            self.square1.power_off();
+            self.square2.power_off();
+            self.wave.power_off();
+            self.noise.power_off();
+        }
+        self.powered = on;
+    }
+
+    /// Advances by one M-cycle, given the timer's internal counter that
+    /// drives the frame sequencer.
+    pub fn step(&mut self, divider: u16) {
+        let input = divider & FRAME_SEQUENCER_BIT != 0;
+        let falling_edge = self.frame_sequencer_input && !input;
+        self.frame_sequencer_input = input;
+
+        if self.powered {
+            if falling_edge {
+                self.clock_frame_sequencer();
+            }
+            self.square1.step(4);
+            self.square2.step(4);
+            self.wave.step(4);
+            self.noise.step(4);
+        }
+
+        if self.sample_rate == 0 {
+            return;
+        }
+        self.sample_clock += self.sample_rate;
+        if self.sample_clock >= M_CYCLES_PER_SECOND {
+            self.sample_clock -= M_CYCLES_PER_SECOND;
+            self.push_sample();
+        }
+    }
+
+    fn clock_frame_sequencer(&mut self) {
+        // Lengths on even steps, the sweep on 2 and 6, envelopes on 7
+        if self.frame_step % 2 == 0 {
+            self.square1.clock_length();
+            self.square2.clock_length();
+            self.wave.clock_length();
+            self.noise.clock_length();
+        }
+        if self.frame_step == 2 || self.frame_step == 6 {
+            self.square1.clock_sweep();
+        }
+        if self.frame_step == 7 {
+            self.square1.clock_envelope();
+            self.square2.clock_envelope();
+            self.noise.clock_envelope();
+        }
+        self.frame_step = (self.frame_step + 1) % 8;
+    }
+
+    fn push_sample(&mut self) {
+        let nr50 = self.registers[0x14];
+        let nr51 = self.registers[0x15];
+        let outputs = [
+            self.square1.output(),
+            self.square2.output(),
+            self.wave.output(),
+            self.noise.output(),
+        ];
+
+        // NR51 pans channels right in its low nibble and left in its high one
+        for (side, shift) in [(0, 4), (1, 0)] {
+            let mut mixed = 0.0;
+            for (channel, output) in outputs.iter().enumerate() {
+                if nr51 >> (shift + channel) & 1 == 0 {
+                    continue;
+                }
+                // Each DAC maps 0-15 to 1.0 down to -1.0
+                if let Some(value) = output {
+                    mixed += 1.0 - *value as f32 / 7.5;
+                }
+            }
+            let volume = (nr50 >> shift & 0x07) + 1;
+            let sample = mixed / 4.0 * volume as f32 / 8.0;
+
+            let filtered = sample - self.capacitors[side];
+            self.capacitors[side] = sample - filtered * self.charge_factor;
+            self.samples.push(filtered);
+        }
+    }
+}
+
+/// Encodes interleaved stereo samples as a 16-bit PCM WAV file.
+pub fn encode_wav(samples: &[f32], sample_rate: u32) -> Vec<u8> {
+    let data_size = samples.len() as u32 * 2;
+    let mut wav = Vec::with_capacity(44 + data_size as usize);
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + data_size).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    // PCM, two channels
+    wav.extend_from_slice(&1u16.to_le_bytes());
+    wav.extend_from_slice(&2u16.to_le_bytes());
+    wav.extend_from_slice(&sample_rate.to_le_bytes());
+    wav.extend_from_slice(&(sample_rate * 4).to_le_bytes());
+    wav.extend_from_slice(&4u16.to_le_bytes());
+    wav.extend_from_slice(&16u16.to_le_bytes());
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_size.to_le_bytes());
+    for sample in samples {
+        let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+        wav.extend_from_slice(&value.to_le_bytes());
+    }
+    wav
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs the APU for `m_cycles` with a free-running divider.
+    fn run(apu: &mut Apu, divider: &mut u16, m_cycles: u32) {
+        for _ in 0..m_cycles {
+            *divider = divider.wrapping_add(4);
+            apu.step(*divider);
+        }
+    }
+
+    // One frame sequencer step
+    const STEP: u32 = 2048;
+
+    #[test]
+    fn power_off_clears_registers_but_not_wave_ram() {
+        let mut apu = Apu::new();
+        apu.write_register(0xFF12, 0xF0);
+        apu.write_register(0xFF24, 0x77);
+        apu.write_register(0xFF30, 0x12);
+        apu.write_register(0xFF14, 0x80);
+        assert_eq!(apu.read_register(0xFF26), 0xF1);
+
+        apu.write_register(0xFF26, 0x00);
+        assert_eq!(apu.read_register(0xFF26), 0x70);
+        assert_eq!(apu.read_register(0xFF12), 0x00);
+        assert_eq!(apu.read_register(0xFF24), 0x00);
+        assert_eq!(apu.read_register(0xFF30), 0x12);
+
+        // Ignored until powered back on
+        apu.write_register(0xFF24, 0x77);
+        assert_eq!(apu.read_register(0xFF24), 0x00);
+        apu.write_register(0xFF26, 0x80);
+        apu.write_register(0xFF24, 0x77);
+        assert_eq!(apu.read_register(0xFF24), 0x77);
+    }
+
+    #[test]
+    fn starts_in_the_post_boot_state() {
+        let apu = Apu::new();
+        let reads: Vec<u8> = (0xFF10..=0xFF26).map(|a| apu.read_register(a)).collect();
+        assert_eq!(
+            reads,
+            [
+                0x80, 0xBF, 0xF3, 0xFF, 0xBF, // NR10-NR14
+                0xFF, 0x3F, 0x00, 0xFF, 0xBF, // NR20-NR24
+                0x7F, 0xFF, 0x9F, 0xFF, 0xBF, // NR30-NR34
+                0xFF, 0xFF, 0x00, 0x00, 0xBF, // NR40-NR44
+                0x77, 0xF3, 0xF1, // NR50-NR52
+            ]
+        );
+        // The chime is over, so channel 1 is on but silent
+        assert_eq!(apu.square1.output(), Some(0));
+    }
+
+    #[test]
+    fn registers_read_back_with_unused_bits_set() {
+        let mut apu = Apu::new();
+        apu.write_register(0xFF26, 0x00);
+        apu.write_register(0xFF26, 0x80);
+        let reads: Vec<u8> = (0xFF10..=0xFF25).map(|a| apu.read_register(a)).collect();
+        assert_eq!(reads, READ_MASKS);
+        assert_eq!(apu.read_register(0xFF27), 0xFF);
+    }
+
+    #[test]
+    fn length_counter_silences_channel() {
+        let mut apu = Apu::new();
+        let mut divider = 0;
+        // Noise, 62 ticks of length left, at 256 Hz
+        apu.write_register(0xFF21, 0xF0);
+        apu.write_register(0xFF20, 2);
+        apu.write_register(0xFF23, 0xC0);
+
+        run(&mut apu, &mut divider, 61 * 2 * STEP);
+        assert_eq!(apu.read_register(0xFF26) & 0x08, 0x08);
+        run(&mut apu, &mut divider, 2 * STEP);
+        assert_eq!(apu.read_register(0xFF26) & 0x08, 0x00);
+    }
+
+    #[test]
+    fn sweep_overflow_disables_channel_1() {
+        let mut apu = Apu::new();
+        apu.write_register(0xFF12, 0xF0);
+        // Adding 0x7FF >> 1 overflows straight away on trigger
+        apu.write_register(0xFF10, 0x11);
+        apu.write_register(0xFF13, 0xFF);
+        apu.write_register(0xFF14, 0x87);
+        assert_eq!(apu.read_register(0xFF26) & 0x01, 0x00);
+
+        // Without a shift only the periodic update can overflow
+        let mut divider = 0;
+        apu.write_register(0xFF10, 0x10);
+        apu.write_register(0xFF14, 0x87);
+        assert_eq!(apu.read_register(0xFF26) & 0x01, 0x01);
+        apu.write_register(0xFF10, 0x11);
+        run(&mut apu, &mut divider, 8 * STEP);
+        assert_eq!(apu.read_register(0xFF26) & 0x01, 0x00);
+    }
+
+    #[test]
+    fn produces_samples_at_host_rate_and_pans() {
+        let mut apu = Apu::new();
+        let mut divider = 0;
+        apu.set_sample_rate(44_100);
+        // Square 2 at full volume, left only
+        apu.write_register(0xFF24, 0x77);
+        apu.write_register(0xFF25, 0x20);
+        apu.write_register(0xFF16, 0x80);
+        apu.write_register(0xFF17, 0xF0);
+        apu.write_register(0xFF19, 0x87);
+
+        run(&mut apu, &mut divider, M_CYCLES_PER_SECOND);
+        let samples = apu.take_samples();
+        assert_eq!(samples.len(), 2 * 44_100);
+        assert!(samples.iter().step_by(2).any(|&left| left.abs() > 0.1));
+        assert!(samples.iter().skip(1).step_by(2).all(|&right| right == 0.0));
+        assert!(apu.take_samples().is_empty());
+    }
+
+    #[test]
+    fn wav_header_describes_stereo_pcm() {
+        let wav = encode_wav(&[0.0, 1.0, -1.0, 0.5], 48_000);
+        assert_eq!(wav.len(), 44 + 8);
+        assert_eq!(&wav[..4], b"RIFF");
+        assert_eq!(&wav[24..28], &48_000u32.to_le_bytes());
+        assert_eq!(&wav[46..48], &i16::MAX.to_le_bytes());
+    }
+}
@@ -0,0 +1,156 @@
+use super::channel::{Envelope, Length};
+
+// T-cycles per LFSR clock for each NR43 divisor code, before the shift
+const DIVISORS: [u32; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+
+pub struct Noise {
+    enabled: bool,
+    length: Length,
+    envelope: Envelope,
+    shift: u8,
+    // 7-bit mode
+    narrow: bool,
+    divisor: u8,
+    // T-cycles until the next LFSR clock
+    timer: u32,
+    lfsr: u16,
+}
+
+impl Noise {
+    pub fn new() -> Noise {
+        Noise {
+            enabled: false,
+            length: Length::new(64),
+            envelope: Envelope::default(),
+            shift: 0,
+            narrow: false,
+            divisor: 0,
+            timer: DIVISORS[0],
+            lfsr: 0x7FFF,
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn period(&self) -> u32 {
+        DIVISORS[self.divisor as usize] << self.shift
+    }
+
+    /// Writes NR41-NR44, numbered 1-4.
+    pub fn write(&mut self, register: u16, value: u8, extra_length_clock: bool) {
+        match register {
+            1 => self.length.load(value & 0x3F),
+            2 => {
+                self.envelope.write(value);
+                if !self.envelope.dac_enabled() {
+                    self.enabled = false;
+                }
+            }
+            3 => {
+                self.shift = value >> 4;
+                self.narrow = value & 0x08 != 0;
+                self.divisor = value & 0x07;
+            }
+            4 => {
+                let trigger = value & 0x80 != 0;
+                if self
+                    .length
+                    .write_control(value & 0x40 != 0, trigger, extra_length_clock)
+                {
+                    self.enabled = false;
+                }
+                if trigger {
+                    self.enabled = self.envelope.dac_enabled();
+                    self.timer = self.period();
+                    self.envelope.trigger();
+                    self.lfsr = 0x7FFF;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn load_length(&mut self, value: u8) {
+        self.length.load(value & 0x3F);
+    }
+
+    pub fn step(&mut self, cycles: u32) {
+        let mut cycles = cycles;
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.period();
+            // Shifts of 14 and 15 leave the LFSR without a clock
+            if self.shift < 14 {
+                self.clock_lfsr();
+            }
+        }
+        self.timer -= cycles;
+    }
+
+    fn clock_lfsr(&mut self) {
+        let bit = (self.lfsr ^ self.lfsr >> 1) & 1;
+        self.lfsr = self.lfsr >> 1 | bit << 14;
+        if self.narrow {
+            self.lfsr = self.lfsr & !0x40 | bit << 6;
+        }
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    /// The DAC input, or `None` while the DAC is off.
+    pub fn output(&self) -> Option<u8> {
+        if !self.envelope.dac_enabled() {
+            return None;
+        }
+        Some(if self.enabled && self.lfsr & 1 == 0 {
+            self.envelope.volume()
+        } else {
+            0
+        })
+    }
+
+    pub fn power_off(&mut self) {
+        let mut length = self.length;
+        length.power_off();
+        *self = Noise::new();
+        self.length = length;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lfsr_period(narrow: bool) -> usize {
+        let mut noise = Noise::new();
+        noise.narrow = narrow;
+        // Skip the states the 7-bit mode never comes back to
+        for _ in 0..16 {
+            noise.clock_lfsr();
+        }
+        let start = noise.lfsr;
+        let mut steps = 1;
+        noise.clock_lfsr();
+        while noise.lfsr != start {
+            noise.clock_lfsr();
+            steps += 1;
+        }
+        steps
+    }
+
+    #[test]
+    fn lfsr_repeats_after_32767_or_127_clocks() {
+        assert_eq!(lfsr_period(false), 32767);
+        assert_eq!(lfsr_period(true), 127);
+    }
+}
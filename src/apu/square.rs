@@ -0,0 +1,226 @@
+use super::channel::{Envelope, Length};
+
+// Waveforms for 12.5%, 25%, 50% and 75% duty, played from the top bit down
+const DUTY: [u8; 4] = [0b0000_0001, 0b1000_0001, 0b1000_0111, 0b0111_1110];
+
+/// Channel 1's frequency sweep.
+struct Sweep {
+    period: u8,
+    negate: bool,
+    shift: u8,
+    timer: u8,
+    enabled: bool,
+    // Frequency the sweep works from, copied on trigger
+    shadow: u16,
+    // Whether a subtraction was calculated since the last trigger
+    negated: bool,
+}
+
+impl Sweep {
+    fn new() -> Sweep {
+        Sweep {
+            period: 0,
+            negate: false,
+            shift: 0,
+            timer: 0,
+            enabled: false,
+            shadow: 0,
+            negated: false,
+        }
+    }
+
+    fn reload_timer(&mut self) {
+        // A period of 0 counts as 8
+        self.timer = if self.period == 0 { 8 } else { self.period };
+    }
+
+    /// The next frequency, or `None` when it overflows 11 bits.
+    fn calculate(&mut self) -> Option<u16> {
+        let delta = self.shadow >> self.shift;
+        let frequency = if self.negate {
+            self.negated = true;
+            self.shadow - delta
+        } else {
+            self.shadow + delta
+        };
+        (frequency <= 0x7FF).then_some(frequency)
+    }
+}
+
+pub struct Square {
+    enabled: bool,
+    sweep: Option<Sweep>,
+    duty: u8,
+    length: Length,
+    envelope: Envelope,
+    frequency: u16,
+    // T-cycles until the next duty step
+    timer: u32,
+    position: u8,
+}
+
+impl Square {
+    /// Channel 1, with a sweep unit.
+    pub fn with_sweep() -> Square {
+        Square {
+            sweep: Some(Sweep::new()),
+            ..Square::new()
+        }
+    }
+
+    pub fn new() -> Square {
+        Square {
+            enabled: false,
+            sweep: None,
+            duty: 0,
+            length: Length::new(64),
+            envelope: Envelope::default(),
+            frequency: 0,
+            timer: 8192,
+            position: 0,
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn period(&self) -> u32 {
+        (2048 - self.frequency as u32) * 4
+    }
+
+    /// Writes NRx0-NRx4, numbered 0-4.
+    pub fn write(&mut self, register: u16, value: u8, extra_length_clock: bool) {
+        match register {
+            0 => {
+                if let Some(sweep) = self.sweep.as_mut() {
+                    sweep.period = (value >> 4) & 0x07;
+                    sweep.negate = value & 0x08 != 0;
+                    sweep.shift = value & 0x07;
+                    // Leaving negate mode after using it silences the channel
+                    if !sweep.negate && sweep.negated {
+                        self.enabled = false;
+                    }
+                }
+            }
+            1 => {
+                self.duty = value >> 6;
+                self.length.load(value & 0x3F);
+            }
+            2 => {
+                self.envelope.write(value);
+                if !self.envelope.dac_enabled() {
+                    self.enabled = false;
+                }
+            }
+            3 => self.frequency = self.frequency & 0x700 | value as u16,
+            4 => {
+                self.frequency = self.frequency & 0xFF | (value as u16 & 0x07) << 8;
+                let trigger = value & 0x80 != 0;
+                if self
+                    .length
+                    .write_control(value & 0x40 != 0, trigger, extra_length_clock)
+                {
+                    self.enabled = false;
+                }
+                if trigger {
+                    self.trigger();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Writes to NRx1 while the APU is off only reach the length.
+    pub fn load_length(&mut self, value: u8) {
+        self.length.load(value & 0x3F);
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.envelope.dac_enabled();
+        self.timer = self.period();
+        self.envelope.trigger();
+
+        if let Some(sweep) = self.sweep.as_mut() {
+            sweep.shadow = self.frequency;
+            sweep.negated = false;
+            sweep.reload_timer();
+            sweep.enabled = sweep.period != 0 || sweep.shift != 0;
+            // The overflow check runs straight away when shifting
+            if sweep.shift != 0 && sweep.calculate().is_none() {
+                self.enabled = false;
+            }
+        }
+    }
+
+    pub fn step(&mut self, cycles: u32) {
+        let mut cycles = cycles;
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.period();
+            self.position = (self.position + 1) & 0x07;
+        }
+        self.timer -= cycles;
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_sweep(&mut self) {
+        let Some(sweep) = self.sweep.as_mut() else {
+            return;
+        };
+
+        sweep.timer = sweep.timer.saturating_sub(1);
+        if sweep.timer > 0 {
+            return;
+        }
+        sweep.reload_timer();
+        if !sweep.enabled || sweep.period == 0 {
+            return;
+        }
+
+        match sweep.calculate() {
+            Some(frequency) if sweep.shift != 0 => {
+                sweep.shadow = frequency;
+                self.frequency = frequency;
+                // A second calculation only checks for overflow
+                if sweep.calculate().is_none() {
+                    self.enabled = false;
+                }
+            }
+            Some(_) => {}
+            None => self.enabled = false,
+        }
+    }
+
+    /// The DAC input, or `None` while the DAC is off.
+    pub fn output(&self) -> Option<u8> {
+        if !self.envelope.dac_enabled() {
+            return None;
+        }
+        let high = DUTY[self.duty as usize] >> (7 - self.position) & 1 != 0;
+        Some(if self.enabled && high {
+            self.envelope.volume()
+        } else {
+            0
+        })
+    }
+
+    pub fn power_off(&mut self) {
+        let mut length = self.length;
+        length.power_off();
+        *self = match self.sweep {
+            Some(_) => Square::with_sweep(),
+            None => Square::new(),
+        };
+        self.length = length;
+    }
+}
@@ -0,0 +1,194 @@
+use super::channel::Length;
+
+pub struct Wave {
+    enabled: bool,
+    dac: bool,
+    length: Length,
+    // NR32 output level: mute, 100%, 50%, 25%
+    level: u8,
+    frequency: u16,
+    // T-cycles until the next sample
+    timer: u32,
+    position: u8,
+    sample: u8,
+    // 32 four-bit samples, high nibble first
+    ram: [u8; 16],
+}
+
+impl Wave {
+    pub fn new() -> Wave {
+        Wave {
+            enabled: false,
+            dac: false,
+            length: Length::new(256),
+            level: 0,
+            frequency: 0,
+            timer: 4096,
+            position: 0,
+            sample: 0,
+            ram: [0; 16],
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn period(&self) -> u32 {
+        (2048 - self.frequency as u32) * 2
+    }
+
+    /// Writes NR30-NR34, numbered 0-4.
+    pub fn write(&mut self, register: u16, value: u8, extra_length_clock: bool) {
+        match register {
+            0 => {
+                self.dac = value & 0x80 != 0;
+                if !self.dac {
+                    self.enabled = false;
+                }
+            }
+            1 => self.length.load(value),
+            2 => self.level = (value >> 5) & 0x03,
+            3 => self.frequency = self.frequency & 0x700 | value as u16,
+            4 => {
+                self.frequency = self.frequency & 0xFF | (value as u16 & 0x07) << 8;
+                let trigger = value & 0x80 != 0;
+                if self
+                    .length
+                    .write_control(value & 0x40 != 0, trigger, extra_length_clock)
+                {
+                    self.enabled = false;
+                }
+                if trigger {
+                    self.enabled = self.dac;
+                    self.timer = self.period();
+                    self.position = 0;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn load_length(&mut self, value: u8) {
+        self.length.load(value);
+    }
+
+    pub fn read_ram(&self, address: u16) -> u8 {
+        self.ram[address as usize & 0x0F]
+    }
+
+    pub fn write_ram(&mut self, address: u16, value: u8) {
+        self.ram[address as usize & 0x0F] = value;
+    }
+
+    pub fn step(&mut self, cycles: u32) {
+        if !self.enabled {
+            return;
+        }
+
+        let mut cycles = cycles;
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.period();
+            self.position = (self.position + 1) & 0x1F;
+            let byte = self.ram[self.position as usize / 2];
+            self.sample = if self.position % 2 == 0 {
+                byte >> 4
+            } else {
+                byte & 0x0F
+            };
+        }
+        self.timer -= cycles;
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    /// The DAC input, or `None` while the DAC is off.
+    pub fn output(&self) -> Option<u8> {
+        if !self.dac {
+            return None;
+        }
+        if !self.enabled || self.level == 0 {
+            return Some(0);
+        }
+        Some(self.sample >> (self.level - 1))
+    }
+
+    /// Wave RAM survives the APU being powered off.
+    pub fn power_off(&mut self) {
+        let mut length = self.length;
+        length.power_off();
+        let ram = self.ram;
+        *self = Wave::new();
+        self.length = length;
+        self.ram = ram;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A playing wave channel at the highest pitch, one sample every 2
+    /// T-cycles, at full volume.
+    fn playing(ram: [u8; 16]) -> Wave {
+        let mut wave = Wave::new();
+        for (address, &byte) in ram.iter().enumerate() {
+            wave.write_ram(0xFF30 + address as u16, byte);
+        }
+        wave.write(0, 0x80, false);
+        wave.write(2, 0x20, false);
+        wave.write(3, 0xFF, false);
+        wave.write(4, 0x87, false);
+        wave
+    }
+
+    #[test]
+    fn plays_wave_ram_high_nibble_first() {
+        let mut ram = [0; 16];
+        for (i, byte) in ram.iter_mut().enumerate() {
+            // Nibbles 0 to 15, then back down
+            let (high, low) = if i < 8 {
+                (i as u8 * 2, i as u8 * 2 + 1)
+            } else {
+                (31 - i as u8 * 2, 30 - i as u8 * 2)
+            };
+            *byte = high << 4 | low;
+        }
+        let mut wave = playing(ram);
+
+        let samples: Vec<u8> = (0..32)
+            .map(|_| {
+                wave.step(2);
+                wave.output().unwrap()
+            })
+            .collect();
+
+        // Playback starts at the second sample after a trigger
+        let mut expected: Vec<u8> = (1..16).chain((0..16).rev()).collect();
+        expected.push(0);
+        assert_eq!(samples, expected);
+    }
+
+    #[test]
+    fn nr32_shifts_the_sample_down() {
+        let mut wave = playing([0xFF; 16]);
+        wave.step(2);
+
+        let mut outputs = Vec::new();
+        for nr32 in [0x00, 0x20, 0x40, 0x60] {
+            wave.write(2, nr32, false);
+            outputs.push(wave.output().unwrap());
+        }
+        assert_eq!(outputs, [0, 15, 7, 3]);
+
+        // Turning the DAC off disconnects the channel entirely
+        wave.write(0, 0x00, false);
+        assert_eq!(wave.output(), None);
+        assert!(!wave.enabled());
+    }
+}
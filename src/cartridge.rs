@@ -0,0 +1,771 @@
+use crate::mbc::{Mbc, Mbc1, Mbc2, Mbc3, Mbc5, RomOnly, RtcClock};
+use std::{
+    fmt,
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+const HEADER_END: usize = 0x150;
+
+/// The logo every licensed cartridge carries at 0x104-0x133, which the boot
+/// ROM checks before starting the game.
+pub const NINTENDO_LOGO: [u8; 48] = [
+    0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D,
+    0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E, 0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99,
+    0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
+];
+
+// How long RAM has to sit untouched before it is flushed to disk
+const SAVE_DELAY: Duration = Duration::from_secs(3);
+// Roughly every 1/64 s of emulated time, check whether a flush is due
+const SAVE_CHECK_INTERVAL: u32 = 0x10000;
+
+#[derive(Debug)]
+pub enum CartridgeError {
+    Io(io::Error),
+    Truncated { expected: usize, actual: usize },
+    RomSizeMismatch { declared: usize, actual: usize },
+    UnknownCartridgeType(u8),
+    UnknownRomSize(u8),
+    UnknownRamSize(u8),
+    HeaderChecksumMismatch { expected: u8, computed: u8 },
+    UnsupportedMapper(Mapper),
+}
+
+impl fmt::Display for CartridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CartridgeError::Io(err) => write!(f, "failed to read ROM: {}", err),
+            CartridgeError::Truncated { expected, actual } => write!(
+                f,
+                "ROM image is truncated: expected {} bytes, got {}",
+                expected, actual
+            ),
+            CartridgeError::RomSizeMismatch { declared, actual } => write!(
+                f,
+                "ROM image is {} bytes but the header declares {}",
+                actual, declared
+            ),
+            CartridgeError::UnknownCartridgeType(code) => {
+                write!(f, "unknown cartridge type {:#04x}", code)
+            }
+            CartridgeError::UnknownRomSize(code) => {
+                write!(f, "unknown ROM size code {:#04x}", code)
+            }
+            CartridgeError::UnknownRamSize(code) => {
+                write!(f, "unknown RAM size code {:#04x}", code)
+            }
+            CartridgeError::HeaderChecksumMismatch { expected, computed } => write!(
+                f,
+                "header checksum mismatch: header says {:#04x}, computed {:#04x}",
+                expected, computed
+            ),
+            CartridgeError::UnsupportedMapper(mapper) => {
+                write!(f, "unsupported cartridge mapper {:?}", mapper)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CartridgeError {}
+
+impl From<io::Error> for CartridgeError {
+    fn from(err: io::Error) -> Self {
+        CartridgeError::Io(err)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Mapper {
+    RomOnly,
+    Mbc1,
+    Mbc2,
+    Mmm01,
+    Mbc3,
+    Mbc5,
+    Mbc6,
+    Mbc7,
+    PocketCamera,
+    Tama5,
+    HuC3,
+    HuC1,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct CartridgeType {
+    pub code: u8,
+    pub mapper: Mapper,
+    pub ram: bool,
+    pub battery: bool,
+    pub timer: bool,
+    pub rumble: bool,
+}
+
+impl CartridgeType {
+    pub fn from_code(code: u8) -> Result<CartridgeType, CartridgeError> {
+        let (mapper, ram, battery, timer, rumble) = match code {
+            0x00 => (Mapper::RomOnly, false, false, false, false),
+            0x01 => (Mapper::Mbc1, false, false, false, false),
+            0x02 => (Mapper::Mbc1, true, false, false, false),
+            0x03 => (Mapper::Mbc1, true, true, false, false),
+            0x05 => (Mapper::Mbc2, false, false, false, false),
+            0x06 => (Mapper::Mbc2, false, true, false, false),
+            0x08 => (Mapper::RomOnly, true, false, false, false),
+            0x09 => (Mapper::RomOnly, true, true, false, false),
+            0x0B => (Mapper::Mmm01, false, false, false, false),
+            0x0C => (Mapper::Mmm01, true, false, false, false),
+            0x0D => (Mapper::Mmm01, true, true, false, false),
+            0x0F => (Mapper::Mbc3, false, true, true, false),
+            0x10 => (Mapper::Mbc3, true, true, true, false),
+            0x11 => (Mapper::Mbc3, false, false, false, false),
+            0x12 => (Mapper::Mbc3, true, false, false, false),
+            0x13 => (Mapper::Mbc3, true, true, false, false),
+            0x19 => (Mapper::Mbc5, false, false, false, false),
+            0x1A => (Mapper::Mbc5, true, false, false, false),
+            0x1B => (Mapper::Mbc5, true, true, false, false),
+            0x1C => (Mapper::Mbc5, false, false, false, true),
+            0x1D => (Mapper::Mbc5, true, false, false, true),
+            0x1E => (Mapper::Mbc5, true, true, false, true),
+            0x20 => (Mapper::Mbc6, false, false, false, false),
+            0x22 => (Mapper::Mbc7, true, true, false, true),
+            0xFC => (Mapper::PocketCamera, false, false, false, false),
+            0xFD => (Mapper::Tama5, false, false, false, false),
+            0xFE => (Mapper::HuC3, false, false, false, false),
+            0xFF => (Mapper::HuC1, true, true, false, false),
+            _ => return Err(CartridgeError::UnknownCartridgeType(code)),
+        };
+
+        Ok(CartridgeType {
+            code,
+            mapper,
+            ram,
+            battery,
+            timer,
+            rumble,
+        })
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CgbSupport {
+    None,
+    Supported,
+    Only,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Destination {
+    Japan,
+    Overseas,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Header {
+    pub title: String,
+    pub manufacturer_code: Option<String>,
+    pub cgb: CgbSupport,
+    pub sgb: bool,
+    pub cartridge_type: CartridgeType,
+    pub rom_size_code: u8,
+    pub ram_size_code: u8,
+    pub destination: Destination,
+    pub version: u8,
+    pub header_checksum: u8,
+    pub global_checksum: u16,
+}
+
+impl Header {
+    pub fn parse(rom: &[u8]) -> Result<Header, CartridgeError> {
+        if rom.len() < HEADER_END {
+            return Err(CartridgeError::Truncated {
+                expected: HEADER_END,
+                actual: rom.len(),
+            });
+        }
+
+        let cgb = match rom[0x143] {
+            0x80 => CgbSupport::Supported,
+            0xC0 => CgbSupport::Only,
+            _ => CgbSupport::None,
+        };
+
+        // CGB-era headers shortened the title to make room for the
+        // manufacturer code and the CGB flag.
+        let (title_bytes, manufacturer_code) = if cgb == CgbSupport::None {
+            (&rom[0x134..0x144], None)
+        } else {
+            let code = &rom[0x13F..0x143];
+            let manufacturer_code = code
+                .iter()
+                .all(|b| b.is_ascii_uppercase() || b.is_ascii_digit())
+                .then(|| String::from_utf8_lossy(code).into_owned());
+            (&rom[0x134..0x13F], manufacturer_code)
+        };
+
+        let title = title_bytes
+            .iter()
+            .take_while(|&&b| b != 0)
+            .map(|&b| {
+                if b.is_ascii_graphic() || b == b' ' {
+                    b as char
+                } else {
+                    '?'
+                }
+            })
+            .collect::<String>()
+            .trim_end()
+            .to_string();
+
+        let computed = header_checksum(rom);
+        let header_checksum = rom[0x14D];
+        if computed != header_checksum {
+            return Err(CartridgeError::HeaderChecksumMismatch {
+                expected: header_checksum,
+                computed,
+            });
+        }
+
+        let header = Header {
+            title,
+            manufacturer_code,
+            cgb,
+            sgb: rom[0x146] == 0x03,
+            cartridge_type: CartridgeType::from_code(rom[0x147])?,
+            rom_size_code: rom[0x148],
+            ram_size_code: rom[0x149],
+            destination: if rom[0x14A] == 0x00 {
+                Destination::Japan
+            } else {
+                Destination::Overseas
+            },
+            version: rom[0x14C],
+            header_checksum,
+            global_checksum: (rom[0x14E] as u16) << 8 | rom[0x14F] as u16,
+        };
+
+        header.rom_size()?;
+        header.ram_size()?;
+
+        Ok(header)
+    }
+
+    pub fn rom_size(&self) -> Result<usize, CartridgeError> {
+        match self.rom_size_code {
+            0x00..=0x08 => Ok(0x8000 << self.rom_size_code),
+            code => Err(CartridgeError::UnknownRomSize(code)),
+        }
+    }
+
+    pub fn ram_size(&self) -> Result<usize, CartridgeError> {
+        match self.ram_size_code {
+            0x00 => Ok(0),
+            // Listed as unused, but a few homebrew images still declare it
+            0x01 => Ok(0x800),
+            0x02 => Ok(0x2000),
+            0x03 => Ok(0x8000),
+            0x04 => Ok(0x20000),
+            0x05 => Ok(0x10000),
+            code => Err(CartridgeError::UnknownRamSize(code)),
+        }
+    }
+}
+
+/// The checksum the boot ROM computes over 0x134-0x14C and compares with
+/// the byte at 0x14D.
+fn header_checksum(rom: &[u8]) -> u8 {
+    rom[0x134..0x14D]
+        .iter()
+        .fold(0u8, |acc, &b| acc.wrapping_sub(b).wrapping_sub(1))
+}
+
+/// ROM images for tests around the crate.
+#[cfg(test)]
+pub(crate) mod test_rom {
+    use super::header_checksum;
+
+    /// A ROM of `banks` 16 KiB banks, each starting with its own bank
+    /// number as a little-endian u16. It has no header, which mappers
+    /// don't need.
+    pub(crate) fn banked(banks: usize) -> Vec<u8> {
+        let mut rom = vec![0; banks * 0x4000];
+        for (bank, data) in rom.chunks_exact_mut(0x4000).enumerate() {
+            data[..2].copy_from_slice(&(bank as u16).to_le_bytes());
+        }
+        rom
+    }
+
+    /// A ROM of the size `rom_size_code` declares, with a valid header.
+    pub(crate) fn with_header(cartridge_type: u8, rom_size_code: u8, ram_size_code: u8) -> Vec<u8> {
+        let size = 0x8000 << rom_size_code.min(8);
+        let mut rom = vec![0; size];
+        rom[0x134..0x138].copy_from_slice(b"TEST");
+        rom[0x147] = cartridge_type;
+        rom[0x148] = rom_size_code;
+        rom[0x149] = ram_size_code;
+        rom[0x14A] = 0x01;
+        rom[0x14C] = 0x02;
+        fix_checksum(&mut rom);
+        rom
+    }
+
+    /// Updates the header checksum after the header was edited.
+    pub(crate) fn fix_checksum(rom: &mut [u8]) {
+        rom[0x14D] = header_checksum(rom);
+    }
+}
+
+pub struct Cartridge {
+    pub header: Header,
+    global_checksum_valid: bool,
+    mbc: Box<dyn Mbc>,
+    save_path: Option<PathBuf>,
+    // RAM has changed since the save file was last written
+    dirty: bool,
+    last_ram_write: Option<Instant>,
+    save_check_cycles: u32,
+}
+
+impl Cartridge {
+    pub fn from_bytes(rom: Vec<u8>) -> Result<Cartridge, CartridgeError> {
+        let header = Header::parse(&rom)?;
+
+        let declared = header.rom_size()?;
+        if rom.len() < declared {
+            return Err(CartridgeError::Truncated {
+                expected: declared,
+                actual: rom.len(),
+            });
+        }
+        if rom.len() > declared {
+            return Err(CartridgeError::RomSizeMismatch {
+                declared,
+                actual: rom.len(),
+            });
+        }
+
+        let global_checksum = rom
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| *i != 0x14E && *i != 0x14F)
+            .fold(0u16, |acc, (_, &b)| acc.wrapping_add(b as u16));
+
+        let ram_size = header.ram_size()?;
+        let mbc: Box<dyn Mbc> = match header.cartridge_type.mapper {
+            Mapper::RomOnly => Box::new(RomOnly::new(rom, ram_size)),
+            Mapper::Mbc1 => Box::new(Mbc1::new(rom, ram_size)),
+            // MBC2 RAM is built into the controller, so the header declares none
+            Mapper::Mbc2 => Box::new(Mbc2::new(rom)),
+            Mapper::Mbc3 => Box::new(Mbc3::new(rom, ram_size, header.cartridge_type.timer)),
+            Mapper::Mbc5 => Box::new(Mbc5::new(rom, ram_size, header.cartridge_type.rumble)),
+            mapper => return Err(CartridgeError::UnsupportedMapper(mapper)),
+        };
+
+        Ok(Cartridge {
+            global_checksum_valid: global_checksum == header.global_checksum,
+            header,
+            mbc,
+            save_path: None,
+            dirty: false,
+            last_ram_write: None,
+            save_check_cycles: 0,
+        })
+    }
+
+    /// Backs the cartridge RAM with `path`, loading it if it already exists.
+    /// Carts without a battery keep their RAM in memory only.
+    pub fn attach_save_file(&mut self, path: &Path) -> io::Result<()> {
+        if !self.header.cartridge_type.battery {
+            return Ok(());
+        }
+
+        match fs::read(path) {
+            Ok(data) => self.load_save_data(&data),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+
+        self.save_path = Some(path.to_path_buf());
+        Ok(())
+    }
+
+    /// Writes the save file if there are unsaved RAM writes, or always for
+    /// carts with a clock, whose footer moves on without any writes. The
+    /// data goes to a temporary file first and is renamed over the old one,
+    /// so a crash mid-write leaves the previous save intact.
+    pub fn save(&mut self) -> io::Result<()> {
+        let Some(path) = self.save_path.clone() else {
+            return Ok(());
+        };
+        if !self.dirty && !self.header.cartridge_type.timer {
+            return Ok(());
+        }
+
+        let mut tmp_path = path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(&self.save_data())?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, &path)?;
+
+        self.dirty = false;
+        Ok(())
+    }
+
+    /// Whether the sum of every byte outside the checksum itself matches the
+    /// header. Hardware never checks this, so a mismatch is only worth a
+    /// warning.
+    pub fn global_checksum_valid(&self) -> bool {
+        self.global_checksum_valid
+    }
+
+    pub fn read_rom(&self, address: u16) -> u8 {
+        self.mbc.read_rom(address)
+    }
+
+    pub fn write_rom(&mut self, address: u16, value: u8) {
+        self.mbc.write_rom(address, value);
+    }
+
+    pub fn read_ram(&self, address: u16) -> u8 {
+        self.mbc.read_ram(address)
+    }
+
+    pub fn write_ram(&mut self, address: u16, value: u8) {
+        let changed = self.mbc.write_ram(address, value);
+
+        if changed && self.save_path.is_some() {
+            self.dirty = true;
+            self.last_ram_write = Some(Instant::now());
+        }
+    }
+
+    pub fn save_data(&mut self) -> Vec<u8> {
+        self.mbc.save_data()
+    }
+
+    pub fn load_save_data(&mut self, data: &[u8]) {
+        self.mbc.load_save_data(data);
+    }
+
+    pub fn tick(&mut self, cycles: u32) {
+        self.mbc.tick(cycles);
+
+        self.save_check_cycles += cycles;
+        if self.save_check_cycles >= SAVE_CHECK_INTERVAL {
+            self.save_check_cycles = 0;
+            self.save_if_idle();
+        }
+    }
+
+    /// Flushes the save once RAM has been quiet for a while, so games that
+    /// write their save in several steps only hit the disk once.
+    fn save_if_idle(&mut self) {
+        let idle = self
+            .last_ram_write
+            .map_or(false, |last| last.elapsed() >= SAVE_DELAY);
+        if self.dirty && idle {
+            if let Err(err) = self.save() {
+                eprintln!("Failed to write save file: {}", err);
+            }
+        }
+    }
+
+    pub fn set_rtc_clock(&mut self, clock: RtcClock) {
+        self.mbc.set_rtc_clock(clock);
+    }
+
+    pub fn rumble(&self) -> bool {
+        self.mbc.rumble()
+    }
+}
+
+impl Drop for Cartridge {
+    fn drop(&mut self) {
+        if let Err(err) = self.save() {
+            eprintln!("Failed to write save file: {}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        test_rom::{fix_checksum, with_header},
+        *,
+    };
+
+    #[test]
+    fn parses_valid_header() {
+        let mut image = with_header(0x13, 0x01, 0x03);
+        image[0x146] = 0x03;
+        fix_checksum(&mut image);
+
+        let header = Header::parse(&image).unwrap();
+        assert_eq!(header.title, "TEST");
+        assert_eq!(header.manufacturer_code, None);
+        assert_eq!(header.cgb, CgbSupport::None);
+        assert!(header.sgb);
+        assert_eq!(header.cartridge_type.mapper, Mapper::Mbc3);
+        assert!(header.cartridge_type.ram && header.cartridge_type.battery);
+        assert!(!header.cartridge_type.timer);
+        assert_eq!(header.rom_size().unwrap(), 0x10000);
+        assert_eq!(header.ram_size().unwrap(), 0x8000);
+        assert_eq!(header.destination, Destination::Overseas);
+        assert_eq!(header.version, 0x02);
+
+        assert!(Cartridge::from_bytes(image).is_ok());
+    }
+
+    type Expect = fn(&CartridgeError) -> bool;
+
+    #[test]
+    fn rejects_bad_images() {
+        let corrupt_checksum = {
+            let mut image = with_header(0x00, 0x00, 0x00);
+            image[0x14D] ^= 0xFF;
+            image
+        };
+        let oversized = {
+            let mut image = with_header(0x00, 0x00, 0x00);
+            image.resize(0x10000, 0);
+            image
+        };
+        let undersized = {
+            let mut image = with_header(0x01, 0x01, 0x00);
+            image.truncate(0x8000);
+            image
+        };
+
+        let cases: [(&str, Vec<u8>, Expect); 8] = [
+            ("shorter than the header", vec![0; 0x100], |e| {
+                matches!(
+                    e,
+                    CartridgeError::Truncated {
+                        expected: 0x150,
+                        actual: 0x100
+                    }
+                )
+            }),
+            (
+                "unknown cartridge type",
+                with_header(0x04, 0x00, 0x00),
+                |e| matches!(e, CartridgeError::UnknownCartridgeType(0x04)),
+            ),
+            ("unknown ROM size", with_header(0x00, 0x09, 0x00), |e| {
+                matches!(e, CartridgeError::UnknownRomSize(0x09))
+            }),
+            ("unknown RAM size", with_header(0x00, 0x00, 0x06), |e| {
+                matches!(e, CartridgeError::UnknownRamSize(0x06))
+            }),
+            ("corrupt header checksum", corrupt_checksum, |e| {
+                matches!(e, CartridgeError::HeaderChecksumMismatch { .. })
+            }),
+            ("larger than declared", oversized, |e| {
+                matches!(
+                    e,
+                    CartridgeError::RomSizeMismatch {
+                        declared: 0x8000,
+                        actual: 0x10000
+                    }
+                )
+            }),
+            ("smaller than declared", undersized, |e| {
+                matches!(
+                    e,
+                    CartridgeError::Truncated {
+                        expected: 0x10000,
+                        actual: 0x8000
+                    }
+                )
+            }),
+            ("MMM01", with_header(0x0B, 0x00, 0x00), |e| {
+                matches!(e, CartridgeError::UnsupportedMapper(Mapper::Mmm01))
+            }),
+        ];
+
+        for (name, image, expected) in cases {
+            match Cartridge::from_bytes(image) {
+                Ok(_) => panic!("{}: loaded", name),
+                Err(err) => assert!(expected(&err), "{}: got {}", name, err),
+            }
+        }
+    }
+
+    #[test]
+    fn cgb_header_splits_title_and_manufacturer_code() {
+        let mut image = with_header(0x00, 0x00, 0x00);
+        image[0x134..0x143].copy_from_slice(b"POKEMON\0\0\0\0AAXE");
+        image[0x143] = 0x80;
+        fix_checksum(&mut image);
+
+        let header = Header::parse(&image).unwrap();
+        assert_eq!(header.title, "POKEMON");
+        assert_eq!(header.manufacturer_code.as_deref(), Some("AAXE"));
+        assert_eq!(header.cgb, CgbSupport::Supported);
+    }
+
+    /// A fresh directory under the system temp dir for one test's files.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("nyanboy-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Pushes the cartridge past the save delay and its next flush check.
+    fn let_ram_settle(cartridge: &mut Cartridge) {
+        cartridge.last_ram_write = cartridge.last_ram_write.map(|last| last - SAVE_DELAY);
+        cartridge.tick(SAVE_CHECK_INTERVAL);
+    }
+
+    #[test]
+    fn loads_an_existing_save_at_startup() {
+        let dir = temp_dir("load");
+        let path = dir.join("game.sav");
+        let mut data = vec![0; 0x2000];
+        data[0x0123] = 0x42;
+        fs::write(&path, &data).unwrap();
+
+        let mut cartridge = Cartridge::from_bytes(with_header(0x03, 0x00, 0x02)).unwrap();
+        cartridge.attach_save_file(&path).unwrap();
+        cartridge.write_rom(0x0000, 0x0A);
+        assert_eq!(cartridge.read_ram(0xA123), 0x42);
+
+        drop(cartridge);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn flushes_only_after_ram_goes_quiet() {
+        let dir = temp_dir("flush");
+        let path = dir.join("game.sav");
+        let mut cartridge = Cartridge::from_bytes(with_header(0x03, 0x00, 0x02)).unwrap();
+        cartridge.attach_save_file(&path).unwrap();
+
+        // Writes that change nothing leave the save clean
+        cartridge.write_ram(0xA000, 0x99);
+        cartridge.write_rom(0x0000, 0x0A);
+        cartridge.write_ram(0xA000, 0x00);
+        assert!(!cartridge.dirty);
+
+        cartridge.write_ram(0xA000, 0x99);
+        assert!(cartridge.dirty);
+        cartridge.tick(SAVE_CHECK_INTERVAL);
+        assert!(!path.exists(), "saved before the delay");
+
+        let_ram_settle(&mut cartridge);
+        assert!(!cartridge.dirty);
+        let saved = fs::read(&path).unwrap();
+        assert_eq!(saved.len(), 0x2000);
+        assert_eq!(saved[0], 0x99);
+        assert_eq!(
+            fs::read_dir(&dir).unwrap().count(),
+            1,
+            "temporary file left"
+        );
+
+        drop(cartridge);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn save_replaces_the_old_file() {
+        let dir = temp_dir("replace");
+        let path = dir.join("game.sav");
+        fs::write(&path, vec![0x11; 0x2000]).unwrap();
+
+        let mut cartridge = Cartridge::from_bytes(with_header(0x03, 0x00, 0x02)).unwrap();
+        cartridge.attach_save_file(&path).unwrap();
+        cartridge.write_rom(0x0000, 0x0A);
+        cartridge.write_ram(0xBFFF, 0x22);
+        drop(cartridge);
+
+        let saved = fs::read(&path).unwrap();
+        assert_eq!(saved[0], 0x11);
+        assert_eq!(saved[0x1FFF], 0x22);
+        assert!(!dir.join("game.sav.tmp").exists());
+
+        let mut reloaded = Cartridge::from_bytes(with_header(0x03, 0x00, 0x02)).unwrap();
+        reloaded.attach_save_file(&path).unwrap();
+        reloaded.write_rom(0x0000, 0x0A);
+        assert_eq!(reloaded.read_ram(0xBFFF), 0x22);
+
+        drop(reloaded);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn clock_footer_survives_a_reload() {
+        let dir = temp_dir("rtc");
+        let path = dir.join("game.sav");
+        let load = |path: &Path| {
+            let mut cartridge = Cartridge::from_bytes(with_header(0x10, 0x00, 0x03)).unwrap();
+            cartridge.attach_save_file(path).unwrap();
+            cartridge.write_rom(0x0000, 0x0A);
+            cartridge
+        };
+        let read_minutes = |cartridge: &mut Cartridge| {
+            cartridge.write_rom(0x6000, 0x00);
+            cartridge.write_rom(0x6000, 0x01);
+            cartridge.write_rom(0x4000, 0x09);
+            cartridge.read_ram(0xA000)
+        };
+
+        let mut cartridge = load(&path);
+        // Halt the clock so the minutes can't roll over mid-test
+        cartridge.write_rom(0x4000, 0x0C);
+        cartridge.write_ram(0xA000, 0x40);
+        assert!(cartridge.dirty);
+        cartridge.write_rom(0x4000, 0x09);
+        cartridge.write_ram(0xA000, 37);
+        drop(cartridge);
+        assert_eq!(fs::read(&path).unwrap().len(), 0x8000 + 48);
+
+        let mut cartridge = load(&path);
+        assert_eq!(read_minutes(&mut cartridge), 37);
+        drop(cartridge);
+
+        // Older emulators write a footer with a 32-bit timestamp
+        let mut short = fs::read(&path).unwrap();
+        short.truncate(0x8000 + 44);
+        fs::write(&path, short).unwrap();
+        let mut cartridge = load(&path);
+        assert_eq!(read_minutes(&mut cartridge), 37);
+
+        drop(cartridge);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn clock_progress_is_saved_without_ram_writes() {
+        let dir = temp_dir("rtc-progress");
+        let path = dir.join("game.sav");
+        let load = |path: &Path| {
+            let mut cartridge = Cartridge::from_bytes(with_header(0x10, 0x00, 0x03)).unwrap();
+            cartridge.set_rtc_clock(RtcClock::Cycles);
+            cartridge.attach_save_file(path).unwrap();
+            cartridge
+        };
+
+        let mut cartridge = load(&path);
+        for _ in 0..3 {
+            cartridge.tick(4_194_304);
+        }
+        assert!(!cartridge.dirty);
+        drop(cartridge);
+
+        let mut cartridge = load(&path);
+        cartridge.write_rom(0x0000, 0x0A);
+        cartridge.write_rom(0x6000, 0x00);
+        cartridge.write_rom(0x6000, 0x01);
+        cartridge.write_rom(0x4000, 0x08);
+        assert_eq!(cartridge.read_ram(0xA000), 3);
+
+        drop(cartridge);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,17 +1,39 @@
-use crate::{opcodes::OP, registers};
-use std::{fs::File, io::Read};
+use crate::{
+    alu::{self, Flags},
+    cartridge::{Cartridge, CartridgeError},
+    joypad::Button,
+    mbc::RtcClock,
+    mmu::Mmu,
+    opcodes::{DecodeError, Timing, OP},
+    registers,
+    serial::SerialDevice,
+};
+use std::{fmt, fs::File, io::Read, path::Path};
+
+/// The CPU hit an opcode with no instruction behind it and has hung, the
+/// way hardware does. Only a reset gets it going again.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Lockup {
+    pub opcode: u8,
+    pub address: u16,
+}
+
+impl fmt::Display for Lockup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "CPU locked up on illegal opcode {:#04x} at {:#06x}",
+            self.opcode, self.address
+        )
+    }
+}
+
+impl std::error::Error for Lockup {}
 
 pub struct Instruction {
     opcode: OP,
     size: u8,
-    duration: u8,
-}
-
-pub struct Flags {
-    z: bool,
-    n: bool,
-    h: bool,
-    c: bool,
+    timing: Timing,
 }
 
 pub struct Cpu {
@@ -25,22 +47,25 @@ pub struct Cpu {
     l: u8,
     sp: u16,
     pc: u16,
-    memory: [u8; 0x10000],
+    mmu: Mmu,
     stopped: bool,
     halted: bool,
+    // HALT with IME off and an interrupt already pending fails to
+    // increment PC past the next opcode, so its first byte is read twice
+    halt_bug: bool,
     ime: bool,
+    // Set by EI; IME turns on once the next instruction has run
+    ime_scheduled: bool,
+    // M-cycles the current step has taken so far
+    elapsed: u32,
+    lockup: Option<Lockup>,
 }
 
 impl Cpu {
     pub fn new() -> Cpu {
         Cpu {
             a: 0x01,
-            f: Flags {
-                z: false,
-                n: false,
-                h: false,
-                c: false,
-            },
+            f: Flags::default(),
             b: 0x00,
             c: 0x13,
             d: 0x00,
@@ -49,269 +74,327 @@ impl Cpu {
             l: 0x4d,
             sp: 0xFFFE,
             pc: 0x100,
-            memory: [0; 0x10000],
+            mmu: Mmu::new(),
             stopped: false,
             halted: false,
+            halt_bug: false,
             ime: false,
+            ime_scheduled: false,
+            elapsed: 0,
+            lockup: None,
         }
     }
 
-    pub fn execute(&mut self) {
-        let (opcode, mut size, _duration) =
-            OP::from_bytes(&self.memory[self.pc as usize..]).expect("Unknown opcode");
+    /// Runs until the PPU has finished a frame, or until STOP has
+    /// switched the clock off. A locked up CPU still lets the rest of the
+    /// system run the frame, but reports the lockup.
+    pub fn run_frame(&mut self) -> Result<(), Lockup> {
+        while !self.mmu.ppu_mut().take_frame_ready() {
+            self.execute();
+            if self.stopped {
+                break;
+            }
+        }
 
-        if opcode != OP::Nop {
-            println!("{:?}", opcode);
-            print!("PC: {} \n", self.pc);
+        match self.lockup {
+            Some(lockup) => Err(lockup),
+            None => Ok(()),
         }
+    }
 
-        match opcode {
-            OP::AddR8(reg) => {
-                let value = self.get_reg8(reg);
-                let result = self.a.wrapping_add(value);
+    pub fn framebuffer(&self) -> &[u32] {
+        self.mmu.ppu().framebuffer()
+    }
 
-                self.set_all_flags(
-                    result == 0,
-                    false,
-                    (self.a & 0xf) + (value & 0xf) > 0xf,
-                    (self.a as u16) + (value as u16) > 0xff,
-                );
+    /// Runs one instruction, one idle HALT cycle or one interrupt dispatch,
+    /// returning the M-cycles it took. The rest of the system is ticked as
+    /// each memory access and internal delay happens rather than at the end.
+    pub fn execute(&mut self) -> u32 {
+        self.elapsed = 0;
 
-                self.a = result;
+        // Nothing, not even an interrupt, gets the CPU out of a lockup
+        if self.lockup.is_some() {
+            self.idle();
+            return self.elapsed;
+        }
+
+        if self.stopped {
+            // Everything is clocked off until a joypad line goes low
+            if !self.mmu.joypad_line_low() {
+                return 0;
             }
-            OP::AddR16(reg) => {
-                let address = self.get_reg16(reg);
-                let value = self.read_byte(address);
-                let result = self.a.wrapping_add(value);
+            self.stopped = false;
+        }
 
-                self.set_all_flags(
-                    result == 0,
-                    false,
-                    (self.a & 0xf) + (value & 0xf) > 0xf,
-                    (self.a as u16) + (value as u16) > 0xff,
-                );
+        if self.halted {
+            // HALT wakes on IE & IF regardless of IME; IME only decides
+            // whether the interrupt is serviced afterwards
+            if self.mmu.pending_interrupt().is_none() {
+                self.idle();
+                return self.elapsed;
+            }
+            self.halted = false;
+        }
 
-                self.a = result;
+        if self.dispatch_interrupt() {
+            return self.elapsed;
+        }
+
+        // EI only takes effect after the instruction following it
+        let enable_ime = self.ime_scheduled;
+
+        let Instruction {
+            opcode,
+            size,
+            timing,
+        } = self.fetch();
+        let mut cycles = timing.cycles;
+
+        // The opcode after a buggy HALT is fetched without moving PC, so
+        // its own byte is read again as the first operand or next opcode
+        if self.halt_bug {
+            self.halt_bug = false;
+            self.pc = self.pc.wrapping_sub(1);
+        }
+
+        // PC points at the next instruction from here on, which is what
+        // relative jumps, calls, RST and interrupt dispatch all expect
+        self.pc = self.pc.wrapping_add(size as u16);
+
+        match opcode {
+            OP::AddR8(reg) => {
+                let value = self.get_reg8(reg);
+                (self.a, self.f) = alu::add(self.a, value, false);
+            }
+            OP::AddR16(reg) => {
+                let value = self.read_byte(self.get_reg16(reg));
+                (self.a, self.f) = alu::add(self.a, value, false);
             }
             OP::DecR8(reg) => {
                 let value = self.get_reg8(reg);
-                let result = value.wrapping_sub(1);
-
-                self.set_all_flags(
-                    result == 0,
-                    true,
-                    (value & 0xf) == 0,
-                    self.get_flag(registers::Flag::C),
-                );
+                let result;
+                (result, self.f) = alu::dec(value, self.f.c);
                 self.set_reg8(reg, result);
             }
             OP::DecHL => {
                 let address = self.get_reg16(registers::Reg16::HL);
                 let value = self.read_byte(address);
-                let result = value.wrapping_sub(1);
-
-                self.set_all_flags(
-                    result == 0,
-                    true,
-                    (value & 0xf) == 0,
-                    self.get_flag(registers::Flag::C),
-                );
+                let result;
+                (result, self.f) = alu::dec(value, self.f.c);
                 self.write_byte(address, result);
             }
             OP::IncR8(reg) => {
                 let value = self.get_reg8(reg);
-                let result = value.wrapping_add(1);
-
-                self.set_all_flags(
-                    result == 0,
-                    false,
-                    (value & 0xf) == 0xf,
-                    self.get_flag(registers::Flag::C),
-                );
+                let result;
+                (result, self.f) = alu::inc(value, self.f.c);
+                self.set_reg8(reg, result);
             }
             OP::IncHL => {
                 let address = self.get_reg16(registers::Reg16::HL);
                 let value = self.read_byte(address);
-                let result = value.wrapping_add(1);
-
-                self.set_all_flags(
-                    result == 0,
-                    false,
-                    (value & 0xf) == 0xf,
-                    self.get_flag(registers::Flag::C),
-                );
+                let result;
+                (result, self.f) = alu::inc(value, self.f.c);
                 self.write_byte(address, result);
             }
             OP::AdcR8(reg) => {
                 let value = self.get_reg8(reg);
-                let result = self.a.wrapping_add(value) + self.get_flag(registers::Flag::C) as u8;
-
-                self.set_all_flags(
-                    result == 0,
-                    false,
-                    (self.a & 0xf) + (value & 0xf) + self.get_flag(registers::Flag::C) as u8 > 0xf,
-                    (self.a as u16) + (value as u16) + self.get_flag(registers::Flag::C) as u16
-                        > 0xff,
-                );
-
-                self.a = result;
+                (self.a, self.f) = alu::add(self.a, value, self.f.c);
             }
             OP::AdcR16(reg) => {
-                let address = self.get_reg16(reg);
-                let value = self.read_byte(address);
-                let result = self.a.wrapping_add(value) + self.get_flag(registers::Flag::C) as u8;
-
-                self.set_all_flags(
-                    result == 0,
-                    false,
-                    (self.a & 0xf) + (value & 0xf) + self.get_flag(registers::Flag::C) as u8 > 0xf,
-                    (self.a as u16) + (value as u16) + self.get_flag(registers::Flag::C) as u16
-                        > 0xff,
-                );
-
-                self.a = result;
+                let value = self.read_byte(self.get_reg16(reg));
+                (self.a, self.f) = alu::add(self.a, value, self.f.c);
             }
             OP::SubR8(reg) => {
                 let value = self.get_reg8(reg);
-                let result = self.a.wrapping_sub(value);
-
-                self.set_all_flags(
-                    result == 0,
-                    true,
-                    (self.a & 0xf) < (value & 0xf),
-                    (self.a as u16) < (value as u16),
-                );
-
-                self.a = result;
+                (self.a, self.f) = alu::sub(self.a, value, false);
             }
             OP::SubR16(reg) => {
-                let address = self.get_reg16(reg);
-                let value = self.read_byte(address);
-                let result = self.a.wrapping_sub(value);
-
-                self.set_all_flags(
-                    result == 0,
-                    true,
-                    (self.a & 0xf) < (value & 0xf),
-                    (self.a as u16) < (value as u16),
-                );
-
-                self.a = result;
+                let value = self.read_byte(self.get_reg16(reg));
+                (self.a, self.f) = alu::sub(self.a, value, false);
             }
             OP::SbcR8(reg) => {
                 let value = self.get_reg8(reg);
-                let result = self.a.wrapping_sub(value) - self.get_flag(registers::Flag::C) as u8;
-
-                self.set_all_flags(
-                    result == 0,
-                    true,
-                    (self.a & 0xf) < (value & 0xf),
-                    (self.a as u16) < (value as u16),
-                );
-
-                self.a = result;
+                (self.a, self.f) = alu::sub(self.a, value, self.f.c);
             }
             OP::SbcR16(reg) => {
-                let address = self.get_reg16(reg);
-                let value = self.read_byte(address);
-                let result = self.a.wrapping_sub(value) - self.get_flag(registers::Flag::C) as u8;
-
-                self.set_all_flags(
-                    result == 0,
-                    true,
-                    (self.a & 0xf) < (value & 0xf),
-                    (self.a as u16) < (value as u16),
-                );
-
-                self.a = result;
+                let value = self.read_byte(self.get_reg16(reg));
+                (self.a, self.f) = alu::sub(self.a, value, self.f.c);
             }
             OP::AddHLR16(reg) => {
-                let address = self.get_reg16(reg);
-                let value = self.read_byte(address);
-                let result = self
-                    .get_reg16(registers::Reg16::HL)
-                    .wrapping_add(value as u16);
-
-                self.set_all_flags(
-                    false,
-                    false,
-                    (self.get_reg16(registers::Reg16::HL) & 0xfff) + (value as u16 & 0xfff) > 0xfff,
-                    (self.get_reg16(registers::Reg16::HL) as u32) + (value as u32) > 0xffff,
-                );
-
+                let value = self.get_reg16(reg);
+                let result;
+                (result, self.f) =
+                    alu::add16(self.get_reg16(registers::Reg16::HL), value, self.f.z);
                 self.set_reg16(registers::Reg16::HL, result);
+                self.idle();
             }
             OP::DecR16(reg) => {
                 let value = self.get_reg16(reg);
                 self.set_reg16(reg, value.wrapping_sub(1));
+                self.idle();
             }
             OP::IncR16(reg) => {
                 let value = self.get_reg16(reg);
                 self.set_reg16(reg, value.wrapping_add(1));
+                self.idle();
+            }
+            OP::RlcR8(reg) => {
+                let value = self.get_reg8(reg);
+                let result;
+                (result, self.f) = alu::rlc(value);
+                self.set_reg8(reg, result);
+            }
+            OP::RlcHL => {
+                let address = self.get_reg16(registers::Reg16::HL);
+                let value = self.read_byte(address);
+                let result;
+                (result, self.f) = alu::rlc(value);
+                self.write_byte(address, result);
+            }
+            OP::RlcA => {
+                (self.a, self.f) = alu::rlc(self.a);
+                self.f.z = false;
+            }
+            OP::RrcR8(reg) => {
+                let value = self.get_reg8(reg);
+                let result;
+                (result, self.f) = alu::rrc(value);
+                self.set_reg8(reg, result);
+            }
+            OP::RrcHL => {
+                let address = self.get_reg16(registers::Reg16::HL);
+                let value = self.read_byte(address);
+                let result;
+                (result, self.f) = alu::rrc(value);
+                self.write_byte(address, result);
+            }
+            OP::RrcA => {
+                (self.a, self.f) = alu::rrc(self.a);
+                self.f.z = false;
             }
             OP::RlR8(reg) => {
                 let value = self.get_reg8(reg);
-                let result = (value << 1) | (self.get_flag(registers::Flag::C) as u8);
-
-                self.set_all_flags(
-                    result == 0,
-                    false,
-                    (value & 0x80) == 0x80,
-                    (value & 0x80) == 0x80,
-                );
-
+                let result;
+                (result, self.f) = alu::rl(value, self.f.c);
                 self.set_reg8(reg, result);
             }
+            OP::RlHL => {
+                let address = self.get_reg16(registers::Reg16::HL);
+                let value = self.read_byte(address);
+                let result;
+                (result, self.f) = alu::rl(value, self.f.c);
+                self.write_byte(address, result);
+            }
             OP::RlA => {
-                let value = self.a;
-                let result = (value << 1) | (self.get_flag(registers::Flag::C) as u8);
-
-                self.set_all_flags(
-                    result == 0,
-                    false,
-                    (value & 0x80) == 0x80,
-                    (value & 0x80) == 0x80,
-                );
-
-                self.a = result;
+                (self.a, self.f) = alu::rl(self.a, self.f.c);
+                self.f.z = false;
             }
             OP::RrR8(reg) => {
                 let value = self.get_reg8(reg);
-                let result = (value >> 1) | ((self.get_flag(registers::Flag::C) as u8) << 7);
-
-                self.set_all_flags(
-                    result == 0,
-                    false,
-                    (value & 0x1) == 0x1,
-                    (value & 0x1) == 0x1,
-                );
-
+                let result;
+                (result, self.f) = alu::rr(value, self.f.c);
                 self.set_reg8(reg, result);
             }
+            OP::RrHL => {
+                let address = self.get_reg16(registers::Reg16::HL);
+                let value = self.read_byte(address);
+                let result;
+                (result, self.f) = alu::rr(value, self.f.c);
+                self.write_byte(address, result);
+            }
             OP::RrA => {
-                let value = self.a;
-                let result = (value >> 1) | ((self.get_flag(registers::Flag::C) as u8) << 7);
-
-                self.set_all_flags(
-                    result == 0,
-                    false,
-                    (value & 0x1) == 0x1,
-                    (value & 0x1) == 0x1,
-                );
-
-                self.a = result;
+                (self.a, self.f) = alu::rr(self.a, self.f.c);
+                self.f.z = false;
+            }
+            OP::SlaR8(reg) => {
+                let value = self.get_reg8(reg);
+                let result;
+                (result, self.f) = alu::sla(value);
+                self.set_reg8(reg, result);
+            }
+            OP::SlaHL => {
+                let address = self.get_reg16(registers::Reg16::HL);
+                let value = self.read_byte(address);
+                let result;
+                (result, self.f) = alu::sla(value);
+                self.write_byte(address, result);
+            }
+            OP::SrAR8(reg) => {
+                let value = self.get_reg8(reg);
+                let result;
+                (result, self.f) = alu::sra(value);
+                self.set_reg8(reg, result);
+            }
+            OP::SrAHL => {
+                let address = self.get_reg16(registers::Reg16::HL);
+                let value = self.read_byte(address);
+                let result;
+                (result, self.f) = alu::sra(value);
+                self.write_byte(address, result);
+            }
+            OP::SrlR8(reg) => {
+                let value = self.get_reg8(reg);
+                let result;
+                (result, self.f) = alu::srl(value);
+                self.set_reg8(reg, result);
+            }
+            OP::SrlHL => {
+                let address = self.get_reg16(registers::Reg16::HL);
+                let value = self.read_byte(address);
+                let result;
+                (result, self.f) = alu::srl(value);
+                self.write_byte(address, result);
+            }
+            OP::SwapR8(reg) => {
+                let value = self.get_reg8(reg);
+                let result;
+                (result, self.f) = alu::swap(value);
+                self.set_reg8(reg, result);
+            }
+            OP::SwapHL => {
+                let address = self.get_reg16(registers::Reg16::HL);
+                let value = self.read_byte(address);
+                let result;
+                (result, self.f) = alu::swap(value);
+                self.write_byte(address, result);
+            }
+            OP::BitBR8(bit, reg) => {
+                let value = self.get_reg8(reg);
+                self.f = alu::bit(bit, value, self.f.c);
+            }
+            OP::BitBHL(bit) => {
+                let value = self.read_byte(self.get_reg16(registers::Reg16::HL));
+                self.f = alu::bit(bit, value, self.f.c);
+            }
+            OP::ResBR8(bit, reg) => {
+                let value = self.get_reg8(reg);
+                self.set_reg8(reg, value & !(1 << bit));
+            }
+            OP::ResBHL(bit) => {
+                let address = self.get_reg16(registers::Reg16::HL);
+                let value = self.read_byte(address);
+                self.write_byte(address, value & !(1 << bit));
+            }
+            OP::SetBR8(bit, reg) => {
+                let value = self.get_reg8(reg);
+                self.set_reg8(reg, value | (1 << bit));
+            }
+            OP::SetBHL(bit) => {
+                let address = self.get_reg16(registers::Reg16::HL);
+                let value = self.read_byte(address);
+                self.write_byte(address, value | (1 << bit));
             }
             OP::LdR8R8(reg, reg2) => {
                 let value = self.get_reg8(reg2);
                 self.set_reg8(reg, value);
             }
-            OP::LdMemR8(_reg, reg2) => {
-                let address = self.get_reg16(registers::Reg16::HL);
+            OP::LdMemR8(reg, reg2) => {
+                let address = self.get_reg16(reg);
                 let value = self.get_reg8(reg2);
                 self.write_byte(address, value);
             }
-            OP::LdR8Mem(reg, _reg2) => {
-                let address = self.get_reg16(registers::Reg16::HL);
+            OP::LdR8Mem(reg, reg2) => {
+                let address = self.get_reg16(reg2);
                 let value = self.read_byte(address);
                 self.set_reg8(reg, value);
             }
@@ -322,7 +405,8 @@ impl Cpu {
                 self.set_reg16(reg, value);
             }
             OP::LdHLImm(value) => {
-                self.set_reg16(registers::Reg16::HL, value as u16);
+                let address = self.get_reg16(registers::Reg16::HL);
+                self.write_byte(address, value);
             }
             OP::LdR16R8(reg1, reg2) => {
                 let address = self.get_reg16(reg1);
@@ -366,38 +450,46 @@ impl Cpu {
                 );
             }
             OP::Jr(value) => {
-                self.pc = self.pc.wrapping_add(value as u16);
+                self.pc = self.pc.wrapping_add(value);
+                self.idle();
             }
             OP::JrCond(flag, value) => {
                 if self.get_flag(flag) {
-                    self.pc = self.pc.wrapping_add(value as u16);
+                    cycles = timing.taken;
+                    self.pc = self.pc.wrapping_add(value);
+                    self.idle();
                 }
             }
             OP::RetCond(flag) => {
+                self.idle();
                 if self.get_flag(flag) {
-                    size = 20;
-                    let address = self.pop_stack();
-                    self.pc = address;
+                    cycles = timing.taken;
+                    self.pc = self.pop_stack();
+                    self.idle();
                 }
             }
             OP::Ret => {
-                let address = self.pop_stack();
-                self.pc = address;
+                self.pc = self.pop_stack();
+                self.idle();
             }
             OP::Reti => {
-                let address = self.pop_stack();
-                self.pc = address;
+                self.pc = self.pop_stack();
+                self.idle();
                 self.ime = true;
             }
             OP::Rst(value) => {
+                self.idle();
                 self.push_stack(self.pc);
-                self.pc = value as u16;
+                self.pc = value;
             }
             OP::LdImmSP(value) => {
-                self.sp = value;
+                let [low, high] = self.sp.to_le_bytes();
+                self.write_byte(value, low);
+                self.write_byte(value.wrapping_add(1), high);
             }
             OP::LdSPHL => {
                 self.sp = self.get_reg16(registers::Reg16::HL);
+                self.idle();
             }
             OP::PopR16(reg) => {
                 let value = self.pop_stack();
@@ -405,229 +497,221 @@ impl Cpu {
             }
             OP::PushR16(reg) => {
                 let value = self.get_reg16(reg);
+                self.idle();
                 self.push_stack(value);
             }
             OP::Ccf => {
-                self.set_all_flags(false, false, false, !self.get_flag(registers::Flag::C));
+                self.f.n = false;
+                self.f.h = false;
+                self.f.c = !self.f.c;
             }
             OP::Cpl => {
                 self.a = !self.a;
-                self.set_all_flags(true, true, true, self.get_flag(registers::Flag::C));
+                self.f.n = true;
+                self.f.h = true;
             }
             OP::Daa => {
-                let mut value = self.a;
-                if self.get_flag(registers::Flag::N) {
-                    if self.get_flag(registers::Flag::H) {
-                        value = value.wrapping_sub(0x06);
-                    }
-                    if self.get_flag(registers::Flag::C) {
-                        value = value.wrapping_sub(0x60);
-                    }
-                } else {
-                    if self.get_flag(registers::Flag::H) || (value & 0xF) > 9 {
-                        value = value.wrapping_add(0x06);
-                    }
-                    if self.get_flag(registers::Flag::C) || value > 0x9F {
-                        value = value.wrapping_add(0x60);
-                    }
-                }
-
-                self.set_all_flags(
-                    value == 0,
-                    self.get_flag(registers::Flag::N),
-                    false,
-                    value as u16 > 0xFF,
-                );
-
-                self.a = value;
+                (self.a, self.f) = alu::daa(self.a, self.f);
             }
             OP::Di => {
                 self.ime = false;
+                self.ime_scheduled = false;
             }
             OP::Ei => {
-                self.ime = true;
+                self.ime_scheduled = true;
             }
             OP::Halt => {
-                self.halted = true;
+                if self.ime || self.mmu.pending_interrupt().is_none() {
+                    self.halted = true;
+                } else {
+                    self.halt_bug = true;
+                }
             }
             OP::Nop => {}
+            OP::Illegal(opcode) => {
+                self.lockup = Some(Lockup {
+                    opcode,
+                    address: self.pc.wrapping_sub(1),
+                });
+            }
             OP::Scf => {
-                self.set_all_flags(false, false, false, true);
+                self.f.n = false;
+                self.f.h = false;
+                self.f.c = true;
             }
             OP::Stop => {
-                self.stopped = true;
+                // On CGB an armed KEY1 turns STOP into a speed switch
+                if !self.mmu.take_speed_switch() {
+                    self.stopped = true;
+                    self.mmu.reset_divider();
+                }
             }
             OP::AndR8(reg) => {
                 let value = self.get_reg8(reg);
-                self.a &= value;
-                self.set_all_flags(self.a == 0, false, true, false);
+                (self.a, self.f) = alu::and(self.a, value);
             }
             OP::AndR16(reg) => {
-                let value = self.get_reg16(reg);
-                self.a &= (value >> 8) as u8;
-                self.set_all_flags(self.a == 0, false, true, false);
+                let value = self.read_byte(self.get_reg16(reg));
+                (self.a, self.f) = alu::and(self.a, value);
             }
             OP::XorR8(reg) => {
                 let value = self.get_reg8(reg);
-                self.a ^= value;
-                self.set_all_flags(self.a == 0, false, false, false);
+                (self.a, self.f) = alu::xor(self.a, value);
             }
             OP::XorR16(reg) => {
-                let value = self.get_reg16(reg);
-                self.a ^= (value >> 8) as u8;
-                self.set_all_flags(self.a == 0, false, false, false);
+                let value = self.read_byte(self.get_reg16(reg));
+                (self.a, self.f) = alu::xor(self.a, value);
             }
             OP::OrR8(reg) => {
                 let value = self.get_reg8(reg);
-                self.a |= value;
-                self.set_all_flags(self.a == 0, false, false, false);
+                (self.a, self.f) = alu::or(self.a, value);
             }
             OP::OrR16(reg) => {
-                let value = self.get_reg16(reg);
-                self.a |= (value >> 8) as u8;
-                self.set_all_flags(self.a == 0, false, false, false);
+                let value = self.read_byte(self.get_reg16(reg));
+                (self.a, self.f) = alu::or(self.a, value);
             }
             OP::CpR8(reg) => {
                 let value = self.get_reg8(reg);
-                self.set_all_flags(self.a == value, true, self.a < value, false);
+                (_, self.f) = alu::sub(self.a, value, false);
             }
             OP::CpR16(reg) => {
-                let value = self.get_reg16(reg);
-                self.set_all_flags(
-                    self.a == (value >> 8) as u8,
-                    true,
-                    self.a < (value >> 8) as u8,
-                    false,
-                );
+                let value = self.read_byte(self.get_reg16(reg));
+                (_, self.f) = alu::sub(self.a, value, false);
             }
-            OP::JPCondImm16(flag) => {
-                let value = self.read_imm16();
+            OP::JPCondImm16(flag, value) => {
                 if self.get_flag(flag) {
+                    cycles = timing.taken;
                     self.pc = value;
+                    self.idle();
                 }
             }
-            OP::CallCondImm16(flag) => {
+            OP::CallCondImm16(flag, value) => {
                 if self.get_flag(flag) {
-                    let value = self.read_imm16();
+                    cycles = timing.taken;
+                    self.idle();
                     self.push_stack(self.pc);
                     self.pc = value;
                 }
             }
-            OP::JPImm16 => {
-                let value = self.read_imm16();
+            OP::JPImm16(value) => {
                 self.pc = value;
+                self.idle();
             }
-            OP::AddImm8 => {
-                let value = self.read_imm8();
-                let (result, overflow) = self.a.overflowing_add(value);
-                self.set_all_flags(result == 0, false, false, overflow);
-                self.a = result;
-            }
-            OP::CBPrefix => {
-                let opcode = self.read_imm8();
-                self.execute_cb(opcode);
+            OP::AddImm8(value) => {
+                (self.a, self.f) = alu::add(self.a, value, false);
             }
-            OP::CallImm16 => {
-                let value = self.read_imm16();
+            OP::CallImm16(value) => {
+                self.idle();
                 self.push_stack(self.pc);
                 self.pc = value;
             }
-            OP::AdcImm8 => {
-                let value = self.read_imm8();
-                let carry = if self.get_flag(registers::Flag::C) {
-                    1
-                } else {
-                    0
-                };
-                let (result, overflow) = self.a.overflowing_add(value);
-                let (result, overflow2) = result.overflowing_add(carry);
-                self.set_all_flags(result == 0, false, false, overflow || overflow2);
-                self.a = result;
-            }
-            OP::SubImm8 => {
-                let value = self.read_imm8();
-                let (result, overflow) = self.a.overflowing_sub(value);
-                self.set_all_flags(result == 0, true, overflow, false);
-                self.a = result;
-            }
-            OP::SbcImm8 => {
-                let value = self.read_imm8();
-                let carry = if self.get_flag(registers::Flag::C) {
-                    1
-                } else {
-                    0
-                };
-                let (result, overflow) = self.a.overflowing_sub(value);
-                let (result, overflow2) = result.overflowing_sub(carry);
-                self.set_all_flags(result == 0, true, overflow || overflow2, false);
-                self.a = result;
+            OP::AdcImm8(value) => {
+                (self.a, self.f) = alu::add(self.a, value, self.f.c);
             }
-            OP::LdIOImm8A => {
-                let value = self.read_imm8();
-                self.write_io(value as u16, self.a);
+            OP::SubImm8(value) => {
+                (self.a, self.f) = alu::sub(self.a, value, false);
+            }
+            OP::SbcImm8(value) => {
+                (self.a, self.f) = alu::sub(self.a, value, self.f.c);
+            }
+            OP::LdIOImm8A(value) => {
+                self.write_byte(0xFF00 | value as u16, self.a);
             }
             OP::LdIOC => {
-                let value = self.read_io(self.c as u16);
-                self.a = value;
+                self.write_byte(0xFF00 | self.c as u16, self.a);
             }
-            OP::AndImm8 => {
-                let value = self.read_imm8();
-                self.a &= value;
-                self.set_all_flags(self.a == 0, false, true, false);
+            OP::AndImm8(value) => {
+                (self.a, self.f) = alu::and(self.a, value);
             }
-            OP::AddSPImm8 => {
-                let value = self.read_imm8();
-                let (result, overflow) = self.sp.overflowing_add(value as u16);
-                self.set_all_flags(false, false, overflow, overflow);
-                self.sp = result;
+            OP::AddSPImm8(value) => {
+                (self.sp, self.f) = alu::add_sp(self.sp, value);
+                self.idle();
+                self.idle();
             }
-            OP::LdImm16A => {
-                let value = self.read_imm16();
-                self.write_mem(value, self.a);
+            OP::LdImm16A(value) => {
+                self.write_byte(value, self.a);
             }
             OP::JPHL => {
                 let value = self.get_reg16(registers::Reg16::HL);
                 self.pc = value;
             }
-            OP::XorImm8 => {
-                let value = self.read_imm8();
-                self.a ^= value;
-                self.set_all_flags(self.a == 0, false, false, false);
+            OP::XorImm8(value) => {
+                (self.a, self.f) = alu::xor(self.a, value);
             }
-            OP::LdAIOImm8 => {
-                let value = self.read_imm8();
-                self.a = self.read_io(value as u16);
+            OP::LdAIOImm8(value) => {
+                self.a = self.read_byte(0xFF00 | value as u16);
             }
             OP::LdACIO => {
-                let value = self.read_io(self.c as u16);
-                self.a = value;
+                self.a = self.read_byte(0xFF00 | self.c as u16);
             }
-            OP::OrImm8 => {
-                let value = self.read_imm8();
-                self.a |= value;
-                self.set_all_flags(self.a == 0, false, false, false);
+            OP::OrImm8(value) => {
+                (self.a, self.f) = alu::or(self.a, value);
             }
-            OP::LdHLSPImm8 => {
-                let value = self.read_imm8();
-                let (result, overflow) = self.sp.overflowing_add(value as u16);
-                self.set_all_flags(false, false, overflow, overflow);
+            OP::LdHLSPImm8(value) => {
+                let result;
+                (result, self.f) = alu::add_sp(self.sp, value);
                 self.set_reg16(registers::Reg16::HL, result);
+                self.idle();
             }
-            OP::LdAImm16 => {
-                let value = self.read_imm16();
-                self.a = self.read_mem(value);
+            OP::LdAImm16(value) => {
+                self.a = self.read_byte(value);
             }
-            OP::CpImm8 => {
-                let value = self.read_imm8();
-                let (result, overflow) = self.a.overflowing_sub(value);
-                self.set_all_flags(result == 0, true, overflow, false);
-            }
-            _ => {
-                panic!("Unknown opcode {:?}", opcode);
+            OP::CpImm8(value) => {
+                (_, self.f) = alu::sub(self.a, value, false);
             }
         }
 
-        self.pc += size as u16;
+        // A DI straight after EI cancels the pending enable
+        if enable_ime && self.ime_scheduled {
+            self.ime = true;
+            self.ime_scheduled = false;
+        }
+
+        debug_assert_eq!(
+            self.elapsed * 4,
+            cycles as u32,
+            "{:?} took the wrong number of cycles",
+            opcode
+        );
+        self.elapsed
+    }
+
+    /// Services the highest-priority pending interrupt if IME allows it:
+    /// PC is pushed and execution continues at the interrupt's vector,
+    /// which takes 5 M-cycles.
+    fn dispatch_interrupt(&mut self) -> bool {
+        if !self.ime || self.mmu.pending_interrupt().is_none() {
+            return false;
+        }
+
+        self.ime = false;
+        // EI; HALT with an interrupt pending returns to the HALT itself
+        if self.halt_bug {
+            self.halt_bug = false;
+            self.pc = self.pc.wrapping_sub(1);
+        }
+
+        self.idle();
+        self.idle();
+        let [low, high] = self.pc.to_le_bytes();
+        self.sp = self.sp.wrapping_sub(1);
+        self.write_byte(self.sp, high);
+        // The vector is only picked after the high byte is pushed, so a push
+        // that overwrites IE can redirect or cancel the dispatch
+        let interrupt = self.mmu.pending_interrupt();
+        self.sp = self.sp.wrapping_sub(1);
+        self.write_byte(self.sp, low);
+
+        self.pc = match interrupt {
+            Some(interrupt) => {
+                self.mmu.acknowledge_interrupt(interrupt);
+                interrupt.vector()
+            }
+            None => 0x0000,
+        };
+        self.idle();
+
+        true
     }
 
     fn get_reg8(&self, reg1: registers::Reg8) -> u8 {
@@ -644,22 +728,15 @@ impl Cpu {
 
     fn get_reg16(&self, reg1: registers::Reg16) -> u16 {
         match reg1 {
-            registers::Reg16::AF => {
-                ((self.a as u16) << 8)
-                    | (self.f.z as u16) << 7
-                    | (self.f.n as u16) << 6
-                    | (self.f.h as u16) << 5
-                    | (self.f.c as u16) << 4
-            }
+            registers::Reg16::AF => (self.a as u16) << 8 | self.f.to_byte() as u16,
             registers::Reg16::BC => ((self.b as u16) << 8) | (self.c as u16),
             registers::Reg16::DE => ((self.d as u16) << 8) | (self.e as u16),
             registers::Reg16::HL => ((self.h as u16) << 8) | (self.l as u16),
             registers::Reg16::SP => self.sp,
-            registers::Reg16::PC => self.pc,
         }
     }
 
-    fn set_reg8(&mut self, reg1: registers::Reg8, value: u8) -> () {
+    fn set_reg8(&mut self, reg1: registers::Reg8, value: u8) {
         match reg1 {
             registers::Reg8::A => self.a = value,
             registers::Reg8::B => self.b = value,
@@ -671,9 +748,12 @@ impl Cpu {
         }
     }
 
-    fn set_reg16(&mut self, reg1: registers::Reg16, value: u16) -> () {
+    fn set_reg16(&mut self, reg1: registers::Reg16, value: u16) {
         match reg1 {
-            registers::Reg16::AF => panic!("Cannot set AF register"),
+            registers::Reg16::AF => {
+                self.a = ((value & 0xff00) >> 8) as u8;
+                self.f = Flags::from_byte(value as u8);
+            }
             registers::Reg16::BC => {
                 self.b = ((value & 0xff00) >> 8) as u8;
                 self.c = (value & 0x00ff) as u8;
@@ -687,99 +767,544 @@ impl Cpu {
                 self.l = (value & 0x00ff) as u8;
             }
             registers::Reg16::SP => self.sp = value,
-            registers::Reg16::PC => self.pc = value,
         }
     }
 
     fn get_flag(&self, flag: registers::Flag) -> bool {
         match flag {
             registers::Flag::Z => self.f.z,
-            registers::Flag::N => self.f.n,
-            registers::Flag::H => self.f.h,
             registers::Flag::C => self.f.c,
             registers::Flag::NZ => !self.f.z,
             registers::Flag::NC => !self.f.c,
         }
     }
 
-    fn set_flag(&mut self, flag: registers::Flag, value: bool) -> () {
-        match flag {
-            registers::Flag::Z => self.f.z = value,
-            registers::Flag::N => self.f.n = value,
-            registers::Flag::H => self.f.h = value,
-            registers::Flag::C => self.f.c = value,
-            registers::Flag::NZ => self.f.z = !value,
-            registers::Flag::NC => self.f.c = !value,
+    pub fn load_rom(&mut self, path: &str) -> Result<(), CartridgeError> {
+        let mut file = File::open(path)?;
+        let mut buffer = Vec::new();
+
+        file.read_to_end(&mut buffer)?;
+
+        let cartridge = Cartridge::from_bytes(buffer)?;
+        if !cartridge.global_checksum_valid() {
+            eprintln!("Warning: global checksum mismatch in {}", path);
         }
+
+        // The RTC clock source has to be in place before the save is read,
+        // which load_cartridge takes care of.
+        self.mmu.load_cartridge(cartridge);
+        self.mmu
+            .attach_save_file(&Path::new(path).with_extension("sav"))?;
+
+        Ok(())
     }
 
-    fn set_all_flags(&mut self, z: bool, n: bool, h: bool, c: bool) -> () {
-        self.set_flag(registers::Flag::Z, z);
-        self.set_flag(registers::Flag::N, n);
-        self.set_flag(registers::Flag::H, h);
-        self.set_flag(registers::Flag::C, c);
+    /// Chooses what drives the cartridge RTC. Takes effect for the loaded
+    /// cartridge and any loaded afterwards.
+    pub fn set_rtc_clock(&mut self, clock: RtcClock) {
+        self.mmu.set_rtc_clock(clock);
     }
 
-    pub fn load_rom(&mut self, path: &str) -> () {
-        let mut file = File::open(path).expect("Failed to open file");
-        let mut buffer = Vec::new();
+    /// Registers a callback invoked whenever a rumble cartridge turns its
+    /// motor on or off.
+    pub fn set_rumble_callback(&mut self, callback: impl FnMut(bool) + 'static) {
+        self.mmu.set_rumble_callback(Box::new(callback));
+    }
 
-        file.read_to_end(&mut buffer).expect("Failed to read file");
+    /// Plugs a device into the link port in place of whatever was there.
+    pub fn connect_serial(&mut self, device: impl SerialDevice + 'static) {
+        self.mmu.connect_serial(Box::new(device));
+    }
 
-        for (i, byte) in buffer.iter().enumerate() {
-            self.memory[i] = *byte;
+    /// Sets how many stereo samples per second the APU produces for the
+    /// host, or 0 for none.
+    pub fn set_sample_rate(&mut self, rate: u32) {
+        self.mmu.apu_mut().set_sample_rate(rate);
+    }
+
+    /// Interleaved left and right samples produced since the last call.
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        self.mmu.apu_mut().take_samples()
+    }
+
+    /// Presses or releases a button. Pressing one may request the joypad
+    /// interrupt and wakes the CPU from STOP.
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        self.mmu.set_button(button, pressed);
+    }
+
+    /// Decodes the instruction at PC, reading only as many operand bytes
+    /// as the opcode asks for.
+    fn fetch(&mut self) -> Instruction {
+        let operands = if self.halt_bug {
+            self.pc
+        } else {
+            self.pc.wrapping_add(1)
+        };
+
+        let mut bytes = [self.read_byte(self.pc), 0, 0];
+        let mut len = 1;
+        let (opcode, size, timing) = loop {
+            match OP::from_bytes(&bytes[..len]) {
+                Ok(decoded) => break decoded,
+                Err(DecodeError::Truncated { .. }) if len < bytes.len() => {
+                    bytes[len] = self.read_byte(operands.wrapping_add(len as u16 - 1));
+                    len += 1;
+                }
+                // Illegal opcodes are executed as a lockup. So is an
+                // instruction the decoder wants more than 3 bytes for, which
+                // would be a decoder bug, so it shows up as a lockup at the
+                // offending opcode rather than a crash.
+                Err(_) => {
+                    break (
+                        OP::Illegal(bytes[0]),
+                        1,
+                        Timing {
+                            cycles: 4,
+                            taken: 4,
+                        },
+                    );
+                }
+            }
+        };
+
+        Instruction {
+            opcode,
+            size: size as u8,
+            timing,
         }
     }
 
-    fn read_byte(&self, address: u16) -> u8 {
-        self.memory[address as usize]
+    /// Advances the rest of the system by one M-cycle.
+    fn idle(&mut self) {
+        self.mmu.tick(4);
+        self.elapsed += 1;
+    }
+
+    // Memory accesses land at the end of their M-cycle
+
+    fn read_byte(&mut self, address: u16) -> u8 {
+        self.idle();
+        self.mmu.read_byte(address)
     }
 
     fn write_byte(&mut self, address: u16, value: u8) {
-        self.memory[address as usize] = value;
+        self.idle();
+        self.mmu.write_byte(address, value);
     }
 
     fn pop_stack(&mut self) -> u16 {
-        let low_byte = self.read_byte(self.sp) as u16;
-        let high_byte = self.read_byte(self.sp + 1) as u16;
-        self.sp += 2;
-        (high_byte << 8) | low_byte
+        let low = self.read_byte(self.sp);
+        let high = self.read_byte(self.sp.wrapping_add(1));
+        self.sp = self.sp.wrapping_add(2);
+        u16::from_le_bytes([low, high])
     }
 
+    // The high byte goes first
     fn push_stack(&mut self, value: u16) {
-        self.sp -= 2;
-        self.write_byte(self.sp, (value & 0x00ff) as u8);
-        self.write_byte(self.sp + 1, ((value & 0xff00) >> 8) as u8);
+        let [low, high] = value.to_le_bytes();
+        self.sp = self.sp.wrapping_sub(1);
+        self.write_byte(self.sp, high);
+        self.sp = self.sp.wrapping_sub(1);
+        self.write_byte(self.sp, low);
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::cartridge::test_rom;
+
+    /// Builds a ROM-only cartridge from `(address, code)` pieces and loads
+    /// it into a CPU in the post-boot state.
+    pub(crate) fn cpu_with(pieces: &[(u16, &[u8])]) -> Cpu {
+        let mut rom = test_rom::with_header(0x00, 0x00, 0x00);
+        for &(address, code) in pieces {
+            let start = address as usize;
+            rom[start..start + code.len()].copy_from_slice(code);
+        }
+        test_rom::fix_checksum(&mut rom);
+
+        let mut cpu = Cpu::new();
+        cpu.mmu
+            .load_cartridge(Cartridge::from_bytes(rom).expect("valid test ROM"));
+        cpu
     }
 
-    fn read_imm16(&mut self) -> u16 {
-        let value = (self.read_byte(self.pc + 2) as u16) << 8 | self.read_byte(self.pc + 1) as u16;
-        value
+    /// Reads memory without ticking the system.
+    pub(crate) fn peek(cpu: &Cpu, address: u16) -> u8 {
+        cpu.mmu.read_byte(address)
     }
 
-    fn read_imm8(&mut self) -> u8 {
-        self.read_byte(self.pc + 1)
+    /// Executes until PC reaches `address`, returning the number of steps.
+    pub(crate) fn run_until(cpu: &mut Cpu, address: u16) -> usize {
+        for steps in 0..200_000 {
+            if cpu.pc == address {
+                return steps;
+            }
+            cpu.execute();
+        }
+        panic!(
+            "PC never reached {:#06x}, stuck at {:#06x}",
+            address, cpu.pc
+        );
     }
 
-    fn write_io(&self, _value: u16, a: u8) -> i32 {
-        match a {
-            _ => panic!("Unknown IO address: {:x}", a),
+    /// Like `run_until`, but gives up after a while rather than after a
+    /// number of steps, for CPUs spinning on a partner in another thread.
+    pub(crate) fn run_linked_until(cpu: &mut Cpu, address: u16) {
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(10);
+        while cpu.pc != address {
+            assert!(
+                std::time::Instant::now() < deadline,
+                "PC never reached {:#06x}, stuck at {:#06x}",
+                address,
+                cpu.pc
+            );
+            cpu.execute();
         }
     }
 
-    fn read_io(&self, _c: u16) -> u8 {
-        0
+    #[test]
+    fn halt_waits_for_interrupt_without_servicing_it() {
+        let mut cpu = cpu_with(&[(
+            0x100,
+            &[
+                0x3E, 0x01, // LD A,$01
+                0xE0, 0xFF, // LDH (IE),A
+                0xAF, // XOR A
+                0xE0, 0x0F, // LDH (IF),A
+                0x76, // HALT
+                0x06, 0x42, // LD B,$42
+                0x18, 0xFE, // JR -2
+            ],
+        )]);
+
+        let steps = run_until(&mut cpu, 0x10A);
+
+        // Halted until VBlank, then carried on with IME off
+        assert!(steps > 1000);
+        assert_eq!(cpu.b, 0x42);
+        assert!(cpu.mmu.read_byte(0xFF44) >= 144);
+        assert_eq!(cpu.mmu.read_byte(0xFF0F) & 0x01, 0x01);
     }
 
-    fn write_mem(&self, _value: u16, _a: u8) -> () {}
+    #[test]
+    fn halt_services_interrupt_with_ime() {
+        let mut cpu = cpu_with(&[
+            (
+                0x40,
+                &[
+                    0x0E, 0x99, // LD C,$99
+                    0xD9, // RETI
+                ],
+            ),
+            (
+                0x100,
+                &[
+                    0x3E, 0x01, // LD A,$01
+                    0xE0, 0xFF, // LDH (IE),A
+                    0xAF, // XOR A
+                    0xE0, 0x0F, // LDH (IF),A
+                    0xFB, // EI
+                    0x76, // HALT
+                    0x06, 0x42, // LD B,$42
+                    0x18, 0xFE, // JR -2
+                ],
+            ),
+        ]);
+
+        run_until(&mut cpu, 0x10B);
 
-    fn read_mem(&self, _value: u16) -> u8 {
-        0
+        assert_eq!(cpu.c, 0x99);
+        assert_eq!(cpu.b, 0x42);
+        assert_eq!(cpu.sp, 0xFFFE);
+        assert_eq!(cpu.mmu.read_byte(0xFF0F) & 0x01, 0x00);
     }
 
-    fn execute_cb(&self, opcode: u8) -> () {
-        match opcode {
-            _ => panic!("Unknown CB opcode: {:x}", opcode),
+    #[test]
+    fn halt_bug_executes_next_byte_twice() {
+        let mut cpu = cpu_with(&[(
+            0x100,
+            &[
+                0x3E, 0x01, // LD A,$01
+                0xE0, 0xFF, // LDH (IE),A
+                0xE0, 0x0F, // LDH (IF),A
+                0x76, // HALT
+                0x03, // INC BC
+                0x18, 0xFE, // JR -2
+            ],
+        )]);
+
+        run_until(&mut cpu, 0x108);
+
+        assert!(!cpu.halted);
+        assert_eq!(cpu.get_reg16(registers::Reg16::BC), 0x0015);
+    }
+
+    #[test]
+    fn stop_freezes_everything_and_resets_div() {
+        let mut cpu = cpu_with(&[(
+            0x100,
+            &[
+                0x10, 0x00, // STOP
+                0x06, 0x42, // LD B,$42
+                0x18, 0xFE, // JR -2
+            ],
+        )]);
+
+        cpu.execute();
+        let ly = cpu.mmu.read_byte(0xFF44);
+        for _ in 0..10_000 {
+            cpu.execute();
+        }
+
+        assert!(cpu.stopped);
+        assert_eq!(cpu.pc, 0x102);
+        assert_eq!(cpu.b, 0x00);
+        assert_eq!(cpu.mmu.read_byte(0xFF44), ly);
+        assert_eq!(cpu.mmu.read_byte(0xFF04), 0x00);
+
+        // Only a line on the selected half of the matrix going low wakes it
+        cpu.mmu.write_byte(0xFF00, 0x20);
+        cpu.set_button(Button::Start, true);
+        cpu.execute();
+        assert!(cpu.stopped);
+        cpu.set_button(Button::Down, true);
+        run_until(&mut cpu, 0x104);
+        assert!(!cpu.stopped);
+        assert_eq!(cpu.b, 0x42);
+    }
+
+    #[test]
+    fn cb_instructions_work_on_registers_and_hl() {
+        let mut cpu = cpu_with(&[(
+            0x100,
+            &[
+                0x26, 0xC0, // LD H,$C0
+                0x2E, 0x00, // LD L,$00
+                0x06, 0x81, // LD B,$81
+                0xCB, 0x00, // RLC B
+                0x3E, 0xF0, // LD A,$F0
+                0x77, // LD (HL),A
+                0xCB, 0x36, // SWAP (HL)
+                0xCB, 0x7E, // BIT 7,(HL)
+                0xCB, 0xFE, // SET 7,(HL)
+                0x18, 0xFE, // JR -2
+            ],
+        )]);
+
+        run_until(&mut cpu, 0x111);
+
+        assert_eq!(cpu.b, 0x03);
+        assert_eq!(cpu.mmu.read_byte(0xC000), 0x8F);
+        assert_eq!(
+            cpu.f,
+            Flags {
+                z: true,
+                n: false,
+                h: true,
+                c: false,
+            }
+        );
+    }
+
+    #[test]
+    fn conditional_branches_cost_more_when_taken() {
+        let mut cpu = cpu_with(&[
+            (
+                0x100,
+                &[
+                    0xAF, // XOR A
+                    0x20, 0x00, // JR NZ,+0
+                    0x28, 0x00, // JR Z,+0
+                    0xC0, // RET NZ
+                    0xCC, 0x20, 0x01, // CALL Z,$0120
+                    0xC2, 0x00, 0x00, // JP NZ,$0000
+                    0xCA, 0x0F, 0x01, // JP Z,$010F
+                    0x18, 0xFE, // JR -2
+                ],
+            ),
+            (
+                0x120,
+                &[
+                    0xC8, // RET Z
+                ],
+            ),
+        ]);
+
+        let cycles: Vec<u32> = (0..8).map(|_| cpu.execute()).collect();
+
+        assert_eq!(cycles, [1, 2, 3, 2, 6, 5, 3, 4]);
+        assert_eq!(cpu.pc, 0x10F);
+    }
+
+    #[test]
+    fn every_instruction_ticks_its_documented_cycles() {
+        let programs = (0..=0xFF_u8)
+            .map(|opcode| [opcode, 0x00, 0xC0])
+            .chain((0..=0xFF_u8).map(|opcode| [0xCB, opcode, 0x00]));
+
+        for program in programs {
+            let (op, _, timing) = match OP::from_bytes(&program) {
+                Ok((OP::Halt | OP::Stop, ..)) | Err(_) => continue,
+                Ok(decoded) => decoded,
+            };
+
+            // Both flag states, so conditional instructions go both ways
+            for flags in [0x00, 0xF0] {
+                let mut cpu = cpu_with(&[(0x100, &program)]);
+                cpu.f = Flags::from_byte(flags);
+                cpu.sp = 0xDFF0;
+                cpu.set_reg16(registers::Reg16::HL, 0xC000);
+
+                let cycles = cpu.execute() as usize * 4;
+                assert!(
+                    cycles == timing.cycles || cycles == timing.taken,
+                    "{:?} took {} cycles",
+                    op,
+                    cycles
+                );
+            }
         }
     }
+
+    #[test]
+    fn illegal_opcode_locks_up_while_the_ppu_keeps_running() {
+        let mut cpu = cpu_with(&[(
+            0x100,
+            &[
+                0x00, // NOP
+                0xD3, // illegal
+                0x06, 0x42, // LD B,$42
+            ],
+        )]);
+        cpu.mmu.write_byte(0xFFFF, 0x01);
+
+        let lockup = Lockup {
+            opcode: 0xD3,
+            address: 0x101,
+        };
+        assert_eq!(cpu.run_frame(), Err(lockup));
+        assert_eq!(cpu.run_frame(), Err(lockup));
+
+        // Time passes and VBlank is raised, but nothing gets executed
+        assert_eq!(cpu.execute(), 1);
+        assert_eq!(cpu.pc, 0x102);
+        assert_eq!(cpu.b, 0x00);
+        assert_eq!(cpu.mmu.read_byte(0xFF0F) & 0x01, 0x01);
+    }
+
+    /// Runs the instruction at 0x100 with an OAM DMA from VRAM that still
+    /// blocks the video bus for the first `blocked` M-cycles. VRAM accesses
+    /// on those cycles are dropped (writes) or see the DMA byte (reads), so
+    /// the result shows which M-cycle each access landed on.
+    fn run_against_dma(program: &[u8], setup: impl Fn(&mut Cpu), blocked: u32) -> Cpu {
+        let mut cpu = cpu_with(&[(0x100, program)]);
+        // LCD off, so only DMA ever blocks VRAM
+        cpu.mmu.write_byte(0xFF40, 0x00);
+        // The byte DMA is moving on the last blocked cycle
+        cpu.mmu.write_byte(0x809E, 0xD0);
+        setup(&mut cpu);
+
+        // The transfer frees the bus 161 M-cycles after the FF46 write
+        cpu.mmu.write_byte(0xFF46, 0x80);
+        cpu.mmu.tick((160 - blocked) * 4);
+        cpu.execute();
+        // Make sure the transfer is over before anything is inspected
+        cpu.mmu.tick(4);
+        cpu
+    }
+
+    // Name, program, register setup, and each write's address, value and
+    // the M-cycle it happens on
+    type WriteCase = (
+        &'static str,
+        &'static [u8],
+        fn(&mut Cpu),
+        &'static [(u16, u8, u32)],
+    );
+
+    #[test]
+    fn writes_land_on_their_documented_m_cycles() {
+        let cases: [WriteCase; 6] = [
+            (
+                "CALL",
+                &[0xCD, 0x00, 0x02],
+                |cpu| cpu.sp = 0x9010,
+                &[(0x900F, 0x01, 5), (0x900E, 0x03, 6)],
+            ),
+            (
+                "PUSH BC",
+                &[0xC5],
+                |cpu| {
+                    cpu.sp = 0x9010;
+                    cpu.set_reg16(registers::Reg16::BC, 0x1234);
+                },
+                &[(0x900F, 0x12, 3), (0x900E, 0x34, 4)],
+            ),
+            (
+                "RST 38",
+                &[0xFF],
+                |cpu| cpu.sp = 0x9010,
+                &[(0x900F, 0x01, 3), (0x900E, 0x01, 4)],
+            ),
+            (
+                "LD (BC),A",
+                &[0x02],
+                |cpu| {
+                    cpu.set_reg16(registers::Reg16::BC, 0x9000);
+                    cpu.a = 0x5A;
+                },
+                &[(0x9000, 0x5A, 2)],
+            ),
+            (
+                "LD (HL),n",
+                &[0x36, 0x66],
+                |cpu| {
+                    cpu.set_reg16(registers::Reg16::HL, 0x9000);
+                },
+                &[(0x9000, 0x66, 3)],
+            ),
+            (
+                "LD (a16),SP",
+                &[0x08, 0x00, 0x90],
+                |cpu| cpu.sp = 0xABCD,
+                &[(0x9000, 0xCD, 4), (0x9001, 0xAB, 5)],
+            ),
+        ];
+
+        for (name, program, setup, writes) in cases {
+            let last = writes.iter().map(|&(.., cycle)| cycle).max().unwrap();
+            for blocked in 0..=last {
+                let cpu = run_against_dma(program, setup, blocked);
+                for &(address, value, cycle) in writes {
+                    let expected = if cycle > blocked { value } else { 0x00 };
+                    assert_eq!(
+                        peek(&cpu, address),
+                        expected,
+                        "{}: write to {:#06x} with {} cycles blocked",
+                        name,
+                        address,
+                        blocked
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn inc_hl_reads_on_m2_and_writes_on_m3() {
+        let setup = |cpu: &mut Cpu| {
+            cpu.set_reg16(registers::Reg16::HL, 0x9000);
+            cpu.mmu.write_byte(0x9000, 0x41);
+        };
+        let results: Vec<u8> = (0..=3)
+            .map(|blocked| peek(&run_against_dma(&[0x34], setup, blocked), 0x9000))
+            .collect();
+
+        // Blocking M2 makes the read see the DMA byte; blocking M3 drops
+        // the write altogether
+        assert_eq!(results, [0x42, 0x42, 0xD1, 0x41]);
+    }
 }
@@ -0,0 +1,97 @@
+// 160 bytes, one per M-cycle
+const TRANSFER_LENGTH: u8 = 0xA0;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Bus {
+    External,
+    Video,
+}
+
+impl Bus {
+    /// The bus an address lives on, or None for memory inside the SoC
+    /// (OAM, IO, HRAM) which DMA never competes for.
+    pub fn of(address: u16) -> Option<Bus> {
+        match address {
+            0x8000..=0x9FFF => Some(Bus::Video),
+            0x0000..=0x7FFF | 0xA000..=0xFDFF => Some(Bus::External),
+            _ => None,
+        }
+    }
+}
+
+pub struct OamDma {
+    // Last value written to FF46
+    register: u8,
+    // Start requested by a write, waiting out the one M-cycle setup
+    pending: Option<u16>,
+    // Source address and OAM index of the transfer in progress
+    transfer: Option<(u16, u8)>,
+    // Byte most recently moved, which is what sits on the source bus
+    current: u8,
+}
+
+impl OamDma {
+    pub fn new() -> OamDma {
+        OamDma {
+            register: 0xFF,
+            pending: None,
+            transfer: None,
+            current: 0xFF,
+        }
+    }
+
+    pub fn read_register(&self) -> u8 {
+        self.register
+    }
+
+    /// Starts a transfer from `value`00-`value`9F. A restart lets the old
+    /// transfer carry on until the new one has finished its setup cycle.
+    pub fn write_register(&mut self, value: u8) {
+        self.register = value;
+        self.pending = Some((value as u16) << 8);
+    }
+
+    pub fn active(&self) -> bool {
+        self.transfer.is_some()
+    }
+
+    /// The bus the transfer is reading from, if one is running.
+    pub fn source_bus(&self) -> Option<Bus> {
+        self.transfer
+            .and_then(|(source, _)| Bus::of(Self::source_address(source)))
+    }
+
+    /// The value a CPU read from a conflicting bus sees.
+    pub fn current_byte(&self) -> u8 {
+        self.current
+    }
+
+    /// DMA reads above DFFF land in echo RAM rather than OAM/IO.
+    fn source_address(address: u16) -> u16 {
+        if address >= 0xE000 {
+            address - 0x2000
+        } else {
+            address
+        }
+    }
+
+    /// Advances one M-cycle. Returns the source address to read and the OAM
+    /// index to store it at when a byte moves during this cycle.
+    pub fn step(&mut self) -> Option<(u16, u8)> {
+        let mut moved = None;
+        if let Some((source, index)) = self.transfer {
+            moved = Some((Self::source_address(source + index as u16), index));
+            self.transfer = (index + 1 < TRANSFER_LENGTH).then_some((source, index + 1));
+        }
+
+        if let Some(source) = self.pending.take() {
+            self.transfer = Some((source, 0));
+        }
+
+        moved
+    }
+
+    pub fn finish_byte(&mut self, value: u8) {
+        self.current = value;
+    }
+}
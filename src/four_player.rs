@@ -0,0 +1,426 @@
+//! The DMG-07 four player adapter. It clocks every transfer, sending the
+//! same position of its current packet to all four Game Boys at once and
+//! reading back what each of them had loaded.
+//!
+//! In the ping phase it repeats `FE STAT STAT STAT`, where STAT holds the
+//! connected players in the high nibble and the receiving player's number in
+//! the low one. Players answer with 0x88 0x88 RATE SIZE, and player 1's
+//! RATE and SIZE set up the link. Player 1 sending 0xAA for a whole ping
+//! packet starts the transmission phase after a packet of 0xCC. From then on
+//! every cycle of 4 * SIZE bytes relays the packets all players sent in the
+//! previous cycle, in player order, while collecting the next ones from
+//! their first SIZE bytes. A packet of nothing but 0xFF from player 1 goes
+//! back to pinging.
+
+use crate::{link::LinkCable, serial::SerialDevice};
+use std::time::Duration;
+
+const PLAYERS: usize = 4;
+const PING_LENGTH: usize = 4;
+
+const PING: u8 = 0xFE;
+const ACK: u8 = 0x88;
+const START: u8 = 0xAA;
+const STARTING: u8 = 0xCC;
+const RESTART: u8 = 0xFF;
+
+// How long the adapter waits on a remote player before reading 0xFF, so a
+// Game Boy that hasn't armed its side only slows the others down
+const REPLY_TIMEOUT: Duration = Duration::from_millis(50);
+
+// Packet sizes the adapter supports
+const MIN_SIZE: u8 = 1;
+const MAX_SIZE: u8 = 4;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Phase {
+    Ping,
+    Starting,
+    Transmission,
+}
+
+pub struct FourPlayerAdapter {
+    ports: [Option<Box<dyn SerialDevice>>; PLAYERS],
+    phase: Phase,
+    // Position within the current packet or cycle
+    position: usize,
+    // One bit per player, player 1 in bit 0
+    connected: u8,
+    // Player 1's answers to the current ping packet
+    host_replies: [u8; PING_LENGTH],
+    size: usize,
+    // Packets being collected this cycle and those being relayed
+    incoming: Vec<u8>,
+    relay: Vec<u8>,
+}
+
+impl FourPlayerAdapter {
+    pub fn new() -> FourPlayerAdapter {
+        FourPlayerAdapter {
+            ports: Default::default(),
+            phase: Phase::Ping,
+            position: 0,
+            connected: 0,
+            host_replies: [0; PING_LENGTH],
+            size: MIN_SIZE as usize,
+            incoming: Vec::new(),
+            relay: Vec::new(),
+        }
+    }
+
+    /// Plugs a Game Boy into the first free port, returning its player
+    /// number or `None` when all four are taken. The Game Boy's end has to
+    /// wait on the external clock, since the adapter drives every transfer.
+    pub fn connect(&mut self, device: impl SerialDevice + 'static) -> Option<usize> {
+        let free = self.ports.iter().position(Option::is_none)?;
+        self.ports[free] = Some(Box::new(device));
+        Some(free + 1)
+    }
+
+    /// Plugs in a Game Boy on the far side of a link cable, which is only
+    /// waited on briefly for each byte.
+    pub fn connect_cable(&mut self, mut cable: LinkCable) -> Option<usize> {
+        cable.set_reply_timeout(REPLY_TIMEOUT);
+        self.connect(cable)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ports.iter().all(Option::is_none)
+    }
+
+    /// Clocks one byte to and from every player, returning the players
+    /// whose cables were unplugged along the way. Their ports are freed for
+    /// someone else, and once every port is empty the adapter goes back to
+    /// pinging.
+    pub fn step(&mut self) -> Vec<usize> {
+        let mut replies = [0xFF; PLAYERS];
+        let mut unplugged = Vec::new();
+        for (player, slot) in self.ports.iter_mut().enumerate() {
+            let Some(port) = slot else {
+                continue;
+            };
+            let outgoing = match self.phase {
+                Phase::Ping if self.position == 0 => PING,
+                Phase::Ping => self.connected << 4 | (player as u8 + 1),
+                Phase::Starting => STARTING,
+                Phase::Transmission => self.relay[self.position],
+            };
+            replies[player] = port.exchange(outgoing);
+
+            if !port.connected() {
+                *slot = None;
+                self.connected &= !(1 << player);
+                unplugged.push(player + 1);
+            }
+        }
+
+        if self.is_empty() {
+            *self = FourPlayerAdapter::new();
+            return unplugged;
+        }
+
+        match self.phase {
+            Phase::Ping => self.ping(replies),
+            Phase::Starting => {
+                self.position += 1;
+                if self.position == PING_LENGTH {
+                    self.phase = Phase::Transmission;
+                    self.position = 0;
+                    self.incoming = vec![0; PLAYERS * self.size];
+                    self.relay = vec![0; PLAYERS * self.size];
+                }
+            }
+            Phase::Transmission => self.transmit(replies),
+        }
+        unplugged
+    }
+
+    fn ping(&mut self, replies: [u8; PLAYERS]) {
+        if self.position == 0 {
+            // Only players answering the header count as connected, player 1
+            // included while it asks to start
+            self.connected = 0;
+            for (player, &reply) in replies.iter().enumerate() {
+                if reply == ACK || (player == 0 && reply == START) {
+                    self.connected |= 1 << player;
+                }
+            }
+        }
+        self.host_replies[self.position] = replies[0];
+
+        self.position += 1;
+        if self.position < PING_LENGTH {
+            return;
+        }
+        self.position = 0;
+
+        if self.host_replies == [START; PING_LENGTH] {
+            self.phase = Phase::Starting;
+        } else {
+            // RATE only sets how fast the adapter clocks, and here the
+            // players' own pace sets that
+            self.size = self.host_replies[3].clamp(MIN_SIZE, MAX_SIZE) as usize;
+        }
+    }
+
+    fn transmit(&mut self, replies: [u8; PLAYERS]) {
+        if self.position < self.size {
+            for (player, &reply) in replies.iter().enumerate() {
+                // Missing players leave their slot empty
+                if self.connected & 1 << player != 0 {
+                    self.incoming[player * self.size + self.position] = reply;
+                }
+            }
+        }
+
+        self.position += 1;
+        if self.position < self.relay.len() {
+            return;
+        }
+        self.position = 0;
+
+        if self.incoming[..self.size].iter().all(|&b| b == RESTART) {
+            self.phase = Phase::Ping;
+            return;
+        }
+        self.relay = std::mem::replace(&mut self.incoming, vec![0; PLAYERS * self.size]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::tests::{cpu_with, peek, run_linked_until};
+    use std::{
+        cell::RefCell,
+        net::{TcpListener, TcpStream},
+        rc::Rc,
+        thread,
+        time::Instant,
+    };
+
+    /// Answers with a fixed script and records what it received.
+    struct Script {
+        replies: Vec<u8>,
+        received: Rc<RefCell<Vec<u8>>>,
+    }
+
+    impl SerialDevice for Script {
+        fn exchange(&mut self, outgoing: u8) -> u8 {
+            let mut received = self.received.borrow_mut();
+            let reply = self.replies.get(received.len()).copied().unwrap_or(0x00);
+            received.push(outgoing);
+            reply
+        }
+    }
+
+    #[test]
+    fn ping_reports_connected_players_and_ids() {
+        let mut adapter = FourPlayerAdapter::new();
+        let mut received = Vec::new();
+        // Player 2 is plugged in but not running a four player game
+        for replies in [[ACK, ACK, 0, 1], [0; 4], [ACK, ACK, 0, 1]] {
+            let log = Rc::default();
+            adapter.connect(Script {
+                replies: replies.repeat(2),
+                received: Rc::clone(&log),
+            });
+            received.push(log);
+        }
+        for _ in 0..8 {
+            adapter.step();
+        }
+
+        let ping = |id: u8| [PING, 0x50 | id, 0x50 | id, 0x50 | id].repeat(2);
+        assert_eq!(*received[0].borrow(), ping(1));
+        assert_eq!(*received[1].borrow(), ping(2));
+        assert_eq!(*received[2].borrow(), ping(3));
+    }
+
+    /// Runs four emulated Game Boys through a whole session on the given
+    /// (adapter end, player end) cables.
+    fn relay_packets(cables: Vec<(LinkCable, LinkCable)>) {
+        // Sends each byte of the table at $0150 on the external clock and
+        // stores every byte received from $C000 up
+        fn program(length: u8) -> Vec<u8> {
+            vec![
+                0x21, 0x00, 0xC0, // LD HL,$C000
+                0x11, 0x50, 0x01, // LD DE,$0150
+                0x06, length, // LD B,length
+                0x1A,   // LD A,(DE)
+                0xE0, 0x01, // LDH (SB),A
+                0x3E, 0x80, // LD A,$80
+                0xE0, 0x02, // LDH (SC),A
+                0xF0, 0x02, // LDH A,(SC)
+                0xCB, 0x7F, // BIT 7,A
+                0x20, 0xFA, // JR NZ,-6
+                0xF0, 0x01, // LDH A,(SB)
+                0x22, // LD (HL+),A
+                0x13, // INC DE
+                0x05, // DEC B
+                0x20, 0xEC, // JR NZ,-20
+                0x18, 0xFE, // JR -2
+            ]
+        }
+        const DONE: u16 = 0x11C;
+
+        // Two pings, player 1 asking to start, the 0xCC packet, two cycles
+        // of two byte packets, player 1 asking to restart, then one byte of
+        // the next ping
+        let script = |player: u8| {
+            let mut bytes = [ACK, ACK, 0x00, 0x02].repeat(2);
+            if player == 1 {
+                bytes.extend([START; 4]);
+            } else {
+                bytes.extend([ACK, ACK, 0x00, 0x02]);
+            }
+            bytes.extend([0x00; 4]);
+            for round in [1, 2] {
+                bytes.extend([player << 4 | round, (player << 4) | (round + 2)]);
+                bytes.extend([0x00; 6]);
+            }
+            if player == 1 {
+                bytes.extend([RESTART, RESTART]);
+            } else {
+                bytes.extend([0x00; 2]);
+            }
+            bytes.extend([0x00; 6]);
+            bytes.push(ACK);
+            bytes
+        };
+
+        let mut adapter_ends = Vec::new();
+        let mut players = Vec::new();
+        for (player, (adapter_end, player_end)) in (1..=4).zip(cables) {
+            adapter_ends.push(adapter_end);
+            let table = script(player);
+            players.push(thread::spawn(move || {
+                let length = table.len();
+                let mut cpu = cpu_with(&[(0x100, &program(length as u8)), (0x150, &table)]);
+                cpu.connect_serial(player_end);
+                run_linked_until(&mut cpu, DONE);
+                (0..length as u16)
+                    .map(|i| peek(&cpu, 0xC000 + i))
+                    .collect::<Vec<u8>>()
+            }));
+        }
+
+        let length = script(1).len();
+        let adapter = thread::spawn(move || {
+            let mut adapter = FourPlayerAdapter::new();
+            for cable in adapter_ends {
+                adapter.connect_cable(cable);
+            }
+            for _ in 0..length {
+                adapter.step();
+            }
+        });
+
+        let received: Vec<Vec<u8>> = players.into_iter().map(|p| p.join().unwrap()).collect();
+        adapter.join().unwrap();
+
+        for (player, received) in (1..=4).zip(&received) {
+            let stat = 0xF0 | player;
+            let mut expected = [PING, stat, stat, stat].repeat(3);
+            expected.extend([STARTING; 4]);
+            // Nothing to relay in the first cycle
+            expected.extend([0x00; 8]);
+            expected.extend([0x11, 0x13, 0x21, 0x23, 0x31, 0x33, 0x41, 0x43]);
+            expected.extend([0x12, 0x14, 0x22, 0x24, 0x32, 0x34, 0x42, 0x44]);
+            expected.push(PING);
+            assert_eq!(received, &expected, "player {}", player);
+        }
+    }
+
+    #[test]
+    fn four_linked_instances_relay_packets_over_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let cables = (0..PLAYERS)
+            .map(|_| {
+                let player_end = TcpStream::connect(address).unwrap();
+                let (adapter_end, _) = listener.accept().unwrap();
+                (
+                    LinkCable::new(adapter_end).unwrap(),
+                    LinkCable::new(player_end).unwrap(),
+                )
+            })
+            .collect();
+        relay_packets(cables);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn four_linked_instances_relay_packets_over_unix_sockets() {
+        use std::os::unix::net::UnixStream;
+
+        let cables = (0..PLAYERS)
+            .map(|_| {
+                let (adapter_end, player_end) = UnixStream::pair().unwrap();
+                (
+                    LinkCable::new(adapter_end).unwrap(),
+                    LinkCable::new(player_end).unwrap(),
+                )
+            })
+            .collect();
+        relay_packets(cables);
+    }
+
+    #[test]
+    fn closed_cables_free_their_port() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let mut adapter = FourPlayerAdapter::new();
+        let log = Rc::new(RefCell::new(Vec::new()));
+        adapter.connect(Script {
+            replies: vec![ACK; 2],
+            received: Rc::clone(&log),
+        });
+
+        let player_end = TcpStream::connect(address).unwrap();
+        let (adapter_end, _) = listener.accept().unwrap();
+        assert_eq!(
+            adapter.connect_cable(LinkCable::new(adapter_end).unwrap()),
+            Some(2)
+        );
+        drop(player_end);
+
+        assert_eq!(adapter.step(), [2]);
+        assert_eq!(adapter.step(), []);
+        // Player 1 now sees itself alone on the adapter
+        assert_eq!(log.borrow()[1], 0x11);
+
+        let replacement = Script {
+            replies: Vec::new(),
+            received: Rc::default(),
+        };
+        assert_eq!(adapter.connect(replacement), Some(2));
+    }
+
+    #[test]
+    fn unarmed_players_do_not_stall_the_others() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let mut adapter = FourPlayerAdapter::new();
+
+        // Player 1 stays connected, but its game never arms a transfer
+        let player_end = LinkCable::new(TcpStream::connect(address).unwrap()).unwrap();
+        let (adapter_end, _) = listener.accept().unwrap();
+        adapter.connect_cable(LinkCable::new(adapter_end).unwrap());
+
+        let log = Rc::new(RefCell::new(Vec::new()));
+        adapter.connect(Script {
+            replies: [ACK, ACK, 0, 1].repeat(2),
+            received: Rc::clone(&log),
+        });
+
+        let start = Instant::now();
+        for _ in 0..8 {
+            assert_eq!(adapter.step(), []);
+        }
+        assert!(start.elapsed() < REPLY_TIMEOUT * 20);
+        // Player 1 reads as absent, player 2 as alone
+        assert_eq!(*log.borrow(), [PING, 0x22, 0x22, 0x22].repeat(2));
+        drop(player_end);
+    }
+}
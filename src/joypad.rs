@@ -0,0 +1,115 @@
+// P1 select bits; a 0 connects that half of the matrix to the input lines
+const SELECT_DIRECTIONS: u8 = 0x10;
+const SELECT_ACTIONS: u8 = 0x20;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Button {
+    Right,
+    Left,
+    Up,
+    Down,
+    A,
+    B,
+    Select,
+    Start,
+}
+
+impl Button {
+    // Low nibble: directions, high nibble: actions, both in P1 line order
+    fn mask(self) -> u8 {
+        match self {
+            Button::Right => 0x01,
+            Button::Left => 0x02,
+            Button::Up => 0x04,
+            Button::Down => 0x08,
+            Button::A => 0x10,
+            Button::B => 0x20,
+            Button::Select => 0x40,
+            Button::Start => 0x80,
+        }
+    }
+}
+
+pub struct Joypad {
+    // Bits 4-5 of P1 as last written
+    select: u8,
+    // Held buttons, laid out as in Button::mask
+    pressed: u8,
+}
+
+impl Joypad {
+    pub fn new() -> Joypad {
+        Joypad {
+            select: SELECT_DIRECTIONS | SELECT_ACTIONS,
+            pressed: 0,
+        }
+    }
+
+    /// The four input lines, active low. Selecting both halves ANDs them.
+    fn lines(&self) -> u8 {
+        let mut low = 0;
+        if self.select & SELECT_DIRECTIONS == 0 {
+            low |= self.pressed & 0x0F;
+        }
+        if self.select & SELECT_ACTIONS == 0 {
+            low |= self.pressed >> 4;
+        }
+        !low & 0x0F
+    }
+
+    pub fn read_register(&self) -> u8 {
+        0xC0 | self.select | self.lines()
+    }
+
+    /// Returns whether the write pulled an input line low, which requests
+    /// the joypad interrupt.
+    pub fn write_register(&mut self, value: u8) -> bool {
+        let before = self.lines();
+        self.select = value & (SELECT_DIRECTIONS | SELECT_ACTIONS);
+        before & !self.lines() != 0
+    }
+
+    /// Returns whether an input line went from high to low.
+    pub fn set_button(&mut self, button: Button, pressed: bool) -> bool {
+        let before = self.lines();
+        if pressed {
+            self.pressed |= button.mask();
+        } else {
+            self.pressed &= !button.mask();
+        }
+        before & !self.lines() != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_selected_half_active_low() {
+        let mut joypad = Joypad::new();
+        joypad.set_button(Button::Down, true);
+        joypad.set_button(Button::A, true);
+
+        joypad.write_register(0x20);
+        assert_eq!(joypad.read_register(), 0xE7);
+        joypad.write_register(0x10);
+        assert_eq!(joypad.read_register(), 0xDE);
+        joypad.write_register(0x30);
+        assert_eq!(joypad.read_register(), 0xFF);
+    }
+
+    #[test]
+    fn interrupts_only_on_high_to_low() {
+        let mut joypad = Joypad::new();
+        joypad.write_register(0x10);
+
+        assert!(joypad.set_button(Button::Start, true));
+        assert!(!joypad.set_button(Button::Start, true));
+        assert!(!joypad.set_button(Button::Start, false));
+        // Unselected half
+        assert!(!joypad.set_button(Button::Up, true));
+        // Selecting the directions exposes the held Up
+        assert!(joypad.write_register(0x00));
+    }
+}
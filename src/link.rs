@@ -0,0 +1,443 @@
+//! A link cable between two emulators over a localhost socket.
+//!
+//! Whichever side selects the internal clock is the master: at the end of its
+//! transfer it sends its byte across and blocks until the other side answers.
+//! The slave only answers once its game has armed a transfer on the external
+//! clock, so every master transfer pairs with exactly one slave transfer no
+//! matter how the two hosts are scheduled.
+//!
+//! Every message carries the sequence number of the master transfer it
+//! belongs to. A master only waits so long for its reply, after which it
+//! reads the line as unconnected and cancels the transfer; the slave then
+//! drops that clock instead of answering it, and the master drops any reply
+//! that was already on its way.
+
+use crate::serial::SerialDevice;
+use std::{
+    io::{self, Read, Write},
+    net::{Shutdown, TcpListener, TcpStream},
+    sync::mpsc::{self, Receiver, RecvTimeoutError, TryRecvError},
+    thread,
+    time::Duration,
+};
+
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+
+// Message kinds, each followed by a sequence number and one data byte
+const CLOCK: u8 = 0x01;
+const REPLY: u8 = 0x02;
+const CANCEL: u8 = 0x03;
+
+// How long a master waits for its reply unless told otherwise. Long enough
+// for the other game to get round to arming its side, short enough that a
+// partner sitting in a menu only stalls a frame or so rather than freezing
+// this emulator and its window.
+const DEFAULT_REPLY_TIMEOUT: Duration = Duration::from_millis(500);
+
+// Addresses with this prefix name a Unix socket path
+const UNIX_PREFIX: &str = "unix:";
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Message {
+    /// The master clocked out a byte.
+    Clock { sequence: u8, value: u8 },
+    /// The slave's byte, shifted back in answer to a clock.
+    Reply { sequence: u8, value: u8 },
+    /// The master timed out and no longer wants an answer.
+    Cancel { sequence: u8 },
+}
+
+/// A connected stream the cable can run over.
+pub trait Socket: Read + Write + Send + 'static {
+    fn try_clone(&self) -> io::Result<Self>
+    where
+        Self: Sized;
+
+    fn shutdown(&self) -> io::Result<()>;
+}
+
+impl Socket for TcpStream {
+    fn try_clone(&self) -> io::Result<TcpStream> {
+        TcpStream::try_clone(self)
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        TcpStream::shutdown(self, Shutdown::Both)
+    }
+}
+
+#[cfg(unix)]
+impl Socket for UnixStream {
+    fn try_clone(&self) -> io::Result<UnixStream> {
+        UnixStream::try_clone(self)
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        UnixStream::shutdown(self, Shutdown::Both)
+    }
+}
+
+pub struct LinkCable {
+    socket: Box<dyn Socket>,
+    // Filled by a reader thread so the slave can poll without blocking
+    incoming: Receiver<Message>,
+    // Sequence number of the latest transfer this side clocked
+    sequence: u8,
+    // How long a master waits for the slave to arm its side
+    reply_timeout: Duration,
+    // Set once the other side has hung up
+    closed: bool,
+}
+
+impl LinkCable {
+    pub fn new<S: Socket>(socket: S) -> io::Result<LinkCable> {
+        let mut reader = socket.try_clone()?;
+        let (sender, incoming) = mpsc::channel();
+
+        thread::spawn(move || {
+            let mut message = [0; 3];
+            while reader.read_exact(&mut message).is_ok() {
+                let message = match message {
+                    [CLOCK, sequence, value] => Message::Clock { sequence, value },
+                    [REPLY, sequence, value] => Message::Reply { sequence, value },
+                    [CANCEL, sequence, _] => Message::Cancel { sequence },
+                    _ => break,
+                };
+                if sender.send(message).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(LinkCable {
+            socket: Box::new(socket),
+            incoming,
+            sequence: 0,
+            reply_timeout: DEFAULT_REPLY_TIMEOUT,
+            closed: false,
+        })
+    }
+
+    /// Sets how long a master waits for a slave to arm its side before it
+    /// gives up and reads the line as unconnected.
+    pub fn set_reply_timeout(&mut self, timeout: Duration) {
+        self.reply_timeout = timeout;
+    }
+
+    /// Waits for the other emulator to connect to `address`, either a TCP
+    /// `host:port` or `unix:<path>`.
+    pub fn listen(address: &str) -> io::Result<LinkCable> {
+        if let Some(path) = address.strip_prefix(UNIX_PREFIX) {
+            return listen_unix(path);
+        }
+        let (stream, _) = TcpListener::bind(address)?.accept()?;
+        stream.set_nodelay(true)?;
+        LinkCable::new(stream)
+    }
+
+    /// Connects to an emulator listening on `address`.
+    pub fn connect(address: &str) -> io::Result<LinkCable> {
+        if let Some(path) = address.strip_prefix(UNIX_PREFIX) {
+            return connect_unix(path);
+        }
+        let stream = TcpStream::connect(address)?;
+        stream.set_nodelay(true)?;
+        LinkCable::new(stream)
+    }
+
+    fn send(&mut self, message: Message) -> io::Result<()> {
+        let bytes = match message {
+            Message::Clock { sequence, value } => [CLOCK, sequence, value],
+            Message::Reply { sequence, value } => [REPLY, sequence, value],
+            Message::Cancel { sequence } => [CANCEL, sequence, 0x00],
+        };
+        let sent = self.socket.write_all(&bytes);
+        if sent.is_err() {
+            self.closed = true;
+        }
+        sent
+    }
+}
+
+/// Accepts link cables without blocking, for hosts that take several.
+pub enum LinkListener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+impl LinkListener {
+    /// Listens on a TCP `host:port` or `unix:<path>`.
+    pub fn bind(address: &str) -> io::Result<LinkListener> {
+        let listener = match address.strip_prefix(UNIX_PREFIX) {
+            #[cfg(unix)]
+            Some(path) => LinkListener::Unix(UnixListener::bind(path)?),
+            #[cfg(not(unix))]
+            Some(_) => return Err(io::ErrorKind::Unsupported.into()),
+            None => LinkListener::Tcp(TcpListener::bind(address)?),
+        };
+        match &listener {
+            LinkListener::Tcp(tcp) => tcp.set_nonblocking(true)?,
+            #[cfg(unix)]
+            LinkListener::Unix(unix) => unix.set_nonblocking(true)?,
+        }
+        Ok(listener)
+    }
+
+    /// Returns the next waiting connection, if there is one.
+    pub fn accept(&self) -> io::Result<Option<LinkCable>> {
+        let accepted = match self {
+            LinkListener::Tcp(tcp) => tcp.accept().and_then(|(stream, _)| {
+                // Some platforms hand out sockets as non-blocking as the listener
+                stream.set_nonblocking(false)?;
+                stream.set_nodelay(true)?;
+                LinkCable::new(stream)
+            }),
+            #[cfg(unix)]
+            LinkListener::Unix(unix) => unix.accept().and_then(|(stream, _)| {
+                stream.set_nonblocking(false)?;
+                LinkCable::new(stream)
+            }),
+        };
+        match accepted {
+            Ok(cable) => Ok(Some(cable)),
+            Err(error) if error.kind() == io::ErrorKind::WouldBlock => Ok(None),
+            Err(error) => Err(error),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn listen_unix(path: &str) -> io::Result<LinkCable> {
+    let (stream, _) = UnixListener::bind(path)?.accept()?;
+    LinkCable::new(stream)
+}
+
+#[cfg(unix)]
+fn connect_unix(path: &str) -> io::Result<LinkCable> {
+    LinkCable::new(UnixStream::connect(path)?)
+}
+
+#[cfg(not(unix))]
+fn listen_unix(_path: &str) -> io::Result<LinkCable> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+#[cfg(not(unix))]
+fn connect_unix(_path: &str) -> io::Result<LinkCable> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+impl SerialDevice for LinkCable {
+    fn exchange(&mut self, outgoing: u8) -> u8 {
+        self.sequence = self.sequence.wrapping_add(1);
+        let sequence = self.sequence;
+        let clock = Message::Clock {
+            sequence,
+            value: outgoing,
+        };
+        if self.send(clock).is_err() {
+            return 0xFF;
+        }
+
+        loop {
+            match self.incoming.recv_timeout(self.reply_timeout) {
+                Ok(Message::Reply { sequence: s, value }) if s == sequence => return value,
+                // Late answer to a transfer we already gave up on
+                Ok(Message::Reply { .. } | Message::Cancel { .. }) => {}
+                // Both sides are driving the clock, so neither hears anything
+                Ok(Message::Clock { sequence, .. }) => {
+                    let reply = Message::Reply {
+                        sequence,
+                        value: 0xFF,
+                    };
+                    if self.send(reply).is_err() {
+                        return 0xFF;
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    let _ = self.send(Message::Cancel { sequence });
+                    return 0xFF;
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    self.closed = true;
+                    return 0xFF;
+                }
+            }
+        }
+    }
+
+    fn external_clock(&mut self, outgoing: u8) -> Option<u8> {
+        // Read everything that has arrived, so a clock the master has since
+        // cancelled is dropped rather than answered
+        let mut clock = None;
+        loop {
+            match self.incoming.try_recv() {
+                Ok(Message::Clock { sequence, value }) => clock = Some((sequence, value)),
+                Ok(Message::Cancel { sequence }) => {
+                    if clock.map_or(false, |(pending, _)| pending == sequence) {
+                        clock = None;
+                    }
+                }
+                // Late answer to a transfer we already gave up on
+                Ok(Message::Reply { .. }) => {}
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.closed = true;
+                    break;
+                }
+            }
+        }
+
+        let (sequence, incoming) = clock?;
+        // A lost reply leaves the master to time out
+        let _ = self.send(Message::Reply {
+            sequence,
+            value: outgoing,
+        });
+        Some(incoming)
+    }
+
+    fn connected(&self) -> bool {
+        !self.closed
+    }
+}
+
+impl Drop for LinkCable {
+    fn drop(&mut self) {
+        // Unblocks the reader thread
+        let _ = self.socket.shutdown();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::tests::{cpu_with, peek, run_linked_until};
+
+    // Sends the byte in `sb` with the clock selected by `sc`, waits for the
+    // transfer to finish and stores what came back at $C000
+    fn program(sb: u8, sc: u8) -> Vec<u8> {
+        vec![
+            0x3E, sb, // LD A,sb
+            0xE0, 0x01, // LDH (SB),A
+            0x3E, sc, // LD A,sc
+            0xE0, 0x02, // LDH (SC),A
+            0xF0, 0x02, // LDH A,(SC)
+            0xCB, 0x7F, // BIT 7,A
+            0x20, 0xFA, // JR NZ,-6
+            0xF0, 0x01, // LDH A,(SB)
+            0xEA, 0x00, 0xC0, // LD ($C000),A
+            0x18, 0xFE, // JR -2
+        ]
+    }
+
+    const DONE: u16 = 0x113;
+
+    fn run(cable: LinkCable, sb: u8, sc: u8) -> thread::JoinHandle<u8> {
+        // Each emulator lives on its own thread like it would in its own process
+        thread::spawn(move || {
+            let mut cpu = cpu_with(&[(0x100, &program(sb, sc))]);
+            cpu.connect_serial(cable);
+            run_linked_until(&mut cpu, DONE);
+            peek(&cpu, 0xC000)
+        })
+    }
+
+    fn tcp_pair() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        (client, server)
+    }
+
+    #[test]
+    fn linked_instances_swap_bytes_over_tcp() {
+        let (client, server) = tcp_pair();
+
+        // Start the slave a little late so the master has to wait for it
+        let master = run(LinkCable::new(client).unwrap(), 0x42, 0x81);
+        thread::sleep(Duration::from_millis(20));
+        let slave = run(LinkCable::new(server).unwrap(), 0x99, 0x80);
+
+        assert_eq!(master.join().unwrap(), 0x99);
+        assert_eq!(slave.join().unwrap(), 0x42);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn linked_instances_swap_bytes_over_unix_socket() {
+        let (a, b) = UnixStream::pair().unwrap();
+        let slave = run(LinkCable::new(a).unwrap(), 0x5A, 0x80);
+        let master = run(LinkCable::new(b).unwrap(), 0xC3, 0x81);
+
+        assert_eq!(master.join().unwrap(), 0x5A);
+        assert_eq!(slave.join().unwrap(), 0xC3);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn master_reads_ff_when_nobody_answers() {
+        let (a, b) = UnixStream::pair().unwrap();
+        let mut cable = LinkCable::new(a).unwrap();
+        drop(b);
+        assert_eq!(cable.exchange(0x12), 0xFF);
+        assert!(!cable.connected());
+    }
+
+    #[test]
+    fn master_with_a_silent_partner_reads_ff_and_finishes() {
+        let (a, b) = tcp_pair();
+        // Stays connected, but its game never arms a transfer
+        let _partner = LinkCable::new(b).unwrap();
+
+        // Only the default timeout stands between the master and a hang
+        let master = run(LinkCable::new(a).unwrap(), 0x42, 0x81);
+        assert_eq!(master.join().unwrap(), 0xFF);
+    }
+
+    #[test]
+    fn slave_arming_after_a_timeout_waits_for_the_next_clock() {
+        let (a, b) = tcp_pair();
+        let mut master = LinkCable::new(a).unwrap();
+        master.set_reply_timeout(Duration::from_millis(20));
+        let mut slave = LinkCable::new(b).unwrap();
+
+        assert_eq!(master.exchange(0x11), 0xFF);
+        // Give the clock and its cancellation time to arrive
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(slave.external_clock(0x22), None);
+
+        let master = thread::spawn(move || master.exchange(0x33));
+        let incoming = loop {
+            if let Some(incoming) = slave.external_clock(0x44) {
+                break incoming;
+            }
+            thread::yield_now();
+        };
+        assert_eq!(incoming, 0x33);
+        assert_eq!(master.join().unwrap(), 0x44);
+    }
+
+    #[test]
+    fn master_ignores_replies_to_other_transfers() {
+        let (a, mut b) = tcp_pair();
+        let mut master = LinkCable::new(a).unwrap();
+
+        let peer = thread::spawn(move || {
+            let mut clock = [0; 3];
+            b.read_exact(&mut clock).unwrap();
+            let [CLOCK, sequence, 0x55] = clock else {
+                panic!("expected a clock, got {:?}", clock);
+            };
+            b.write_all(&[REPLY, sequence.wrapping_sub(1), 0x01])
+                .unwrap();
+            b.write_all(&[REPLY, sequence, 0x02]).unwrap();
+            b
+        });
+
+        assert_eq!(master.exchange(0x55), 0x02);
+        peer.join().unwrap();
+    }
+}
@@ -1,16 +1,199 @@
-use crate::cpu::Cpu;
+use crate::{
+    apu::encode_wav,
+    cpu::Cpu,
+    four_player::FourPlayerAdapter,
+    joypad::Button,
+    link::{LinkCable, LinkListener},
+    mbc::RtcClock,
+    ppu::{SCREEN_HEIGHT, SCREEN_WIDTH},
+    printer::Printer,
+    serial::SerialSink,
+};
+use minifb::{Key, Scale, Window, WindowOptions};
+use std::{fs, path::Path, thread, time::Duration};
 
+mod alu;
+mod apu;
+mod cartridge;
 mod cpu;
+mod dma;
+mod four_player;
+mod interrupts;
+mod joypad;
+mod link;
+mod mbc;
+mod mmu;
 mod opcodes;
+mod ppu;
+mod printer;
 mod registers;
+mod serial;
+mod timer;
+
+// 70224 dots at 4.194304 MHz
+const FRAME_DURATION: Duration = Duration::from_micros(16_742);
+
+const KEYMAP: [(Key, Button); 8] = [
+    (Key::Right, Button::Right),
+    (Key::Left, Button::Left),
+    (Key::Up, Button::Up),
+    (Key::Down, Button::Down),
+    (Key::Z, Button::A),
+    (Key::X, Button::B),
+    (Key::Backspace, Button::Select),
+    (Key::Enter, Button::Start),
+];
 
 fn main() {
     let mut cpu = Cpu::new();
+    let mut rom = String::from("roms/04-op r,imm.gb");
+    let mut link = None;
+    let mut link_timeout = None;
+    let mut print_dir = None;
+    let mut wav_path = None;
+    let mut sample_rate = 44_100;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            // Drive the cartridge clock from emulated cycles so runs are reproducible
+            "--rtc-cycles" => cpu.set_rtc_clock(RtcClock::Cycles),
+            // Link to another instance at host:port or unix:<path>
+            "--link-listen" | "--link-connect" => {
+                link = Some((arg, args.next().expect("Missing link address")));
+            }
+            // Read the link as unconnected when the other side takes longer
+            // than this many milliseconds to answer, instead of the default
+            "--link-timeout" => {
+                link_timeout = args
+                    .next()
+                    .and_then(|ms| ms.parse().ok())
+                    .map(Duration::from_millis);
+                assert!(link_timeout.is_some(), "Missing or invalid link timeout");
+            }
+            // Run a four player adapter for instances linking to this address
+            "--four-player" => {
+                run_four_player_adapter(&args.next().expect("Missing adapter address"));
+                return;
+            }
+            // Record the audio to a WAV file when the window closes
+            "--wav" => wav_path = Some(args.next().expect("Missing WAV path")),
+            "--sample-rate" => {
+                sample_rate = args
+                    .next()
+                    .and_then(|rate| rate.parse().ok())
+                    .expect("Missing or invalid sample rate")
+            }
+            // Plug in a printer and save its pages into a directory
+            "--printer" => print_dir = Some(args.next().expect("Missing printer directory")),
+            _ => rom = arg,
+        }
+    }
+
+    cpu.set_rumble_callback(|on| println!("Rumble {}", if on { "on" } else { "off" }));
 
-    cpu.load_rom("roms/04-op r,imm.gb");
+    // Test ROMs report their results over the link port
+    let serial = SerialSink::new();
+    let printer = Printer::new();
+    match (link, &print_dir) {
+        // There is only one link port to plug either into
+        (Some(_), Some(_)) => panic!("--printer can't be combined with a link cable"),
+        (Some((arg, address)), None) => {
+            println!("Linking over {}", address);
+            let mut link = if arg == "--link-listen" {
+                LinkCable::listen(&address)
+            } else {
+                LinkCable::connect(&address)
+            }
+            .expect("Failed to link");
+            if let Some(timeout) = link_timeout {
+                link.set_reply_timeout(timeout);
+            }
+            cpu.connect_serial(link)
+        }
+        (None, Some(_)) => cpu.connect_serial(printer.clone()),
+        (None, None) => cpu.connect_serial(serial.clone()),
+    }
+    let mut pages_printed = 0;
+
+    // There is no audio output yet, so only produce samples to record them
+    let mut audio = Vec::new();
+    if wav_path.is_some() {
+        cpu.set_sample_rate(sample_rate);
+    }
+
+    cpu.load_rom(&rom).expect("Failed to load ROM");
     println!("Loaded ROM");
 
+    let mut window = Window::new(
+        "gamenya",
+        SCREEN_WIDTH,
+        SCREEN_HEIGHT,
+        WindowOptions {
+            scale: Scale::X4,
+            ..WindowOptions::default()
+        },
+    )
+    .expect("Failed to open window");
+    window.limit_update_rate(Some(FRAME_DURATION));
+
+    let mut locked_up = false;
+
+    while window.is_open() && !window.is_key_down(Key::Escape) {
+        for (key, button) in KEYMAP {
+            cpu.set_button(button, window.is_key_down(key));
+        }
+
+        // Keep presenting frames after a lockup, but only report it once
+        if let Err(lockup) = cpu.run_frame() {
+            if !locked_up {
+                eprintln!("{}", lockup);
+                locked_up = true;
+            }
+        }
+        print!("{}", serial.take());
+        audio.extend(cpu.take_samples());
+
+        for page in printer.take_pages() {
+            pages_printed += 1;
+            let path = Path::new(print_dir.as_deref().unwrap_or("."))
+                .join(format!("print-{}.pgm", pages_printed));
+            match fs::write(&path, page.to_pgm()) {
+                Ok(()) => println!("Printed {}", path.display()),
+                Err(error) => eprintln!("Failed to save {}: {}", path.display(), error),
+            }
+        }
+
+        window
+            .update_with_buffer(cpu.framebuffer(), SCREEN_WIDTH, SCREEN_HEIGHT)
+            .expect("Failed to update window");
+    }
+
+    if let Some(path) = wav_path {
+        fs::write(&path, encode_wav(&audio, sample_rate)).expect("Failed to save WAV");
+        println!("Saved {}", path);
+    }
+}
+
+fn run_four_player_adapter(address: &str) {
+    let listener = LinkListener::bind(address).expect("Failed to listen");
+    let mut adapter = FourPlayerAdapter::new();
+    println!("Four player adapter on {}", address);
+
     loop {
-        cpu.execute();
+        while let Some(cable) = listener.accept().expect("Failed to accept") {
+            match adapter.connect_cable(cable) {
+                Some(player) => println!("Player {} connected", player),
+                None => eprintln!("All four ports are taken"),
+            }
+        }
+
+        if adapter.is_empty() {
+            thread::sleep(Duration::from_millis(100));
+        } else {
+            for player in adapter.step() {
+                println!("Player {} disconnected", player);
+            }
+        }
     }
 }
@@ -0,0 +1,208 @@
+use super::{read_rom_bank, store, Mbc};
+use crate::cartridge::NINTENDO_LOGO;
+
+pub struct Mbc1 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    ram_enabled: bool,
+    // 5-bit BANK1 register (2000-3FFF)
+    bank1: u8,
+    // 2-bit BANK2 register (4000-5FFF)
+    bank2: u8,
+    // Banking mode select (6000-7FFF)
+    mode: bool,
+    // MBC1M wires BANK2 to ROM address lines 18-19 instead of 19-20
+    multicart: bool,
+}
+
+impl Mbc1 {
+    pub fn new(rom: Vec<u8>, ram_size: usize) -> Mbc1 {
+        let multicart = Mbc1::is_multicart(&rom);
+
+        Mbc1 {
+            rom,
+            ram: vec![0; ram_size],
+            ram_enabled: false,
+            bank1: 1,
+            bank2: 0,
+            mode: false,
+            multicart,
+        }
+    }
+
+    /// MBC1M collections are 8 Mbit images where each 2 Mbit game carries
+    /// its own header. The boot logo showing up again at the start of the
+    /// second game, in bank 0x10, is the usual way to tell them apart from
+    /// a plain MBC1.
+    fn is_multicart(rom: &[u8]) -> bool {
+        rom.len() == 0x100000 && rom[0x40104..0x40134] == NINTENDO_LOGO
+    }
+
+    fn bank2_shift(&self) -> u32 {
+        if self.multicart {
+            4
+        } else {
+            5
+        }
+    }
+
+    fn ram_offset(&self, address: u16) -> Option<usize> {
+        if !self.ram_enabled || self.ram.is_empty() {
+            return None;
+        }
+
+        let bank = if self.mode { self.bank2 as usize } else { 0 };
+        let offset = bank * 0x2000 + (address as usize - 0xA000);
+        Some(offset & (self.ram.len() - 1))
+    }
+}
+
+impl Mbc for Mbc1 {
+    fn read_rom(&self, address: u16) -> u8 {
+        let shift = self.bank2_shift();
+
+        match address {
+            0x0000..=0x3FFF => {
+                // In mode 1 the BANK2 bits also apply to the lower area,
+                // which is how large ROMs reach banks 0x20, 0x40 and 0x60.
+                let bank = if self.mode {
+                    (self.bank2 as usize) << shift
+                } else {
+                    0
+                };
+                read_rom_bank(&self.rom, bank, address)
+            }
+            _ => {
+                let bank1 = if self.multicart {
+                    self.bank1 & 0x0F
+                } else {
+                    self.bank1
+                };
+                let bank = (self.bank2 as usize) << shift | bank1 as usize;
+                read_rom_bank(&self.rom, bank, address)
+            }
+        }
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.ram_enabled = value & 0x0F == 0x0A,
+            0x2000..=0x3FFF => {
+                // The zero check only sees the 5 register bits, so bank
+                // 0x20 still becomes 0x21 rather than 0x20.
+                let bank = value & 0x1F;
+                self.bank1 = if bank == 0 { 1 } else { bank };
+            }
+            0x4000..=0x5FFF => self.bank2 = value & 0x03,
+            _ => self.mode = value & 0x01 == 0x01,
+        }
+    }
+
+    fn read_ram(&self, address: u16) -> u8 {
+        self.ram_offset(address)
+            .map_or(0xFF, |offset| self.ram[offset])
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) -> bool {
+        self.ram_offset(address)
+            .is_some_and(|offset| store(&mut self.ram[offset], value))
+    }
+
+    fn ram(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::test_rom::banked as banked_rom;
+
+    #[test]
+    fn bank_0_selects_bank_1() {
+        let mut mbc = Mbc1::new(banked_rom(128), 0);
+        mbc.write_rom(0x2000, 0x00);
+        assert_eq!(mbc.read_rom(0x4000), 0x01);
+
+        // Only the 5 register bits are checked, so 0x20 maps to 0x21
+        mbc.write_rom(0x4000, 0x01);
+        mbc.write_rom(0x2000, 0x00);
+        assert_eq!(mbc.read_rom(0x4000), 0x21);
+        mbc.write_rom(0x2000, 0xE0);
+        assert_eq!(mbc.read_rom(0x4000), 0x21);
+    }
+
+    #[test]
+    fn bank2_supplies_upper_rom_bank_bits() {
+        let mut mbc = Mbc1::new(banked_rom(128), 0);
+        mbc.write_rom(0x2000, 0x03);
+        mbc.write_rom(0x4000, 0x02);
+        assert_eq!(mbc.read_rom(0x4000), 0x43);
+        // Mode 0 leaves the lower area on bank 0
+        assert_eq!(mbc.read_rom(0x0000), 0x00);
+
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(mbc.read_rom(0x0000), 0x40);
+        assert_eq!(mbc.read_rom(0x4000), 0x43);
+
+        // Bank numbers wrap to the ROM size
+        let mut small = Mbc1::new(banked_rom(8), 0);
+        small.write_rom(0x2000, 0x1B);
+        assert_eq!(small.read_rom(0x4000), 0x03);
+    }
+
+    #[test]
+    fn mode_1_banks_ram_with_bank2() {
+        let mut mbc = Mbc1::new(banked_rom(4), 0x8000);
+        assert_eq!(mbc.read_ram(0xA000), 0xFF);
+        mbc.write_rom(0x0000, 0x0A);
+
+        mbc.write_rom(0x4000, 0x02);
+        mbc.write_ram(0xA000, 0x11);
+        // Mode 0 always uses RAM bank 0
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(mbc.read_ram(0xA000), 0x00);
+        mbc.write_ram(0xA000, 0x22);
+        mbc.write_rom(0x6000, 0x00);
+        assert_eq!(mbc.read_ram(0xA000), 0x11);
+        assert_eq!(mbc.ram[0x4000], 0x22);
+
+        mbc.write_rom(0x0000, 0x00);
+        assert_eq!(mbc.read_ram(0xA000), 0xFF);
+    }
+
+    #[test]
+    fn detects_mbc1m_multicarts() {
+        let mut rom = banked_rom(64);
+        // A plain 8 Mbit MBC1 only has the logo once
+        rom[0x104..0x134].copy_from_slice(&NINTENDO_LOGO);
+        assert!(!Mbc1::is_multicart(&rom));
+
+        rom[0x40104..0x40134].copy_from_slice(&NINTENDO_LOGO);
+        let mut mbc = Mbc1::new(rom, 0);
+        assert!(mbc.multicart);
+
+        // BANK2 moves to bits 4-5 and BANK1 loses its top bit
+        mbc.write_rom(0x4000, 0x01);
+        mbc.write_rom(0x2000, 0x12);
+        assert_eq!(mbc.read_rom(0x4000), 0x12);
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(mbc.read_rom(0x0000), 0x10);
+    }
+
+    #[test]
+    fn matching_padding_is_not_a_multicart() {
+        for padding in [0x00, 0xFF] {
+            let mut rom = banked_rom(64);
+            rom[0x104..0x134].fill(padding);
+            rom[0x40104..0x40134].fill(padding);
+            let mut mbc = Mbc1::new(rom, 0);
+            assert!(!mbc.multicart);
+
+            // BANK2 stays on bits 5-6
+            mbc.write_rom(0x4000, 0x01);
+            mbc.write_rom(0x2000, 0x12);
+            assert_eq!(mbc.read_rom(0x4000), 0x32);
+        }
+    }
+}
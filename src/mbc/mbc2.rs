@@ -0,0 +1,119 @@
+use super::{read_rom_bank, store, Mbc};
+
+pub struct Mbc2 {
+    rom: Vec<u8>,
+    // 512 half-bytes, stored one per byte
+    ram: [u8; 0x200],
+    ram_enabled: bool,
+    rom_bank: u8,
+}
+
+impl Mbc2 {
+    pub fn new(rom: Vec<u8>) -> Mbc2 {
+        Mbc2 {
+            rom,
+            ram: [0; 0x200],
+            ram_enabled: false,
+            rom_bank: 1,
+        }
+    }
+}
+
+impl Mbc for Mbc2 {
+    fn read_rom(&self, address: u16) -> u8 {
+        let bank = match address {
+            0x0000..=0x3FFF => 0,
+            _ => self.rom_bank as usize,
+        };
+        read_rom_bank(&self.rom, bank, address)
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        if address > 0x3FFF {
+            return;
+        }
+
+        // Address bit 8 selects between the two registers
+        if address & 0x0100 == 0 {
+            self.ram_enabled = value & 0x0F == 0x0A;
+        } else {
+            let bank = value & 0x0F;
+            self.rom_bank = if bank == 0 { 1 } else { bank };
+        }
+    }
+
+    fn read_ram(&self, address: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xFF;
+        }
+
+        // Only the low nibble exists; the open upper bits read back as 1s
+        0xF0 | self.ram[address as usize & 0x1FF]
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) -> bool {
+        self.ram_enabled && store(&mut self.ram[address as usize & 0x1FF], value & 0x0F)
+    }
+
+    fn ram(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    // Saves only ever hold the low nibble
+    fn load_save_data(&mut self, data: &[u8]) {
+        for (cell, byte) in self.ram.iter_mut().zip(data) {
+            *cell = byte & 0x0F;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::test_rom::banked as banked_rom;
+
+    #[test]
+    fn address_bit_8_picks_the_register() {
+        let mut mbc = Mbc2::new(banked_rom(16));
+
+        // Bit 8 set: ROM bank, whatever the rest of the address
+        mbc.write_rom(0x2100, 0x05);
+        assert_eq!(mbc.read_rom(0x4000), 5);
+        mbc.write_rom(0x0100, 0x0A);
+        assert_eq!(mbc.read_rom(0x4000), 10);
+        assert_eq!(mbc.read_ram(0xA000), 0xFF);
+        mbc.write_rom(0x3FFF, 0x00);
+        assert_eq!(mbc.read_rom(0x4000), 1);
+
+        // Bit 8 clear: RAM enable, leaving the bank alone
+        mbc.write_rom(0x2000, 0x0A);
+        assert_eq!(mbc.read_rom(0x4000), 1);
+        assert_eq!(mbc.read_ram(0xA000), 0xF0);
+        mbc.write_rom(0x00FF, 0x00);
+        assert_eq!(mbc.read_ram(0xA000), 0xFF);
+    }
+
+    #[test]
+    fn ram_holds_nibbles_and_echoes() {
+        let mut mbc = Mbc2::new(banked_rom(2));
+        mbc.write_rom(0x0000, 0x0A);
+
+        mbc.write_ram(0xA1FF, 0x5C);
+        assert_eq!(mbc.read_ram(0xA1FF), 0xFC);
+        assert_eq!(mbc.ram[0x1FF], 0x0C);
+
+        // The 512 cells repeat across the whole A000-BFFF window
+        for echo in (0xA000..0xC000).step_by(0x200) {
+            assert_eq!(mbc.read_ram(echo + 0x1FF), 0xFC);
+        }
+        mbc.write_ram(0xB803, 0x07);
+        assert_eq!(mbc.read_ram(0xA003), 0xF7);
+    }
+
+    #[test]
+    fn saves_keep_the_low_nibble() {
+        let mut mbc = Mbc2::new(banked_rom(2));
+        mbc.load_save_data(&[0xAB; 0x200]);
+        assert_eq!(mbc.save_data(), vec![0x0B; 0x200]);
+    }
+}
@@ -0,0 +1,165 @@
+use super::{
+    load_ram, read_rom_bank,
+    rtc::{Rtc, RtcClock},
+    store, Mbc,
+};
+
+pub struct Mbc3 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    rtc: Option<Rtc>,
+    // Enables both RAM and the RTC registers
+    ram_enabled: bool,
+    rom_bank: u8,
+    // 0x00-0x07 selects a RAM bank, 0x08-0x0C an RTC register
+    ram_bank: u8,
+}
+
+impl Mbc3 {
+    pub fn new(rom: Vec<u8>, ram_size: usize, timer: bool) -> Mbc3 {
+        Mbc3 {
+            rom,
+            ram: vec![0; ram_size],
+            rtc: timer.then(Rtc::new),
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+        }
+    }
+
+    fn ram_offset(&self, address: u16) -> Option<usize> {
+        if self.ram.is_empty() || self.ram_bank > 0x07 {
+            return None;
+        }
+
+        let offset = self.ram_bank as usize * 0x2000 + (address as usize - 0xA000);
+        Some(offset & (self.ram.len() - 1))
+    }
+}
+
+impl Mbc for Mbc3 {
+    fn read_rom(&self, address: u16) -> u8 {
+        let bank = match address {
+            0x0000..=0x3FFF => 0,
+            _ => self.rom_bank as usize,
+        };
+        read_rom_bank(&self.rom, bank, address)
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.ram_enabled = value & 0x0F == 0x0A,
+            0x2000..=0x3FFF => {
+                let bank = value & 0x7F;
+                self.rom_bank = if bank == 0 { 1 } else { bank };
+            }
+            0x4000..=0x5FFF => self.ram_bank = value & 0x0F,
+            _ => {
+                if let Some(rtc) = self.rtc.as_mut() {
+                    rtc.write_latch(value);
+                }
+            }
+        }
+    }
+
+    fn read_ram(&self, address: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xFF;
+        }
+
+        match self.ram_bank {
+            0x08..=0x0C => self
+                .rtc
+                .as_ref()
+                .map_or(0xFF, |rtc| rtc.read(self.ram_bank)),
+            _ => self
+                .ram_offset(address)
+                .map_or(0xFF, |offset| self.ram[offset]),
+        }
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) -> bool {
+        if !self.ram_enabled {
+            return false;
+        }
+
+        match self.ram_bank {
+            // Setting the clock always counts, if only for the divider reset
+            0x08..=0x0C => self
+                .rtc
+                .as_mut()
+                .map(|rtc| rtc.write(self.ram_bank, value))
+                .is_some(),
+            _ => self
+                .ram_offset(address)
+                .is_some_and(|offset| store(&mut self.ram[offset], value)),
+        }
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        if let Some(rtc) = self.rtc.as_mut() {
+            rtc.tick(cycles);
+        }
+    }
+
+    fn set_rtc_clock(&mut self, clock: RtcClock) {
+        if let Some(rtc) = self.rtc.as_mut() {
+            rtc.set_clock(clock);
+        }
+    }
+
+    fn ram(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn save_data(&mut self) -> Vec<u8> {
+        let mut data = self.ram.clone();
+        if let Some(rtc) = self.rtc.as_mut() {
+            data.extend(rtc.save());
+        }
+        data
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        load_ram(&mut self.ram, data);
+
+        if let Some(rtc) = self.rtc.as_mut() {
+            if data.len() > self.ram.len() {
+                rtc.load(&data[self.ram.len()..]);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::test_rom::banked as banked_rom;
+
+    #[test]
+    fn latches_and_saves_the_clock_with_ram() {
+        let mut mbc = Mbc3::new(banked_rom(2), 0x2000, true);
+        mbc.set_rtc_clock(RtcClock::Cycles);
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_ram(0xA000, 0x42);
+
+        mbc.tick(4_194_304 * 2);
+        mbc.write_rom(0x6000, 0x00);
+        mbc.write_rom(0x6000, 0x01);
+        mbc.write_rom(0x4000, 0x08);
+        assert_eq!(mbc.read_ram(0xA000), 2);
+
+        // The battery file is the RAM followed by the RTC footer
+        let data = mbc.save_data();
+        assert_eq!(data.len(), 0x2000 + 48);
+
+        let mut restored = Mbc3::new(banked_rom(2), 0x2000, true);
+        restored.set_rtc_clock(RtcClock::Cycles);
+        restored.load_save_data(&data);
+        restored.write_rom(0x0000, 0x0A);
+        restored.write_rom(0x4000, 0x08);
+        assert_eq!(restored.read_ram(0xA000), 2);
+        restored.write_rom(0x4000, 0x00);
+        assert_eq!(restored.read_ram(0xA000), 0x42);
+    }
+}
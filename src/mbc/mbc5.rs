@@ -0,0 +1,171 @@
+use super::{read_rom_bank, store, Mbc};
+
+pub struct Mbc5 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    ram_enabled: bool,
+    // 9-bit ROM bank number, split across 2000-2FFF and 3000-3FFF
+    rom_bank: u16,
+    ram_bank: u8,
+    // Rumble carts wire bit 3 of the RAM bank register to the motor
+    has_rumble: bool,
+    rumble: bool,
+}
+
+impl Mbc5 {
+    pub fn new(rom: Vec<u8>, ram_size: usize, has_rumble: bool) -> Mbc5 {
+        Mbc5 {
+            rom,
+            ram: vec![0; ram_size],
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            has_rumble,
+            rumble: false,
+        }
+    }
+
+    fn ram_offset(&self, address: u16) -> Option<usize> {
+        if !self.ram_enabled || self.ram.is_empty() {
+            return None;
+        }
+
+        let offset = self.ram_bank as usize * 0x2000 + (address as usize - 0xA000);
+        Some(offset & (self.ram.len() - 1))
+    }
+}
+
+impl Mbc for Mbc5 {
+    fn read_rom(&self, address: u16) -> u8 {
+        // Unlike MBC1 and MBC3, bank 0 can be mapped into 4000-7FFF
+        let bank = match address {
+            0x0000..=0x3FFF => 0,
+            _ => self.rom_bank as usize,
+        };
+        read_rom_bank(&self.rom, bank, address)
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.ram_enabled = value == 0x0A,
+            0x2000..=0x2FFF => self.rom_bank = (self.rom_bank & 0x100) | value as u16,
+            0x3000..=0x3FFF => self.rom_bank = (self.rom_bank & 0xFF) | (value as u16 & 0x01) << 8,
+            0x4000..=0x5FFF => {
+                if self.has_rumble {
+                    self.rumble = value & 0x08 != 0;
+                    self.ram_bank = value & 0x07;
+                } else {
+                    self.ram_bank = value & 0x0F;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn read_ram(&self, address: u16) -> u8 {
+        self.ram_offset(address)
+            .map_or(0xFF, |offset| self.ram[offset])
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) -> bool {
+        self.ram_offset(address)
+            .is_some_and(|offset| store(&mut self.ram[offset], value))
+    }
+
+    fn ram(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn rumble(&self) -> bool {
+        self.rumble
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cartridge::{
+            test_rom::{banked as banked_rom, with_header},
+            Cartridge,
+        },
+        mmu::Mmu,
+    };
+    use std::{cell::RefCell, rc::Rc};
+
+    fn bank_at_4000(mbc: &Mbc5) -> u16 {
+        u16::from_le_bytes([mbc.read_rom(0x4000), mbc.read_rom(0x4001)])
+    }
+
+    #[test]
+    fn nine_bit_rom_banking_includes_bank_0() {
+        let mut mbc = Mbc5::new(banked_rom(512), 0, false);
+        assert_eq!(bank_at_4000(&mbc), 1);
+
+        mbc.write_rom(0x2000, 0x00);
+        assert_eq!(bank_at_4000(&mbc), 0);
+        mbc.write_rom(0x2000, 0xFF);
+        assert_eq!(bank_at_4000(&mbc), 0xFF);
+        mbc.write_rom(0x3000, 0x01);
+        assert_eq!(bank_at_4000(&mbc), 0x1FF);
+        // Only bit 0 of the high register exists
+        mbc.write_rom(0x3000, 0xFE);
+        assert_eq!(bank_at_4000(&mbc), 0xFF);
+        mbc.write_rom(0x3000, 0x01);
+        mbc.write_rom(0x2000, 0x00);
+        assert_eq!(bank_at_4000(&mbc), 0x100);
+    }
+
+    #[test]
+    fn ram_banks_and_enable() {
+        let mut mbc = Mbc5::new(banked_rom(4), 0x20000, false);
+        mbc.write_ram(0xA000, 0x11);
+        assert_eq!(mbc.read_ram(0xA000), 0xFF);
+
+        mbc.write_rom(0x0000, 0x0A);
+        for bank in 0..16 {
+            mbc.write_rom(0x4000, bank);
+            mbc.write_ram(0xA123, bank + 0x40);
+        }
+        for bank in 0..16 {
+            mbc.write_rom(0x4000, bank);
+            assert_eq!(mbc.read_ram(0xA123), bank + 0x40);
+        }
+        assert_eq!(mbc.ram[5 * 0x2000 + 0x123], 0x45);
+    }
+
+    #[test]
+    fn rumble_carts_take_bit_3_for_the_motor() {
+        let mut mbc = Mbc5::new(banked_rom(4), 0x8000, true);
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_rom(0x4000, 0x0B);
+        assert!(mbc.rumble());
+        mbc.write_ram(0xA000, 0x33);
+        assert_eq!(mbc.ram[3 * 0x2000], 0x33);
+
+        mbc.write_rom(0x4000, 0x03);
+        assert!(!mbc.rumble());
+
+        // Without a motor bit 3 is a RAM bank bit
+        let mut plain = Mbc5::new(banked_rom(4), 0x20000, false);
+        plain.write_rom(0x4000, 0x08);
+        assert!(!plain.rumble());
+    }
+
+    #[test]
+    fn rumble_changes_reach_the_frontend_once() {
+        // MBC5 with a rumble motor
+        let rom = with_header(0x1C, 0x00, 0x00);
+
+        let calls = Rc::new(RefCell::new(Vec::new()));
+        let mut mmu = Mmu::new();
+        let log = Rc::clone(&calls);
+        mmu.set_rumble_callback(Box::new(move |on| log.borrow_mut().push(on)));
+        mmu.load_cartridge(Cartridge::from_bytes(rom).unwrap());
+
+        for value in [0x08, 0x09, 0x00, 0x00, 0x08] {
+            mmu.write_byte(0x4000, value);
+        }
+        assert_eq!(*calls.borrow(), [true, false, true]);
+    }
+}
@@ -0,0 +1,107 @@
+mod mbc1;
+mod mbc2;
+mod mbc3;
+mod mbc5;
+mod rtc;
+
+pub use mbc1::Mbc1;
+pub use mbc2::Mbc2;
+pub use mbc3::Mbc3;
+pub use mbc5::Mbc5;
+pub use rtc::RtcClock;
+
+/// A memory bank controller sitting between the cartridge ROM/RAM and the
+/// bus. Addresses are passed through unmodified (0x0000-0x7FFF for ROM,
+/// 0xA000-0xBFFF for RAM).
+pub trait Mbc {
+    fn read_rom(&self, address: u16) -> u8;
+    fn write_rom(&mut self, address: u16, value: u8);
+    fn read_ram(&self, address: u16) -> u8;
+    /// Returns whether the write changed any battery-backed state.
+    fn write_ram(&mut self, address: u16, value: u8) -> bool;
+
+    /// The external RAM, empty when the cartridge has none.
+    fn ram(&mut self) -> &mut [u8];
+
+    /// Battery-backed state: the external RAM, followed by any extra
+    /// mapper state such as the MBC3 clock.
+    fn save_data(&mut self) -> Vec<u8> {
+        self.ram().to_vec()
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        load_ram(self.ram(), data);
+    }
+
+    fn tick(&mut self, _cycles: u32) {}
+
+    fn set_rtc_clock(&mut self, _clock: RtcClock) {}
+
+    /// Whether the rumble motor is currently driven.
+    fn rumble(&self) -> bool {
+        false
+    }
+}
+
+/// Copies as much of a save file as fits into RAM. A short file leaves
+/// the rest of RAM as it was.
+fn load_ram(ram: &mut [u8], data: &[u8]) {
+    let len = ram.len().min(data.len());
+    ram[..len].copy_from_slice(&data[..len]);
+}
+
+/// Reads `address` from 16 KiB ROM bank `bank`. Bank numbers past the end
+/// of the image wrap, as the unconnected high address lines are ignored.
+fn read_rom_bank(rom: &[u8], bank: usize, address: u16) -> u8 {
+    let banks = (rom.len() / 0x4000).max(1);
+    let offset = (bank & (banks - 1)) * 0x4000 + (address as usize & 0x3FFF);
+    rom.get(offset).copied().unwrap_or(0xFF)
+}
+
+/// Stores `value` in a RAM cell, reporting whether it differed.
+fn store(cell: &mut u8, value: u8) -> bool {
+    let changed = *cell != value;
+    *cell = value;
+    changed
+}
+
+/// Cartridges without a bank controller: 32 KiB of ROM mapped directly,
+/// plus up to 8 KiB of optional RAM.
+pub struct RomOnly {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+}
+
+impl RomOnly {
+    pub fn new(rom: Vec<u8>, ram_size: usize) -> RomOnly {
+        RomOnly {
+            rom,
+            ram: vec![0; ram_size],
+        }
+    }
+}
+
+impl Mbc for RomOnly {
+    fn read_rom(&self, address: u16) -> u8 {
+        self.rom.get(address as usize).copied().unwrap_or(0xFF)
+    }
+
+    fn write_rom(&mut self, _address: u16, _value: u8) {}
+
+    fn read_ram(&self, address: u16) -> u8 {
+        self.ram
+            .get(address as usize - 0xA000)
+            .copied()
+            .unwrap_or(0xFF)
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) -> bool {
+        self.ram
+            .get_mut(address as usize - 0xA000)
+            .is_some_and(|byte| store(byte, value))
+    }
+
+    fn ram(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+}
@@ -0,0 +1,373 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const CYCLES_PER_SECOND: u32 = 4_194_304;
+
+const HALT: u8 = 0x40;
+const DAY_CARRY: u8 = 0x80;
+
+/// What the RTC counts against. Wall-clock time keeps running between
+/// sessions; emulated cycles keep runs reproducible.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RtcClock {
+    WallClock,
+    Cycles,
+}
+
+#[derive(Copy, Clone, Default)]
+struct Registers {
+    seconds: u8,
+    minutes: u8,
+    hours: u8,
+    days_low: u8,
+    // Bit 0: day counter bit 8, bit 6: halt, bit 7: day counter carry
+    days_high: u8,
+}
+
+impl Registers {
+    fn days(&self) -> u16 {
+        (self.days_high as u16 & 0x01) << 8 | self.days_low as u16
+    }
+
+    fn set_days(&mut self, days: u16) {
+        self.days_low = days as u8;
+        self.days_high = (self.days_high & !0x01) | (days >> 8) as u8 & 0x01;
+    }
+
+    fn in_range(&self) -> bool {
+        self.seconds < 60 && self.minutes < 60 && self.hours < 24
+    }
+
+    /// Advances by a single second, wrapping out-of-range values the way
+    /// the counters do when software writes e.g. 63 into the seconds.
+    fn tick_second(&mut self) {
+        self.seconds = (self.seconds + 1) & 0x3F;
+        if self.seconds != 60 {
+            return;
+        }
+        self.seconds = 0;
+
+        self.minutes = (self.minutes + 1) & 0x3F;
+        if self.minutes != 60 {
+            return;
+        }
+        self.minutes = 0;
+
+        self.hours = (self.hours + 1) & 0x1F;
+        if self.hours != 24 {
+            return;
+        }
+        self.hours = 0;
+
+        self.add_days(1);
+    }
+
+    fn add_days(&mut self, days: u64) {
+        let total = self.days() as u64 + days;
+        if total > 0x1FF {
+            self.days_high |= DAY_CARRY;
+        }
+        self.set_days((total & 0x1FF) as u16);
+    }
+
+    fn advance(&mut self, mut seconds: u64) {
+        while seconds > 0 && !self.in_range() {
+            self.tick_second();
+            seconds -= 1;
+        }
+
+        let total =
+            self.seconds as u64 + self.minutes as u64 * 60 + self.hours as u64 * 3600 + seconds;
+        self.seconds = (total % 60) as u8;
+        self.minutes = (total / 60 % 60) as u8;
+        self.hours = (total / 3600 % 24) as u8;
+        self.add_days(total / 86400);
+    }
+}
+
+pub struct Rtc {
+    clock: RtcClock,
+    live: Registers,
+    latched: Registers,
+    // 0x00 written to the latch register, waiting for 0x01
+    latch_armed: bool,
+    // Wall-clock time the live registers were last brought up to date
+    synced_at: SystemTime,
+    // Emulated cycles since the last whole second
+    cycles: u32,
+}
+
+impl Rtc {
+    pub fn new() -> Rtc {
+        Rtc {
+            clock: RtcClock::WallClock,
+            live: Registers::default(),
+            latched: Registers::default(),
+            latch_armed: false,
+            synced_at: SystemTime::now(),
+            cycles: 0,
+        }
+    }
+
+    pub fn set_clock(&mut self, clock: RtcClock) {
+        self.sync();
+        self.clock = clock;
+        self.synced_at = SystemTime::now();
+        self.cycles = 0;
+    }
+
+    fn halted(&self) -> bool {
+        self.live.days_high & HALT != 0
+    }
+
+    /// Catches the live registers up with the wall clock.
+    fn sync(&mut self) {
+        if self.clock != RtcClock::WallClock {
+            return;
+        }
+
+        let now = SystemTime::now();
+        if self.halted() {
+            self.synced_at = now;
+            return;
+        }
+
+        // A clock that went backwards just leaves the RTC where it is
+        let elapsed = now.duration_since(self.synced_at).unwrap_or_default();
+        let seconds = elapsed.as_secs();
+        if seconds > 0 {
+            self.live.advance(seconds);
+            self.synced_at += Duration::from_secs(seconds);
+        }
+    }
+
+    pub fn tick(&mut self, cycles: u32) {
+        if self.clock != RtcClock::Cycles || self.halted() {
+            return;
+        }
+
+        self.cycles += cycles;
+        while self.cycles >= CYCLES_PER_SECOND {
+            self.cycles -= CYCLES_PER_SECOND;
+            self.live.tick_second();
+        }
+    }
+
+    pub fn write_latch(&mut self, value: u8) {
+        if self.latch_armed && value == 0x01 {
+            self.sync();
+            self.latched = self.live;
+        }
+        self.latch_armed = value == 0x00;
+    }
+
+    pub fn read(&self, register: u8) -> u8 {
+        match register {
+            0x08 => self.latched.seconds,
+            0x09 => self.latched.minutes,
+            0x0A => self.latched.hours,
+            0x0B => self.latched.days_low,
+            0x0C => self.latched.days_high,
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write(&mut self, register: u8, value: u8) {
+        self.sync();
+
+        match register {
+            0x08 => {
+                self.live.seconds = value & 0x3F;
+                // Writing the seconds resets the sub-second divider
+                self.cycles = 0;
+                self.synced_at = SystemTime::now();
+            }
+            0x09 => self.live.minutes = value & 0x3F,
+            0x0A => self.live.hours = value & 0x1F,
+            0x0B => self.live.days_low = value,
+            0x0C => self.live.days_high = value & (DAY_CARRY | HALT | 0x01),
+            _ => {}
+        }
+    }
+
+    /// Serializes the clock in the 48-byte footer layout used by most
+    /// emulators: live then latched registers as little-endian u32s,
+    /// followed by a 64-bit UNIX timestamp.
+    pub fn save(&mut self) -> Vec<u8> {
+        self.sync();
+
+        let mut data = Vec::with_capacity(48);
+        for registers in [self.live, self.latched] {
+            for value in [
+                registers.seconds,
+                registers.minutes,
+                registers.hours,
+                registers.days_low,
+                registers.days_high,
+            ] {
+                data.extend_from_slice(&(value as u32).to_le_bytes());
+            }
+        }
+
+        let timestamp = self
+            .synced_at
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        data.extend_from_slice(&timestamp.to_le_bytes());
+
+        data
+    }
+
+    /// Restores a footer written by `save`. Older files with a 32-bit
+    /// timestamp (44 bytes) are accepted too.
+    pub fn load(&mut self, data: &[u8]) {
+        if data.len() != 44 && data.len() != 48 {
+            return;
+        }
+
+        let word = |i: usize| data[i * 4];
+        let registers = |base: usize| Registers {
+            seconds: word(base),
+            minutes: word(base + 1),
+            hours: word(base + 2),
+            days_low: word(base + 3),
+            days_high: word(base + 4),
+        };
+        self.live = registers(0);
+        self.latched = registers(5);
+
+        let mut timestamp = [0; 8];
+        timestamp[..data.len() - 40].copy_from_slice(&data[40..]);
+        self.synced_at = UNIX_EPOCH + Duration::from_secs(u64::from_le_bytes(timestamp));
+        self.cycles = 0;
+
+        self.sync();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cycle_rtc() -> Rtc {
+        let mut rtc = Rtc::new();
+        rtc.set_clock(RtcClock::Cycles);
+        rtc
+    }
+
+    fn latch(rtc: &mut Rtc) {
+        rtc.write_latch(0x00);
+        rtc.write_latch(0x01);
+    }
+
+    fn read_all(rtc: &Rtc) -> [u8; 5] {
+        [0x08, 0x09, 0x0A, 0x0B, 0x0C].map(|register| rtc.read(register))
+    }
+
+    #[test]
+    fn latches_only_on_00_then_01() {
+        let mut rtc = cycle_rtc();
+        rtc.tick(CYCLES_PER_SECOND * 61);
+        assert_eq!(read_all(&rtc), [0, 0, 0, 0, 0]);
+
+        rtc.write_latch(0x01);
+        assert_eq!(rtc.read(0x08), 0);
+        rtc.write_latch(0x00);
+        rtc.write_latch(0x02);
+        rtc.write_latch(0x01);
+        assert_eq!(rtc.read(0x08), 0);
+
+        latch(&mut rtc);
+        assert_eq!(read_all(&rtc), [1, 1, 0, 0, 0]);
+
+        // The latched copy holds still while the clock runs on
+        rtc.tick(CYCLES_PER_SECOND);
+        assert_eq!(rtc.read(0x08), 1);
+    }
+
+    #[test]
+    fn halt_stops_the_clock() {
+        let mut rtc = cycle_rtc();
+        rtc.write(0x0C, HALT);
+        rtc.tick(CYCLES_PER_SECOND * 10);
+        latch(&mut rtc);
+        assert_eq!(rtc.read(0x08), 0);
+
+        rtc.write(0x0C, 0x00);
+        rtc.tick(CYCLES_PER_SECOND * 10);
+        latch(&mut rtc);
+        assert_eq!(rtc.read(0x08), 10);
+    }
+
+    #[test]
+    fn day_counter_overflow_sets_sticky_carry() {
+        let mut rtc = cycle_rtc();
+        rtc.write(0x08, 59);
+        rtc.write(0x09, 59);
+        rtc.write(0x0A, 23);
+        rtc.write(0x0B, 0xFF);
+        rtc.write(0x0C, 0x01);
+
+        rtc.tick(CYCLES_PER_SECOND);
+        latch(&mut rtc);
+        assert_eq!(read_all(&rtc), [0, 0, 0, 0x00, DAY_CARRY]);
+
+        for _ in 0..86400 {
+            rtc.tick(CYCLES_PER_SECOND);
+        }
+        latch(&mut rtc);
+        assert_eq!(rtc.read(0x0B), 0x01);
+        assert_eq!(rtc.read(0x0C), DAY_CARRY);
+    }
+
+    #[test]
+    fn out_of_range_values_wrap_at_their_bit_width() {
+        let mut rtc = cycle_rtc();
+        rtc.write(0x08, 63);
+        rtc.tick(CYCLES_PER_SECOND);
+        latch(&mut rtc);
+        // 63 rolls over to 0 without carrying into the minutes
+        assert_eq!(read_all(&rtc), [0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn cycle_clock_is_deterministic() {
+        let run = || {
+            let mut rtc = cycle_rtc();
+            // Uneven chunks, including a partial second carried over
+            for chunk in [1_000, CYCLES_PER_SECOND * 3 + 17, 4_194_287, 70_224] {
+                rtc.tick(chunk);
+            }
+            latch(&mut rtc);
+            (read_all(&rtc), rtc.cycles)
+        };
+        assert_eq!(run(), run());
+        assert_eq!(run().0, [4, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn footer_round_trips_in_both_sizes() {
+        let mut rtc = cycle_rtc();
+        rtc.write(0x0A, 5);
+        rtc.write(0x0B, 0x34);
+        rtc.write(0x0C, HALT | 0x01);
+        latch(&mut rtc);
+        rtc.write(0x08, 7);
+
+        let footer = rtc.save();
+        assert_eq!(footer.len(), 48);
+
+        for data in [&footer[..], &footer[..44]] {
+            let mut restored = Rtc::new();
+            restored.set_clock(RtcClock::Cycles);
+            restored.load(data);
+            assert_eq!(read_all(&restored), [0, 0, 5, 0x34, HALT | 0x01]);
+            assert_eq!(restored.live.seconds, 7);
+        }
+
+        // Anything else is ignored
+        let mut untouched = cycle_rtc();
+        untouched.load(&footer[..40]);
+        assert_eq!(untouched.live.hours, 0);
+    }
+}
@@ -0,0 +1,373 @@
+use crate::{
+    apu::Apu,
+    cartridge::Cartridge,
+    dma::{Bus, OamDma},
+    interrupts::Interrupt,
+    joypad::{Button, Joypad},
+    mbc::RtcClock,
+    ppu::Ppu,
+    serial::{Serial, SerialDevice},
+    timer::Timer,
+};
+use std::{io, path::Path};
+
+pub struct Mmu {
+    cartridge: Option<Cartridge>,
+    rtc_clock: RtcClock,
+    rumble: bool,
+    rumble_callback: Option<Box<dyn FnMut(bool)>>,
+    ppu: Ppu,
+    apu: Apu,
+    dma: OamDma,
+    timer: Timer,
+    joypad: Joypad,
+    serial: Serial,
+    wram: [u8; 0x2000],
+    io: [u8; 0x80],
+    hram: [u8; 0x7F],
+    // IF (FF0F)
+    interrupt_flag: u8,
+    // IE (FFFF)
+    ie: u8,
+}
+
+impl Mmu {
+    pub fn new() -> Mmu {
+        Mmu {
+            cartridge: None,
+            rtc_clock: RtcClock::WallClock,
+            rumble: false,
+            rumble_callback: None,
+            ppu: Ppu::new(),
+            apu: Apu::new(),
+            dma: OamDma::new(),
+            timer: Timer::new(),
+            joypad: Joypad::new(),
+            serial: Serial::new(),
+            wram: [0; 0x2000],
+            io: [0; 0x80],
+            hram: [0; 0x7F],
+            interrupt_flag: 0x01,
+            ie: 0,
+        }
+    }
+
+    pub fn load_cartridge(&mut self, mut cartridge: Cartridge) {
+        cartridge.set_rtc_clock(self.rtc_clock);
+        self.cartridge = Some(cartridge);
+    }
+
+    pub fn attach_save_file(&mut self, path: &Path) -> io::Result<()> {
+        match self.cartridge.as_mut() {
+            Some(cartridge) => cartridge.attach_save_file(path),
+            None => Ok(()),
+        }
+    }
+
+    pub fn set_rtc_clock(&mut self, clock: RtcClock) {
+        self.rtc_clock = clock;
+        if let Some(cartridge) = self.cartridge.as_mut() {
+            cartridge.set_rtc_clock(clock);
+        }
+    }
+
+    pub fn set_rumble_callback(&mut self, callback: Box<dyn FnMut(bool)>) {
+        self.rumble_callback = Some(callback);
+    }
+
+    pub fn ppu(&self) -> &Ppu {
+        &self.ppu
+    }
+
+    pub fn ppu_mut(&mut self) -> &mut Ppu {
+        &mut self.ppu
+    }
+
+    pub fn apu_mut(&mut self) -> &mut Apu {
+        &mut self.apu
+    }
+
+    pub fn tick(&mut self, cycles: u32) {
+        if let Some(cartridge) = self.cartridge.as_mut() {
+            cartridge.tick(cycles);
+        }
+
+        for _ in 0..cycles / 4 {
+            if let Some((address, index)) = self.dma.step() {
+                let value = self.read_bus(address);
+                self.dma.finish_byte(value);
+                self.ppu.write_oam_dma(index, value);
+            }
+
+            if self.timer.step() {
+                self.interrupt_flag |= Interrupt::Timer.bit();
+            }
+            self.apu.step(self.timer.divider());
+            if self.serial.step() {
+                self.interrupt_flag |= Interrupt::Serial.bit();
+            }
+        }
+
+        self.interrupt_flag |= self.ppu.tick(cycles);
+    }
+
+    /// The highest-priority interrupt that is both requested and enabled.
+    pub fn pending_interrupt(&self) -> Option<Interrupt> {
+        let pending = self.ie & self.interrupt_flag;
+        Interrupt::ALL
+            .into_iter()
+            .find(|interrupt| pending & interrupt.bit() != 0)
+    }
+
+    pub fn acknowledge_interrupt(&mut self, interrupt: Interrupt) {
+        self.interrupt_flag &= !interrupt.bit();
+    }
+
+    pub fn connect_serial(&mut self, device: Box<dyn SerialDevice>) {
+        self.serial.connect(device);
+    }
+
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        if self.joypad.set_button(button, pressed) {
+            self.interrupt_flag |= Interrupt::Joypad.bit();
+        }
+    }
+
+    /// Whether any of the P1 input lines is pulled low, which is what
+    /// brings the CPU back out of STOP.
+    pub fn joypad_line_low(&self) -> bool {
+        self.read_io(0xFF00) & 0x0F != 0x0F
+    }
+
+    /// STOP resets the divider along with the rest of the clock.
+    pub fn reset_divider(&mut self) {
+        self.timer.reset_divider();
+    }
+
+    /// Consumes a pending CGB speed switch. KEY1 (FF4D) only exists on CGB
+    /// hardware, which isn't emulated, so a switch is never armed.
+    pub fn take_speed_switch(&mut self) -> bool {
+        false
+    }
+
+    /// Whether a CPU access to `address` collides with a running OAM DMA.
+    /// OAM itself is always off limits; otherwise only the bus DMA is
+    /// reading from is taken, leaving e.g. HRAM and IO reachable.
+    fn dma_conflict(&self, address: u16) -> bool {
+        if !self.dma.active() {
+            return false;
+        }
+
+        match address {
+            0xFE00..=0xFEFF => true,
+            _ => Bus::of(address).map_or(false, |bus| self.dma.source_bus() == Some(bus)),
+        }
+    }
+
+    pub fn read_byte(&self, address: u16) -> u8 {
+        if self.dma_conflict(address) {
+            return match address {
+                0xFE00..=0xFEFF => 0xFF,
+                // The CPU sees whatever DMA is currently moving
+                _ => self.dma.current_byte(),
+            };
+        }
+
+        self.read_bus(address)
+    }
+
+    fn read_bus(&self, address: u16) -> u8 {
+        match address {
+            // ROM bank 00 and switchable ROM bank 01-NN
+            0x0000..=0x7FFF => self
+                .cartridge
+                .as_ref()
+                .map_or(0xFF, |cartridge| cartridge.read_rom(address)),
+            0x8000..=0x9FFF => self.ppu.read_vram(address),
+            0xA000..=0xBFFF => self
+                .cartridge
+                .as_ref()
+                .map_or(0xFF, |cartridge| cartridge.read_ram(address)),
+            0xC000..=0xDFFF => self.wram[address as usize - 0xC000],
+            // Echo RAM mirrors C000-DDFF
+            0xE000..=0xFDFF => self.wram[address as usize - 0xE000],
+            0xFE00..=0xFE9F => self.ppu.read_oam(address),
+            // Not usable
+            0xFEA0..=0xFEFF => 0xFF,
+            0xFF00..=0xFF7F => self.read_io(address),
+            0xFF80..=0xFFFE => self.hram[address as usize - 0xFF80],
+            0xFFFF => self.ie,
+        }
+    }
+
+    pub fn write_byte(&mut self, address: u16, value: u8) {
+        if self.dma_conflict(address) {
+            return;
+        }
+
+        match address {
+            0x0000..=0x7FFF => {
+                if let Some(cartridge) = self.cartridge.as_mut() {
+                    cartridge.write_rom(address, value);
+                    self.update_rumble();
+                }
+            }
+            0x8000..=0x9FFF => self.ppu.write_vram(address, value),
+            0xA000..=0xBFFF => {
+                if let Some(cartridge) = self.cartridge.as_mut() {
+                    cartridge.write_ram(address, value);
+                }
+            }
+            0xC000..=0xDFFF => self.wram[address as usize - 0xC000] = value,
+            0xE000..=0xFDFF => self.wram[address as usize - 0xE000] = value,
+            0xFE00..=0xFE9F => self.ppu.write_oam(address, value),
+            0xFEA0..=0xFEFF => {}
+            0xFF00..=0xFF7F => self.write_io(address, value),
+            0xFF80..=0xFFFE => self.hram[address as usize - 0xFF80] = value,
+            0xFFFF => self.ie = value,
+        }
+    }
+
+    fn update_rumble(&mut self) {
+        let rumble = self
+            .cartridge
+            .as_ref()
+            .map_or(false, |cartridge| cartridge.rumble());
+        if rumble == self.rumble {
+            return;
+        }
+
+        self.rumble = rumble;
+        if let Some(callback) = self.rumble_callback.as_mut() {
+            callback(rumble);
+        }
+    }
+
+    fn read_io(&self, address: u16) -> u8 {
+        match address {
+            0xFF40..=0xFF45 | 0xFF47..=0xFF4B => self.ppu.read_register(address),
+            0xFF01..=0xFF02 => self.serial.read_register(address),
+            0xFF04..=0xFF07 => self.timer.read_register(address),
+            0xFF10..=0xFF3F => self.apu.read_register(address),
+            0xFF46 => self.dma.read_register(),
+            // Only five interrupt lines exist; the upper bits read as 1
+            0xFF0F => 0xE0 | self.interrupt_flag,
+            0xFF00 => self.joypad.read_register(),
+            _ => self.io[address as usize - 0xFF00],
+        }
+    }
+
+    fn write_io(&mut self, address: u16, value: u8) {
+        match address {
+            0xFF40..=0xFF45 | 0xFF47..=0xFF4B => self.ppu.write_register(address, value),
+            0xFF46 => self.dma.write_register(value),
+            0xFF0F => self.interrupt_flag = value & 0x1F,
+            0xFF00 => {
+                if self.joypad.write_register(value) {
+                    self.interrupt_flag |= Interrupt::Joypad.bit();
+                }
+            }
+            0xFF01..=0xFF02 => self.serial.write_register(address, value),
+            0xFF04..=0xFF07 => self.timer.write_register(address, value),
+            0xFF10..=0xFF3F => self.apu.write_register(address, value),
+            _ => self.io[address as usize - 0xFF00] = value,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An MMU with the LCD off, so OAM is only ever blocked by DMA, and
+    /// 0x40-0xDF in WRAM at C000-C09F and VRAM at 8000-809F.
+    fn mmu() -> Mmu {
+        let mut mmu = Mmu::new();
+        mmu.write_byte(0xFF40, 0x00);
+        for i in 0..0xA0 {
+            mmu.write_byte(0xC000 + i, 0x40 + i as u8);
+            mmu.write_byte(0x8000 + i, 0x40 + i as u8);
+        }
+        mmu.write_byte(0xD000, 0x11);
+        mmu.write_byte(0x9000, 0x22);
+        mmu.write_byte(0xFF80, 0x33);
+        mmu
+    }
+
+    fn m_cycles(mmu: &mut Mmu, count: u32) {
+        mmu.tick(count * 4);
+    }
+
+    #[test]
+    fn transfer_takes_640_dots_after_its_setup_cycle() {
+        let mut mmu = mmu();
+        mmu.write_byte(0xFF46, 0xC0);
+        assert_eq!(mmu.read_byte(0xFF46), 0xC0);
+        assert!(!mmu.dma.active());
+
+        m_cycles(&mut mmu, 1);
+        assert!(mmu.dma.active());
+        mmu.tick(640 - 4);
+        assert!(mmu.dma.active());
+        assert_eq!(mmu.read_byte(0xFE9F), 0xFF);
+        m_cycles(&mut mmu, 1);
+        assert!(!mmu.dma.active());
+
+        for i in 0..0xA0 {
+            assert_eq!(mmu.read_byte(0xFE00 + i), 0x40 + i as u8);
+        }
+    }
+
+    #[test]
+    fn oam_reads_ff_and_ignores_writes_during_transfer() {
+        let mut mmu = mmu();
+        mmu.write_byte(0xFF46, 0xC0);
+        m_cycles(&mut mmu, 10);
+
+        assert_eq!(mmu.read_byte(0xFE00), 0xFF);
+        mmu.write_byte(0xFE00, 0x00);
+        m_cycles(&mut mmu, 160);
+        assert_eq!(mmu.read_byte(0xFE00), 0x40);
+    }
+
+    #[test]
+    fn same_bus_reads_see_the_byte_in_flight() {
+        let mut mmu = mmu();
+        mmu.write_byte(0xFF46, 0xC0);
+        // Setup, then bytes 0-4
+        m_cycles(&mut mmu, 6);
+        assert_eq!(mmu.dma.current_byte(), 0x44);
+
+        // WRAM and echo RAM share the external bus with the source
+        assert_eq!(mmu.read_byte(0xD000), 0x44);
+        assert_eq!(mmu.read_byte(0xF000), 0x44);
+        mmu.write_byte(0xD000, 0x99);
+
+        // VRAM, IO and HRAM stay reachable
+        assert_eq!(mmu.read_byte(0x9000), 0x22);
+        assert_eq!(mmu.read_byte(0xFF80), 0x33);
+        mmu.write_byte(0xFF81, 0x55);
+        assert_eq!(mmu.read_byte(0xFF81), 0x55);
+        assert_eq!(mmu.read_byte(0xFF46), 0xC0);
+
+        m_cycles(&mut mmu, 160);
+        assert_eq!(mmu.read_byte(0xD000), 0x11);
+    }
+
+    #[test]
+    fn vram_source_blocks_only_the_video_bus() {
+        let mut mmu = mmu();
+        mmu.write_byte(0xFF46, 0x80);
+        m_cycles(&mut mmu, 3);
+
+        assert_eq!(mmu.read_byte(0x9000), 0x41);
+        mmu.write_byte(0x9000, 0x99);
+        assert_eq!(mmu.read_byte(0xD000), 0x11);
+        mmu.write_byte(0xD001, 0x77);
+        assert_eq!(mmu.read_byte(0xD001), 0x77);
+
+        m_cycles(&mut mmu, 160);
+        assert_eq!(mmu.read_byte(0x9000), 0x22);
+        assert_eq!(mmu.read_byte(0xFE01), 0x41);
+    }
+}
@@ -1,6 +1,41 @@
 use crate::registers::{Flag, Reg16, Reg8};
+use std::fmt;
 
 #[derive(Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    /// One of the opcodes with no instruction behind it, which lock up the
+    /// CPU on hardware.
+    IllegalOpcode(u8),
+    /// The buffer ends before the instruction does.
+    Truncated { needed: usize, available: usize },
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::IllegalOpcode(opcode) => write!(f, "illegal opcode {:#04x}", opcode),
+            DecodeError::Truncated { needed, available } => write!(
+                f,
+                "instruction is truncated: needs {} bytes, got {}",
+                needed, available
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// How long an instruction takes, in T-cycles.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Timing {
+    // Unconditional instructions, and conditional ones whose condition fails
+    pub cycles: usize,
+    // Conditional jumps, calls and returns whose condition holds
+    pub taken: usize,
+}
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum OP {
     // 8-bit Arithmetic and Logic
     AddR8(Reg8),
@@ -22,34 +57,34 @@ pub enum OP {
     IncR16(Reg16),
 
     // Bit Operations
-    // BitBR8(u8, Reg8),
-    // BitBHL(u8),
-    // ResBR8(u8, Reg8),
-    // ResBHL(u8),
-    // SetBR8(u8, Reg8),
-    // SetBHL(u8),
-    // SwapR8(Reg8),
-    // SwapHL,
+    BitBR8(u8, Reg8),
+    BitBHL(u8),
+    ResBR8(u8, Reg8),
+    ResBHL(u8),
+    SetBR8(u8, Reg8),
+    SetBHL(u8),
+    SwapR8(Reg8),
+    SwapHL,
 
     // Bit Shift
     RlR8(Reg8),
     RlA,
     RrR8(Reg8),
     RrA,
-    // RlHL,
-    // RlcR8(Reg8),
-    // RlcHL,
-    // RlcA,
-    // RrHL,
-    // RrcR8(Reg8),
-    // RrcHL,
-    // RrcA,
-    // SlaR8(Reg8),
-    // SlaHL,
-    // SrAR8(Reg8),
-    // SrAHL,
-    // SrlR8(Reg8),
-    // SrlHL,
+    RlHL,
+    RlcR8(Reg8),
+    RlcHL,
+    RlcA,
+    RrHL,
+    RrcR8(Reg8),
+    RrcHL,
+    RrcA,
+    SlaR8(Reg8),
+    SlaHL,
+    SrAR8(Reg8),
+    SrAHL,
+    SrlR8(Reg8),
+    SrlHL,
 
     // Load instructions
     LdR8R8(Reg8, Reg8),
@@ -88,6 +123,8 @@ pub enum OP {
     Nop,
     Scf,
     Stop,
+    // One of the opcodes that hang the CPU
+    Illegal(u8),
 
     AndR8(Reg8),
     AndR16(Reg16),
@@ -97,308 +134,556 @@ pub enum OP {
     OrR16(Reg16),
     CpR8(Reg8),
     CpR16(Reg16),
-    JPCondImm16(Flag),
-    CallCondImm16(Flag),
-    JPImm16,
-    AddImm8,
-    CBPrefix,
-    CallImm16,
-    AdcImm8,
-    SubImm8,
-    SbcImm8,
-    LdIOImm8A,
+    JPCondImm16(Flag, u16),
+    CallCondImm16(Flag, u16),
+    JPImm16(u16),
+    AddImm8(u8),
+    CallImm16(u16),
+    AdcImm8(u8),
+    SubImm8(u8),
+    SbcImm8(u8),
+    LdIOImm8A(u8),
     LdIOC,
-    AndImm8,
-    AddSPImm8,
-    LdImm16A,
+    AndImm8(u8),
+    AddSPImm8(u8),
+    LdImm16A(u16),
     JPHL,
-    XorImm8,
-    LdAIOImm8,
+    XorImm8(u8),
+    LdAIOImm8(u8),
     LdACIO,
-    OrImm8,
-    LdHLSPImm8,
-    LdAImm16,
-    CpImm8,
+    OrImm8(u8),
+    LdHLSPImm8(u8),
+    LdAImm16(u16),
+    CpImm8(u8),
 }
 
 impl OP {
-    pub fn from_bytes(bytes: &[u8]) -> Option<(OP, usize, usize)> {
-        let n = *bytes.get(1)?;
-        let n16 = ((*bytes.get(1)? as u16) << 8) | (*bytes.get(2)? as u16);
-        let rel = n as i8;
-
-        match bytes.get(0)? {
-            0x00 => Some((OP::Nop, 1, 4)),
-            0x01 => Some((OP::LdR16Imm(Reg16::BC, n16), 3, 12)),
-            0x02 => Some((OP::LdMemR8(Reg16::BC, Reg8::A), 1, 8)),
-            0x03 => Some((OP::IncR16(Reg16::BC), 1, 8)),
-            0x04 => Some((OP::IncR8(Reg8::B), 1, 4)),
-            0x05 => Some((OP::DecR8(Reg8::B), 1, 4)),
-            0x06 => Some((OP::LdR8Imm(Reg8::B, n), 2, 8)),
-            0x07 => Some((OP::RlR8(Reg8::A), 1, 4)),
-            0x08 => Some((OP::LdImmSP(n16), 3, 20)),
-            0x09 => Some((OP::AddHLR16(Reg16::BC), 1, 8)),
-            0x0A => Some((OP::LdR8Mem(Reg8::A, Reg16::BC), 1, 8)),
-            0x0B => Some((OP::DecR16(Reg16::BC), 1, 8)),
-            0x0C => Some((OP::IncR8(Reg8::C), 1, 4)),
-            0x0D => Some((OP::DecR8(Reg8::C), 1, 4)),
-            0x0E => Some((OP::LdR8Imm(Reg8::C, n), 2, 8)),
-            0x0F => Some((OP::RrR8(Reg8::A), 1, 4)),
-
-            0x10 => Some((OP::Stop, 2, 4)),
-            0x11 => Some((OP::LdR16Imm(Reg16::DE, n16), 3, 12)),
-            0x12 => Some((OP::LdMemR8(Reg16::DE, Reg8::A), 1, 8)),
-            0x13 => Some((OP::IncR16(Reg16::DE), 1, 8)),
-            0x14 => Some((OP::IncR8(Reg8::D), 1, 4)),
-            0x15 => Some((OP::DecR8(Reg8::D), 1, 4)),
-            0x16 => Some((OP::LdR8Imm(Reg8::D, n), 2, 8)),
-            0x17 => Some((OP::RlA, 1, 4)),
-            0x18 => Some((OP::Jr(rel as u16), 2, 12)),
-            0x19 => Some((OP::AddHLR16(Reg16::DE), 1, 8)),
-            0x1A => Some((OP::LdR8Mem(Reg8::A, Reg16::DE), 1, 8)),
-            0x1B => Some((OP::DecR16(Reg16::DE), 1, 8)),
-            0x1C => Some((OP::IncR8(Reg8::E), 1, 4)),
-            0x1D => Some((OP::DecR8(Reg8::E), 1, 4)),
-            0x1E => Some((OP::LdR8Imm(Reg8::E, n), 2, 8)),
-            0x1F => Some((OP::RrA, 1, 4)),
-
-            0x20 => Some((OP::JrCond(Flag::NZ, rel as u16), 2, 8)),
-            0x21 => Some((OP::LdR16Imm(Reg16::HL, n16), 3, 12)),
-            0x22 => Some((OP::LdHLIA, 1, 8)),
-            0x23 => Some((OP::IncR16(Reg16::HL), 1, 8)),
-            0x24 => Some((OP::IncR8(Reg8::H), 1, 4)),
-            0x25 => Some((OP::DecR8(Reg8::H), 1, 4)),
-            0x26 => Some((OP::LdR8Imm(Reg8::H, n), 2, 8)),
-            0x27 => Some((OP::Daa, 1, 4)),
-            0x28 => Some((OP::JrCond(Flag::Z, rel as u16), 2, 8)),
-            0x29 => Some((OP::AddHLR16(Reg16::HL), 1, 8)),
-            0x2A => Some((OP::LdAHLI, 1, 8)),
-            0x2B => Some((OP::DecR16(Reg16::HL), 1, 8)),
-            0x2C => Some((OP::IncR8(Reg8::L), 1, 4)),
-            0x2D => Some((OP::DecR8(Reg8::L), 1, 4)),
-            0x2E => Some((OP::LdR8Imm(Reg8::L, n), 2, 8)),
-            0x2F => Some((OP::Cpl, 1, 4)),
-
-            0x30 => Some((OP::JrCond(Flag::NC, rel as u16), 2, 8)),
-            0x31 => Some((OP::LdR16Imm(Reg16::SP, n16), 3, 12)),
-            0x32 => Some((OP::LdHLDA, 1, 8)),
-            0x33 => Some((OP::IncR16(Reg16::SP), 1, 8)),
-            0x34 => Some((OP::IncHL, 1, 12)),
-            0x35 => Some((OP::DecHL, 1, 12)),
-            0x36 => Some((OP::LdHLImm(n), 2, 12)),
-            0x37 => Some((OP::Scf, 1, 4)),
-            0x38 => Some((OP::JrCond(Flag::C, rel as u16), 2, 8)),
-            0x39 => Some((OP::AddHLR16(Reg16::SP), 1, 8)),
-            0x3A => Some((OP::LdAHLD, 1, 8)),
-            0x3B => Some((OP::DecR16(Reg16::SP), 1, 8)),
-            0x3C => Some((OP::IncR8(Reg8::A), 1, 4)),
-            0x3D => Some((OP::DecR8(Reg8::A), 1, 4)),
-            0x3E => Some((OP::LdR8Imm(Reg8::A, n), 2, 8)),
-            0x3F => Some((OP::Ccf, 1, 4)),
-
-            0x40 => Some((OP::LdR8R8(Reg8::B, Reg8::B), 1, 4)),
-            0x41 => Some((OP::LdR8R8(Reg8::B, Reg8::C), 1, 4)),
-            0x42 => Some((OP::LdR8R8(Reg8::B, Reg8::D), 1, 4)),
-            0x43 => Some((OP::LdR8R8(Reg8::B, Reg8::E), 1, 4)),
-            0x44 => Some((OP::LdR8R8(Reg8::B, Reg8::H), 1, 4)),
-            0x45 => Some((OP::LdR8R8(Reg8::B, Reg8::L), 1, 4)),
-            0x46 => Some((OP::LdR8Mem(Reg8::B, Reg16::HL), 1, 8)),
-            0x47 => Some((OP::LdR8R8(Reg8::B, Reg8::A), 1, 4)),
-            0x48 => Some((OP::LdR8R8(Reg8::C, Reg8::B), 1, 4)),
-            0x49 => Some((OP::LdR8R8(Reg8::C, Reg8::C), 1, 4)),
-            0x4A => Some((OP::LdR8R8(Reg8::C, Reg8::D), 1, 4)),
-            0x4B => Some((OP::LdR8R8(Reg8::C, Reg8::E), 1, 4)),
-            0x4C => Some((OP::LdR8R8(Reg8::C, Reg8::H), 1, 4)),
-            0x4D => Some((OP::LdR8R8(Reg8::C, Reg8::L), 1, 4)),
-            0x4E => Some((OP::LdR8Mem(Reg8::C, Reg16::HL), 1, 8)),
-            0x4F => Some((OP::LdR8R8(Reg8::C, Reg8::A), 1, 4)),
-
-            0x50 => Some((OP::LdR8R8(Reg8::D, Reg8::B), 1, 4)),
-            0x51 => Some((OP::LdR8R8(Reg8::D, Reg8::C), 1, 4)),
-            0x52 => Some((OP::LdR8R8(Reg8::D, Reg8::D), 1, 4)),
-            0x53 => Some((OP::LdR8R8(Reg8::D, Reg8::E), 1, 4)),
-            0x54 => Some((OP::LdR8R8(Reg8::D, Reg8::H), 1, 4)),
-            0x55 => Some((OP::LdR8R8(Reg8::D, Reg8::L), 1, 4)),
-            0x56 => Some((OP::LdR8Mem(Reg8::D, Reg16::HL), 1, 8)),
-            0x57 => Some((OP::LdR8R8(Reg8::D, Reg8::A), 1, 4)),
-            0x58 => Some((OP::LdR8R8(Reg8::E, Reg8::B), 1, 4)),
-            0x59 => Some((OP::LdR8R8(Reg8::E, Reg8::C), 1, 4)),
-            0x5A => Some((OP::LdR8R8(Reg8::E, Reg8::D), 1, 4)),
-            0x5B => Some((OP::LdR8R8(Reg8::E, Reg8::E), 1, 4)),
-            0x5C => Some((OP::LdR8R8(Reg8::E, Reg8::H), 1, 4)),
-            0x5D => Some((OP::LdR8R8(Reg8::E, Reg8::L), 1, 4)),
-            0x5E => Some((OP::LdR8Mem(Reg8::E, Reg16::HL), 1, 8)),
-            0x5F => Some((OP::LdR8R8(Reg8::E, Reg8::A), 1, 4)),
-
-            0x60 => Some((OP::LdR8R8(Reg8::H, Reg8::B), 1, 4)),
-            0x61 => Some((OP::LdR8R8(Reg8::H, Reg8::C), 1, 4)),
-            0x62 => Some((OP::LdR8R8(Reg8::H, Reg8::D), 1, 4)),
-            0x63 => Some((OP::LdR8R8(Reg8::H, Reg8::E), 1, 4)),
-            0x64 => Some((OP::LdR8R8(Reg8::H, Reg8::H), 1, 4)),
-            0x65 => Some((OP::LdR8R8(Reg8::H, Reg8::L), 1, 4)),
-            0x66 => Some((OP::LdR8Mem(Reg8::H, Reg16::HL), 1, 8)),
-            0x67 => Some((OP::LdR8R8(Reg8::H, Reg8::A), 1, 4)),
-            0x68 => Some((OP::LdR8R8(Reg8::L, Reg8::B), 1, 4)),
-            0x69 => Some((OP::LdR8R8(Reg8::L, Reg8::C), 1, 4)),
-            0x6A => Some((OP::LdR8R8(Reg8::L, Reg8::D), 1, 4)),
-            0x6B => Some((OP::LdR8R8(Reg8::L, Reg8::E), 1, 4)),
-            0x6C => Some((OP::LdR8R8(Reg8::L, Reg8::H), 1, 4)),
-            0x6D => Some((OP::LdR8R8(Reg8::L, Reg8::L), 1, 4)),
-            0x6E => Some((OP::LdR8Mem(Reg8::L, Reg16::HL), 1, 8)),
-            0x6F => Some((OP::LdR8R8(Reg8::L, Reg8::A), 1, 4)),
-
-            0x70 => Some((OP::LdR16R8(Reg16::HL, Reg8::B), 1, 8)),
-            0x71 => Some((OP::LdR16R8(Reg16::HL, Reg8::C), 1, 8)),
-            0x72 => Some((OP::LdR16R8(Reg16::HL, Reg8::D), 1, 8)),
-            0x73 => Some((OP::LdR16R8(Reg16::HL, Reg8::E), 1, 8)),
-            0x74 => Some((OP::LdR16R8(Reg16::HL, Reg8::H), 1, 8)),
-            0x75 => Some((OP::LdR16R8(Reg16::HL, Reg8::L), 1, 8)),
-            0x76 => Some((OP::Halt, 1, 4)),
-            0x77 => Some((OP::LdR16R8(Reg16::HL, Reg8::A), 1, 8)),
-            0x78 => Some((OP::LdR8R8(Reg8::A, Reg8::B), 1, 4)),
-            0x79 => Some((OP::LdR8R8(Reg8::A, Reg8::C), 1, 4)),
-            0x7A => Some((OP::LdR8R8(Reg8::A, Reg8::D), 1, 4)),
-            0x7B => Some((OP::LdR8R8(Reg8::A, Reg8::E), 1, 4)),
-            0x7C => Some((OP::LdR8R8(Reg8::A, Reg8::H), 1, 4)),
-            0x7D => Some((OP::LdR8R8(Reg8::A, Reg8::L), 1, 4)),
-            0x7E => Some((OP::LdR8Mem(Reg8::A, Reg16::HL), 1, 8)),
-            0x7F => Some((OP::LdR8R8(Reg8::A, Reg8::A), 1, 4)),
-
-            0x80 => Some((OP::AddR8(Reg8::B), 1, 4)),
-            0x81 => Some((OP::AddR8(Reg8::C), 1, 4)),
-            0x82 => Some((OP::AddR8(Reg8::D), 1, 4)),
-            0x83 => Some((OP::AddR8(Reg8::E), 1, 4)),
-            0x84 => Some((OP::AddR8(Reg8::H), 1, 4)),
-            0x85 => Some((OP::AddR8(Reg8::L), 1, 4)),
-            0x86 => Some((OP::AddR16(Reg16::HL), 1, 8)),
-            0x87 => Some((OP::AddR8(Reg8::A), 1, 4)),
-            0x88 => Some((OP::AdcR8(Reg8::B), 1, 4)),
-            0x89 => Some((OP::AdcR8(Reg8::C), 1, 4)),
-            0x8A => Some((OP::AdcR8(Reg8::D), 1, 4)),
-            0x8B => Some((OP::AdcR8(Reg8::E), 1, 4)),
-            0x8C => Some((OP::AdcR8(Reg8::H), 1, 4)),
-            0x8D => Some((OP::AdcR8(Reg8::L), 1, 4)),
-            0x8E => Some((OP::AdcR16(Reg16::HL), 1, 8)),
-            0x8F => Some((OP::AdcR8(Reg8::A), 1, 4)),
-
-            0x90 => Some((OP::SubR8(Reg8::B), 1, 4)),
-            0x91 => Some((OP::SubR8(Reg8::C), 1, 4)),
-            0x92 => Some((OP::SubR8(Reg8::D), 1, 4)),
-            0x93 => Some((OP::SubR8(Reg8::E), 1, 4)),
-            0x94 => Some((OP::SubR8(Reg8::H), 1, 4)),
-            0x95 => Some((OP::SubR8(Reg8::L), 1, 4)),
-            0x96 => Some((OP::SubR16(Reg16::HL), 1, 8)),
-            0x97 => Some((OP::SubR8(Reg8::A), 1, 4)),
-            0x98 => Some((OP::SbcR8(Reg8::B), 1, 4)),
-            0x99 => Some((OP::SbcR8(Reg8::C), 1, 4)),
-            0x9A => Some((OP::SbcR8(Reg8::D), 1, 4)),
-            0x9B => Some((OP::SbcR8(Reg8::E), 1, 4)),
-            0x9C => Some((OP::SbcR8(Reg8::H), 1, 4)),
-            0x9D => Some((OP::SbcR8(Reg8::L), 1, 4)),
-            0x9E => Some((OP::SbcR16(Reg16::HL), 1, 8)),
-            0x9F => Some((OP::SbcR8(Reg8::A), 1, 4)),
-
-            0xA0 => Some((OP::AndR8(Reg8::B), 1, 4)),
-            0xA1 => Some((OP::AndR8(Reg8::C), 1, 4)),
-            0xA2 => Some((OP::AndR8(Reg8::D), 1, 4)),
-            0xA3 => Some((OP::AndR8(Reg8::E), 1, 4)),
-            0xA4 => Some((OP::AndR8(Reg8::H), 1, 4)),
-            0xA5 => Some((OP::AndR8(Reg8::L), 1, 4)),
-            0xA6 => Some((OP::AndR16(Reg16::HL), 1, 8)),
-            0xA7 => Some((OP::AndR8(Reg8::A), 1, 4)),
-            0xA8 => Some((OP::XorR8(Reg8::B), 1, 4)),
-            0xA9 => Some((OP::XorR8(Reg8::C), 1, 4)),
-            0xAA => Some((OP::XorR8(Reg8::D), 1, 4)),
-            0xAB => Some((OP::XorR8(Reg8::E), 1, 4)),
-            0xAC => Some((OP::XorR8(Reg8::H), 1, 4)),
-            0xAD => Some((OP::XorR8(Reg8::L), 1, 4)),
-            0xAE => Some((OP::XorR16(Reg16::HL), 1, 8)),
-            0xAF => Some((OP::XorR8(Reg8::A), 1, 4)),
-
-            0xB0 => Some((OP::OrR8(Reg8::B), 1, 4)),
-            0xB1 => Some((OP::OrR8(Reg8::C), 1, 4)),
-            0xB2 => Some((OP::OrR8(Reg8::D), 1, 4)),
-            0xB3 => Some((OP::OrR8(Reg8::E), 1, 4)),
-            0xB4 => Some((OP::OrR8(Reg8::H), 1, 4)),
-            0xB5 => Some((OP::OrR8(Reg8::L), 1, 4)),
-            0xB6 => Some((OP::OrR16(Reg16::HL), 1, 8)),
-            0xB7 => Some((OP::OrR8(Reg8::A), 1, 4)),
-            0xB8 => Some((OP::CpR8(Reg8::B), 1, 4)),
-            0xB9 => Some((OP::CpR8(Reg8::C), 1, 4)),
-            0xBA => Some((OP::CpR8(Reg8::D), 1, 4)),
-            0xBB => Some((OP::CpR8(Reg8::E), 1, 4)),
-            0xBC => Some((OP::CpR8(Reg8::H), 1, 4)),
-            0xBD => Some((OP::CpR8(Reg8::L), 1, 4)),
-            0xBE => Some((OP::CpR16(Reg16::HL), 1, 8)),
-            0xBF => Some((OP::CpR8(Reg8::A), 1, 4)),
-
-            0xC0 => Some((OP::RetCond(Flag::NZ), 1, 20)),
-            0xC1 => Some((OP::PopR16(Reg16::BC), 1, 12)),
-            0xC2 => Some((OP::JPCondImm16(Flag::NZ), 3, 16)),
-            0xC3 => Some((OP::JPImm16, 3, 16)),
-            0xC4 => Some((OP::CallCondImm16(Flag::NZ), 3, 24)),
-            0xC5 => Some((OP::PushR16(Reg16::BC), 1, 16)),
-            0xC6 => Some((OP::AddImm8, 2, 8)),
-            0xC7 => Some((OP::Rst(0x00), 1, 16)),
-            0xC8 => Some((OP::RetCond(Flag::Z), 1, 8)),
-            0xC9 => Some((OP::Ret, 1, 16)),
-            0xCA => Some((OP::JPCondImm16(Flag::Z), 3, 16)),
-            0xCB => Some((OP::CBPrefix, 1, 4)),
-            0xCC => Some((OP::CallCondImm16(Flag::Z), 3, 24)),
-            0xCD => Some((OP::CallImm16, 3, 24)),
-            0xCE => Some((OP::AdcImm8, 2, 8)),
-            0xCF => Some((OP::Rst(0x08), 1, 16)),
-
-            0xD0 => Some((OP::RetCond(Flag::NC), 1, 20)),
-            0xD1 => Some((OP::PopR16(Reg16::DE), 1, 12)),
-            0xD2 => Some((OP::JPCondImm16(Flag::NC), 3, 16)),
-            0xD3 => None,
-            0xD4 => Some((OP::CallCondImm16(Flag::NC), 3, 24)),
-            0xD5 => Some((OP::PushR16(Reg16::DE), 1, 16)),
-            0xD6 => Some((OP::SubImm8, 2, 8)),
-            0xD7 => Some((OP::Rst(0x10), 1, 16)),
-            0xD8 => Some((OP::RetCond(Flag::C), 1, 20)),
-            0xD9 => Some((OP::Reti, 1, 16)),
-            0xDA => Some((OP::JPCondImm16(Flag::C), 3, 16)),
-            0xDB => None,
-            0xDC => Some((OP::CallCondImm16(Flag::C), 3, 24)),
-            0xDD => None,
-            0xDE => Some((OP::SbcImm8, 2, 8)),
-            0xDF => Some((OP::Rst(0x18), 1, 16)),
-
-            0xE0 => Some((OP::LdIOImm8A, 2, 12)),
-            0xE1 => Some((OP::PopR16(Reg16::HL), 1, 12)),
-            0xE2 => Some((OP::LdIOC, 1, 8)),
-            0xE3 => None,
-            0xE4 => None,
-            0xE5 => Some((OP::PushR16(Reg16::HL), 1, 16)),
-            0xE6 => Some((OP::AndImm8, 2, 8)),
-            0xE7 => Some((OP::Rst(0x20), 1, 16)),
-            0xE8 => Some((OP::AddSPImm8, 2, 16)),
-            0xE9 => Some((OP::JPHL, 1, 4)),
-            0xEA => Some((OP::LdImm16A, 3, 16)),
-            0xEB => None,
-            0xEC => None,
-            0xED => None,
-            0xEE => Some((OP::XorImm8, 2, 8)),
-            0xEF => Some((OP::Rst(0x28), 1, 16)),
-
-            0xF0 => Some((OP::LdAIOImm8, 2, 12)),
-            0xF1 => Some((OP::PopR16(Reg16::AF), 1, 12)),
-            0xF2 => Some((OP::LdACIO, 1, 8)),
-            0xF3 => Some((OP::Di, 1, 4)),
-            0xF4 => None,
-            0xF5 => Some((OP::PushR16(Reg16::AF), 1, 16)),
-            0xF6 => Some((OP::OrImm8, 2, 8)),
-            0xF7 => Some((OP::Rst(0x30), 1, 16)),
-            0xF8 => Some((OP::LdHLSPImm8, 2, 12)),
-            0xF9 => Some((OP::LdSPHL, 1, 8)),
-            0xFA => Some((OP::LdAImm16, 3, 16)),
-            0xFB => Some((OP::Ei, 1, 4)),
-            0xFC => None,
-            0xFD => None,
-            0xFE => Some((OP::CpImm8, 2, 8)),
-            0xFF => Some((OP::Rst(0x38), 1, 16)),
+    /// Decodes the instruction at the start of `bytes`, returning it along
+    /// with its size in bytes and its timing. Only the bytes the opcode
+    /// actually needs are looked at, and 16-bit operands are little-endian.
+    pub fn from_bytes(bytes: &[u8]) -> Result<(OP, usize, Timing), DecodeError> {
+        let byte = |index: usize| {
+            bytes.get(index).copied().ok_or(DecodeError::Truncated {
+                needed: index + 1,
+                available: bytes.len(),
+            })
+        };
+        let n = || byte(1);
+        let n16 = || Ok(u16::from_le_bytes([byte(1)?, byte(2)?]));
+        let rel = || Ok(byte(1)? as i8);
+
+        let (op, size, cycles) = match byte(0)? {
+            0x00 => (OP::Nop, 1, 4),
+            0x01 => (OP::LdR16Imm(Reg16::BC, n16()?), 3, 12),
+            0x02 => (OP::LdMemR8(Reg16::BC, Reg8::A), 1, 8),
+            0x03 => (OP::IncR16(Reg16::BC), 1, 8),
+            0x04 => (OP::IncR8(Reg8::B), 1, 4),
+            0x05 => (OP::DecR8(Reg8::B), 1, 4),
+            0x06 => (OP::LdR8Imm(Reg8::B, n()?), 2, 8),
+            0x07 => (OP::RlcA, 1, 4),
+            0x08 => (OP::LdImmSP(n16()?), 3, 20),
+            0x09 => (OP::AddHLR16(Reg16::BC), 1, 8),
+            0x0A => (OP::LdR8Mem(Reg8::A, Reg16::BC), 1, 8),
+            0x0B => (OP::DecR16(Reg16::BC), 1, 8),
+            0x0C => (OP::IncR8(Reg8::C), 1, 4),
+            0x0D => (OP::DecR8(Reg8::C), 1, 4),
+            0x0E => (OP::LdR8Imm(Reg8::C, n()?), 2, 8),
+            0x0F => (OP::RrcA, 1, 4),
+
+            0x10 => (OP::Stop, 2, 4),
+            0x11 => (OP::LdR16Imm(Reg16::DE, n16()?), 3, 12),
+            0x12 => (OP::LdMemR8(Reg16::DE, Reg8::A), 1, 8),
+            0x13 => (OP::IncR16(Reg16::DE), 1, 8),
+            0x14 => (OP::IncR8(Reg8::D), 1, 4),
+            0x15 => (OP::DecR8(Reg8::D), 1, 4),
+            0x16 => (OP::LdR8Imm(Reg8::D, n()?), 2, 8),
+            0x17 => (OP::RlA, 1, 4),
+            0x18 => (OP::Jr(rel()? as u16), 2, 12),
+            0x19 => (OP::AddHLR16(Reg16::DE), 1, 8),
+            0x1A => (OP::LdR8Mem(Reg8::A, Reg16::DE), 1, 8),
+            0x1B => (OP::DecR16(Reg16::DE), 1, 8),
+            0x1C => (OP::IncR8(Reg8::E), 1, 4),
+            0x1D => (OP::DecR8(Reg8::E), 1, 4),
+            0x1E => (OP::LdR8Imm(Reg8::E, n()?), 2, 8),
+            0x1F => (OP::RrA, 1, 4),
+
+            0x20 => (OP::JrCond(Flag::NZ, rel()? as u16), 2, 8),
+            0x21 => (OP::LdR16Imm(Reg16::HL, n16()?), 3, 12),
+            0x22 => (OP::LdHLIA, 1, 8),
+            0x23 => (OP::IncR16(Reg16::HL), 1, 8),
+            0x24 => (OP::IncR8(Reg8::H), 1, 4),
+            0x25 => (OP::DecR8(Reg8::H), 1, 4),
+            0x26 => (OP::LdR8Imm(Reg8::H, n()?), 2, 8),
+            0x27 => (OP::Daa, 1, 4),
+            0x28 => (OP::JrCond(Flag::Z, rel()? as u16), 2, 8),
+            0x29 => (OP::AddHLR16(Reg16::HL), 1, 8),
+            0x2A => (OP::LdAHLI, 1, 8),
+            0x2B => (OP::DecR16(Reg16::HL), 1, 8),
+            0x2C => (OP::IncR8(Reg8::L), 1, 4),
+            0x2D => (OP::DecR8(Reg8::L), 1, 4),
+            0x2E => (OP::LdR8Imm(Reg8::L, n()?), 2, 8),
+            0x2F => (OP::Cpl, 1, 4),
+
+            0x30 => (OP::JrCond(Flag::NC, rel()? as u16), 2, 8),
+            0x31 => (OP::LdR16Imm(Reg16::SP, n16()?), 3, 12),
+            0x32 => (OP::LdHLDA, 1, 8),
+            0x33 => (OP::IncR16(Reg16::SP), 1, 8),
+            0x34 => (OP::IncHL, 1, 12),
+            0x35 => (OP::DecHL, 1, 12),
+            0x36 => (OP::LdHLImm(n()?), 2, 12),
+            0x37 => (OP::Scf, 1, 4),
+            0x38 => (OP::JrCond(Flag::C, rel()? as u16), 2, 8),
+            0x39 => (OP::AddHLR16(Reg16::SP), 1, 8),
+            0x3A => (OP::LdAHLD, 1, 8),
+            0x3B => (OP::DecR16(Reg16::SP), 1, 8),
+            0x3C => (OP::IncR8(Reg8::A), 1, 4),
+            0x3D => (OP::DecR8(Reg8::A), 1, 4),
+            0x3E => (OP::LdR8Imm(Reg8::A, n()?), 2, 8),
+            0x3F => (OP::Ccf, 1, 4),
+
+            0x40 => (OP::LdR8R8(Reg8::B, Reg8::B), 1, 4),
+            0x41 => (OP::LdR8R8(Reg8::B, Reg8::C), 1, 4),
+            0x42 => (OP::LdR8R8(Reg8::B, Reg8::D), 1, 4),
+            0x43 => (OP::LdR8R8(Reg8::B, Reg8::E), 1, 4),
+            0x44 => (OP::LdR8R8(Reg8::B, Reg8::H), 1, 4),
+            0x45 => (OP::LdR8R8(Reg8::B, Reg8::L), 1, 4),
+            0x46 => (OP::LdR8Mem(Reg8::B, Reg16::HL), 1, 8),
+            0x47 => (OP::LdR8R8(Reg8::B, Reg8::A), 1, 4),
+            0x48 => (OP::LdR8R8(Reg8::C, Reg8::B), 1, 4),
+            0x49 => (OP::LdR8R8(Reg8::C, Reg8::C), 1, 4),
+            0x4A => (OP::LdR8R8(Reg8::C, Reg8::D), 1, 4),
+            0x4B => (OP::LdR8R8(Reg8::C, Reg8::E), 1, 4),
+            0x4C => (OP::LdR8R8(Reg8::C, Reg8::H), 1, 4),
+            0x4D => (OP::LdR8R8(Reg8::C, Reg8::L), 1, 4),
+            0x4E => (OP::LdR8Mem(Reg8::C, Reg16::HL), 1, 8),
+            0x4F => (OP::LdR8R8(Reg8::C, Reg8::A), 1, 4),
+
+            0x50 => (OP::LdR8R8(Reg8::D, Reg8::B), 1, 4),
+            0x51 => (OP::LdR8R8(Reg8::D, Reg8::C), 1, 4),
+            0x52 => (OP::LdR8R8(Reg8::D, Reg8::D), 1, 4),
+            0x53 => (OP::LdR8R8(Reg8::D, Reg8::E), 1, 4),
+            0x54 => (OP::LdR8R8(Reg8::D, Reg8::H), 1, 4),
+            0x55 => (OP::LdR8R8(Reg8::D, Reg8::L), 1, 4),
+            0x56 => (OP::LdR8Mem(Reg8::D, Reg16::HL), 1, 8),
+            0x57 => (OP::LdR8R8(Reg8::D, Reg8::A), 1, 4),
+            0x58 => (OP::LdR8R8(Reg8::E, Reg8::B), 1, 4),
+            0x59 => (OP::LdR8R8(Reg8::E, Reg8::C), 1, 4),
+            0x5A => (OP::LdR8R8(Reg8::E, Reg8::D), 1, 4),
+            0x5B => (OP::LdR8R8(Reg8::E, Reg8::E), 1, 4),
+            0x5C => (OP::LdR8R8(Reg8::E, Reg8::H), 1, 4),
+            0x5D => (OP::LdR8R8(Reg8::E, Reg8::L), 1, 4),
+            0x5E => (OP::LdR8Mem(Reg8::E, Reg16::HL), 1, 8),
+            0x5F => (OP::LdR8R8(Reg8::E, Reg8::A), 1, 4),
+
+            0x60 => (OP::LdR8R8(Reg8::H, Reg8::B), 1, 4),
+            0x61 => (OP::LdR8R8(Reg8::H, Reg8::C), 1, 4),
+            0x62 => (OP::LdR8R8(Reg8::H, Reg8::D), 1, 4),
+            0x63 => (OP::LdR8R8(Reg8::H, Reg8::E), 1, 4),
+            0x64 => (OP::LdR8R8(Reg8::H, Reg8::H), 1, 4),
+            0x65 => (OP::LdR8R8(Reg8::H, Reg8::L), 1, 4),
+            0x66 => (OP::LdR8Mem(Reg8::H, Reg16::HL), 1, 8),
+            0x67 => (OP::LdR8R8(Reg8::H, Reg8::A), 1, 4),
+            0x68 => (OP::LdR8R8(Reg8::L, Reg8::B), 1, 4),
+            0x69 => (OP::LdR8R8(Reg8::L, Reg8::C), 1, 4),
+            0x6A => (OP::LdR8R8(Reg8::L, Reg8::D), 1, 4),
+            0x6B => (OP::LdR8R8(Reg8::L, Reg8::E), 1, 4),
+            0x6C => (OP::LdR8R8(Reg8::L, Reg8::H), 1, 4),
+            0x6D => (OP::LdR8R8(Reg8::L, Reg8::L), 1, 4),
+            0x6E => (OP::LdR8Mem(Reg8::L, Reg16::HL), 1, 8),
+            0x6F => (OP::LdR8R8(Reg8::L, Reg8::A), 1, 4),
+
+            0x70 => (OP::LdR16R8(Reg16::HL, Reg8::B), 1, 8),
+            0x71 => (OP::LdR16R8(Reg16::HL, Reg8::C), 1, 8),
+            0x72 => (OP::LdR16R8(Reg16::HL, Reg8::D), 1, 8),
+            0x73 => (OP::LdR16R8(Reg16::HL, Reg8::E), 1, 8),
+            0x74 => (OP::LdR16R8(Reg16::HL, Reg8::H), 1, 8),
+            0x75 => (OP::LdR16R8(Reg16::HL, Reg8::L), 1, 8),
+            0x76 => (OP::Halt, 1, 4),
+            0x77 => (OP::LdR16R8(Reg16::HL, Reg8::A), 1, 8),
+            0x78 => (OP::LdR8R8(Reg8::A, Reg8::B), 1, 4),
+            0x79 => (OP::LdR8R8(Reg8::A, Reg8::C), 1, 4),
+            0x7A => (OP::LdR8R8(Reg8::A, Reg8::D), 1, 4),
+            0x7B => (OP::LdR8R8(Reg8::A, Reg8::E), 1, 4),
+            0x7C => (OP::LdR8R8(Reg8::A, Reg8::H), 1, 4),
+            0x7D => (OP::LdR8R8(Reg8::A, Reg8::L), 1, 4),
+            0x7E => (OP::LdR8Mem(Reg8::A, Reg16::HL), 1, 8),
+            0x7F => (OP::LdR8R8(Reg8::A, Reg8::A), 1, 4),
+
+            0x80 => (OP::AddR8(Reg8::B), 1, 4),
+            0x81 => (OP::AddR8(Reg8::C), 1, 4),
+            0x82 => (OP::AddR8(Reg8::D), 1, 4),
+            0x83 => (OP::AddR8(Reg8::E), 1, 4),
+            0x84 => (OP::AddR8(Reg8::H), 1, 4),
+            0x85 => (OP::AddR8(Reg8::L), 1, 4),
+            0x86 => (OP::AddR16(Reg16::HL), 1, 8),
+            0x87 => (OP::AddR8(Reg8::A), 1, 4),
+            0x88 => (OP::AdcR8(Reg8::B), 1, 4),
+            0x89 => (OP::AdcR8(Reg8::C), 1, 4),
+            0x8A => (OP::AdcR8(Reg8::D), 1, 4),
+            0x8B => (OP::AdcR8(Reg8::E), 1, 4),
+            0x8C => (OP::AdcR8(Reg8::H), 1, 4),
+            0x8D => (OP::AdcR8(Reg8::L), 1, 4),
+            0x8E => (OP::AdcR16(Reg16::HL), 1, 8),
+            0x8F => (OP::AdcR8(Reg8::A), 1, 4),
+
+            0x90 => (OP::SubR8(Reg8::B), 1, 4),
+            0x91 => (OP::SubR8(Reg8::C), 1, 4),
+            0x92 => (OP::SubR8(Reg8::D), 1, 4),
+            0x93 => (OP::SubR8(Reg8::E), 1, 4),
+            0x94 => (OP::SubR8(Reg8::H), 1, 4),
+            0x95 => (OP::SubR8(Reg8::L), 1, 4),
+            0x96 => (OP::SubR16(Reg16::HL), 1, 8),
+            0x97 => (OP::SubR8(Reg8::A), 1, 4),
+            0x98 => (OP::SbcR8(Reg8::B), 1, 4),
+            0x99 => (OP::SbcR8(Reg8::C), 1, 4),
+            0x9A => (OP::SbcR8(Reg8::D), 1, 4),
+            0x9B => (OP::SbcR8(Reg8::E), 1, 4),
+            0x9C => (OP::SbcR8(Reg8::H), 1, 4),
+            0x9D => (OP::SbcR8(Reg8::L), 1, 4),
+            0x9E => (OP::SbcR16(Reg16::HL), 1, 8),
+            0x9F => (OP::SbcR8(Reg8::A), 1, 4),
+
+            0xA0 => (OP::AndR8(Reg8::B), 1, 4),
+            0xA1 => (OP::AndR8(Reg8::C), 1, 4),
+            0xA2 => (OP::AndR8(Reg8::D), 1, 4),
+            0xA3 => (OP::AndR8(Reg8::E), 1, 4),
+            0xA4 => (OP::AndR8(Reg8::H), 1, 4),
+            0xA5 => (OP::AndR8(Reg8::L), 1, 4),
+            0xA6 => (OP::AndR16(Reg16::HL), 1, 8),
+            0xA7 => (OP::AndR8(Reg8::A), 1, 4),
+            0xA8 => (OP::XorR8(Reg8::B), 1, 4),
+            0xA9 => (OP::XorR8(Reg8::C), 1, 4),
+            0xAA => (OP::XorR8(Reg8::D), 1, 4),
+            0xAB => (OP::XorR8(Reg8::E), 1, 4),
+            0xAC => (OP::XorR8(Reg8::H), 1, 4),
+            0xAD => (OP::XorR8(Reg8::L), 1, 4),
+            0xAE => (OP::XorR16(Reg16::HL), 1, 8),
+            0xAF => (OP::XorR8(Reg8::A), 1, 4),
+
+            0xB0 => (OP::OrR8(Reg8::B), 1, 4),
+            0xB1 => (OP::OrR8(Reg8::C), 1, 4),
+            0xB2 => (OP::OrR8(Reg8::D), 1, 4),
+            0xB3 => (OP::OrR8(Reg8::E), 1, 4),
+            0xB4 => (OP::OrR8(Reg8::H), 1, 4),
+            0xB5 => (OP::OrR8(Reg8::L), 1, 4),
+            0xB6 => (OP::OrR16(Reg16::HL), 1, 8),
+            0xB7 => (OP::OrR8(Reg8::A), 1, 4),
+            0xB8 => (OP::CpR8(Reg8::B), 1, 4),
+            0xB9 => (OP::CpR8(Reg8::C), 1, 4),
+            0xBA => (OP::CpR8(Reg8::D), 1, 4),
+            0xBB => (OP::CpR8(Reg8::E), 1, 4),
+            0xBC => (OP::CpR8(Reg8::H), 1, 4),
+            0xBD => (OP::CpR8(Reg8::L), 1, 4),
+            0xBE => (OP::CpR16(Reg16::HL), 1, 8),
+            0xBF => (OP::CpR8(Reg8::A), 1, 4),
+
+            0xC0 => (OP::RetCond(Flag::NZ), 1, 8),
+            0xC1 => (OP::PopR16(Reg16::BC), 1, 12),
+            0xC2 => (OP::JPCondImm16(Flag::NZ, n16()?), 3, 12),
+            0xC3 => (OP::JPImm16(n16()?), 3, 16),
+            0xC4 => (OP::CallCondImm16(Flag::NZ, n16()?), 3, 12),
+            0xC5 => (OP::PushR16(Reg16::BC), 1, 16),
+            0xC6 => (OP::AddImm8(n()?), 2, 8),
+            0xC7 => (OP::Rst(0x00), 1, 16),
+            0xC8 => (OP::RetCond(Flag::Z), 1, 8),
+            0xC9 => (OP::Ret, 1, 16),
+            0xCA => (OP::JPCondImm16(Flag::Z, n16()?), 3, 12),
+            0xCB => {
+                let (op, duration) = OP::from_cb(n()?);
+                (op, 2, duration)
+            }
+            0xCC => (OP::CallCondImm16(Flag::Z, n16()?), 3, 12),
+            0xCD => (OP::CallImm16(n16()?), 3, 24),
+            0xCE => (OP::AdcImm8(n()?), 2, 8),
+            0xCF => (OP::Rst(0x08), 1, 16),
+
+            0xD0 => (OP::RetCond(Flag::NC), 1, 8),
+            0xD1 => (OP::PopR16(Reg16::DE), 1, 12),
+            0xD2 => (OP::JPCondImm16(Flag::NC, n16()?), 3, 12),
+            0xD3 => return Err(DecodeError::IllegalOpcode(0xD3)),
+            0xD4 => (OP::CallCondImm16(Flag::NC, n16()?), 3, 12),
+            0xD5 => (OP::PushR16(Reg16::DE), 1, 16),
+            0xD6 => (OP::SubImm8(n()?), 2, 8),
+            0xD7 => (OP::Rst(0x10), 1, 16),
+            0xD8 => (OP::RetCond(Flag::C), 1, 8),
+            0xD9 => (OP::Reti, 1, 16),
+            0xDA => (OP::JPCondImm16(Flag::C, n16()?), 3, 12),
+            0xDB => return Err(DecodeError::IllegalOpcode(0xDB)),
+            0xDC => (OP::CallCondImm16(Flag::C, n16()?), 3, 12),
+            0xDD => return Err(DecodeError::IllegalOpcode(0xDD)),
+            0xDE => (OP::SbcImm8(n()?), 2, 8),
+            0xDF => (OP::Rst(0x18), 1, 16),
+
+            0xE0 => (OP::LdIOImm8A(n()?), 2, 12),
+            0xE1 => (OP::PopR16(Reg16::HL), 1, 12),
+            0xE2 => (OP::LdIOC, 1, 8),
+            0xE3 => return Err(DecodeError::IllegalOpcode(0xE3)),
+            0xE4 => return Err(DecodeError::IllegalOpcode(0xE4)),
+            0xE5 => (OP::PushR16(Reg16::HL), 1, 16),
+            0xE6 => (OP::AndImm8(n()?), 2, 8),
+            0xE7 => (OP::Rst(0x20), 1, 16),
+            0xE8 => (OP::AddSPImm8(n()?), 2, 16),
+            0xE9 => (OP::JPHL, 1, 4),
+            0xEA => (OP::LdImm16A(n16()?), 3, 16),
+            0xEB => return Err(DecodeError::IllegalOpcode(0xEB)),
+            0xEC => return Err(DecodeError::IllegalOpcode(0xEC)),
+            0xED => return Err(DecodeError::IllegalOpcode(0xED)),
+            0xEE => (OP::XorImm8(n()?), 2, 8),
+            0xEF => (OP::Rst(0x28), 1, 16),
+
+            0xF0 => (OP::LdAIOImm8(n()?), 2, 12),
+            0xF1 => (OP::PopR16(Reg16::AF), 1, 12),
+            0xF2 => (OP::LdACIO, 1, 8),
+            0xF3 => (OP::Di, 1, 4),
+            0xF4 => return Err(DecodeError::IllegalOpcode(0xF4)),
+            0xF5 => (OP::PushR16(Reg16::AF), 1, 16),
+            0xF6 => (OP::OrImm8(n()?), 2, 8),
+            0xF7 => (OP::Rst(0x30), 1, 16),
+            0xF8 => (OP::LdHLSPImm8(n()?), 2, 12),
+            0xF9 => (OP::LdSPHL, 1, 8),
+            0xFA => (OP::LdAImm16(n16()?), 3, 16),
+            0xFB => (OP::Ei, 1, 4),
+            0xFC => return Err(DecodeError::IllegalOpcode(0xFC)),
+            0xFD => return Err(DecodeError::IllegalOpcode(0xFD)),
+            0xFE => (OP::CpImm8(n()?), 2, 8),
+            0xFF => (OP::Rst(0x38), 1, 16),
+        };
+
+        let taken = match op {
+            OP::JrCond(..) => 12,
+            OP::JPCondImm16(..) => 16,
+            OP::RetCond(_) => 20,
+            OP::CallCondImm16(..) => 24,
+            _ => cycles,
+        };
+
+        Ok((op, size, Timing { cycles, taken }))
+    }
+
+    /// Decodes the opcode following a 0xCB prefix into the instruction and
+    /// the duration of the whole two-byte sequence.
+    fn from_cb(opcode: u8) -> (OP, usize) {
+        // Bits 0-2 pick the operand, B C D E H L (HL) A
+        let reg = match opcode & 0x07 {
+            0 => Some(Reg8::B),
+            1 => Some(Reg8::C),
+            2 => Some(Reg8::D),
+            3 => Some(Reg8::E),
+            4 => Some(Reg8::H),
+            5 => Some(Reg8::L),
+            6 => None,
+            _ => Some(Reg8::A),
+        };
+        let bit = (opcode >> 3) & 0x07;
+
+        let op = match (opcode >> 3, reg) {
+            (0x00, Some(reg)) => OP::RlcR8(reg),
+            (0x00, None) => OP::RlcHL,
+            (0x01, Some(reg)) => OP::RrcR8(reg),
+            (0x01, None) => OP::RrcHL,
+            (0x02, Some(reg)) => OP::RlR8(reg),
+            (0x02, None) => OP::RlHL,
+            (0x03, Some(reg)) => OP::RrR8(reg),
+            (0x03, None) => OP::RrHL,
+            (0x04, Some(reg)) => OP::SlaR8(reg),
+            (0x04, None) => OP::SlaHL,
+            (0x05, Some(reg)) => OP::SrAR8(reg),
+            (0x05, None) => OP::SrAHL,
+            (0x06, Some(reg)) => OP::SwapR8(reg),
+            (0x06, None) => OP::SwapHL,
+            (0x07, Some(reg)) => OP::SrlR8(reg),
+            (0x07, None) => OP::SrlHL,
+            (0x08..=0x0F, Some(reg)) => OP::BitBR8(bit, reg),
+            (0x08..=0x0F, None) => OP::BitBHL(bit),
+            (0x10..=0x17, Some(reg)) => OP::ResBR8(bit, reg),
+            (0x10..=0x17, None) => OP::ResBHL(bit),
+            (_, Some(reg)) => OP::SetBR8(bit, reg),
+            (_, None) => OP::SetBHL(bit),
+        };
+
+        // BIT only reads (HL); everything else reads and writes it back
+        let duration = match (opcode >> 6, reg) {
+            (_, Some(_)) => 8,
+            (0x01, None) => 12,
+            (_, None) => 16,
+        };
+
+        (op, duration)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Instruction sizes for every unprefixed opcode, 0 for the illegal ones.
+    // CB counts as two bytes since the prefixed opcode is decoded with it.
+    #[rustfmt::skip]
+    const SIZES: [usize; 256] = [
+        1, 3, 1, 1, 1, 1, 2, 1, 3, 1, 1, 1, 1, 1, 2, 1,
+        2, 3, 1, 1, 1, 1, 2, 1, 2, 1, 1, 1, 1, 1, 2, 1,
+        2, 3, 1, 1, 1, 1, 2, 1, 2, 1, 1, 1, 1, 1, 2, 1,
+        2, 3, 1, 1, 1, 1, 2, 1, 2, 1, 1, 1, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+        1, 1, 3, 3, 3, 1, 2, 1, 1, 1, 3, 2, 3, 3, 2, 1,
+        1, 1, 3, 0, 3, 1, 2, 1, 1, 1, 3, 0, 3, 0, 2, 1,
+        2, 1, 1, 0, 0, 1, 2, 1, 2, 1, 3, 0, 0, 0, 2, 1,
+        2, 1, 1, 1, 0, 1, 2, 1, 2, 1, 3, 1, 0, 0, 2, 1,
+    ];
+
+    // T-cycles for every unprefixed opcode; conditional instructions list
+    // the branch not taken. CB is left at 0 and checked separately.
+    #[rustfmt::skip]
+    const DURATIONS: [usize; 256] = [
+         4, 12,  8,  8,  4,  4,  8,  4, 20,  8,  8,  8,  4,  4,  8,  4,
+         4, 12,  8,  8,  4,  4,  8,  4, 12,  8,  8,  8,  4,  4,  8,  4,
+         8, 12,  8,  8,  4,  4,  8,  4,  8,  8,  8,  8,  4,  4,  8,  4,
+         8, 12,  8,  8, 12, 12, 12,  4,  8,  8,  8,  8,  4,  4,  8,  4,
+         4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4,
+         4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4,
+         4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4,
+         8,  8,  8,  8,  8,  8,  4,  8,  4,  4,  4,  4,  4,  4,  8,  4,
+         4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4,
+         4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4,
+         4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4,
+         4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4,
+         8, 12, 12, 16, 12, 16,  8, 16,  8, 16, 12,  0, 12, 24,  8, 16,
+         8, 12, 12,  0, 12, 16,  8, 16,  8, 16, 12,  0, 12,  0,  8, 16,
+        12, 12,  8,  0,  0, 16,  8, 16, 16,  4, 16,  0,  0,  0,  8, 16,
+        12, 12,  8,  4,  0, 16,  8, 16, 12,  8, 16,  4,  0,  0,  8, 16,
+    ];
+
+    fn fixed(cycles: usize) -> Timing {
+        Timing {
+            cycles,
+            taken: cycles,
+        }
+    }
+
+    #[test]
+    fn decodes_every_opcode_with_table_size_and_duration() {
+        for opcode in 0..=0xFF_u8 {
+            let result = OP::from_bytes(&[opcode, 0x00, 0x00]);
+            match SIZES[opcode as usize] {
+                0 => assert_eq!(result, Err(DecodeError::IllegalOpcode(opcode))),
+                size => {
+                    let (_, decoded_size, timing) = result.unwrap();
+                    assert_eq!(decoded_size, size, "size of {:#04x}", opcode);
+                    if opcode != 0xCB {
+                        let cycles = DURATIONS[opcode as usize];
+                        let taken = match opcode {
+                            0x20 | 0x28 | 0x30 | 0x38 => 12,
+                            0xC2 | 0xCA | 0xD2 | 0xDA => 16,
+                            0xC0 | 0xC8 | 0xD0 | 0xD8 => 20,
+                            0xC4 | 0xCC | 0xD4 | 0xDC => 24,
+                            _ => cycles,
+                        };
+                        assert_eq!(
+                            timing,
+                            Timing { cycles, taken },
+                            "timing of {:#04x}",
+                            opcode
+                        );
+                    }
+                }
+            }
         }
     }
+
+    #[test]
+    fn reads_only_the_bytes_an_opcode_needs() {
+        for opcode in 0..=0xFF_u8 {
+            let size = SIZES[opcode as usize];
+            if size == 0 {
+                continue;
+            }
+
+            let bytes = [opcode, 0x00, 0x00];
+            assert!(OP::from_bytes(&bytes[..size]).is_ok(), "{:#04x}", opcode);
+            // STOP skips its second byte without looking at it
+            if opcode == 0x10 {
+                continue;
+            }
+            assert_eq!(
+                OP::from_bytes(&bytes[..size - 1]).map(|_| ()),
+                Err(DecodeError::Truncated {
+                    needed: size,
+                    available: size - 1,
+                }),
+                "{:#04x}",
+                opcode
+            );
+        }
+
+        assert_eq!(
+            OP::from_bytes(&[]),
+            Err(DecodeError::Truncated {
+                needed: 1,
+                available: 0
+            })
+        );
+    }
+
+    #[test]
+    fn decodes_operands_little_endian() {
+        assert_eq!(
+            OP::from_bytes(&[0x01, 0x34, 0x12]),
+            Ok((OP::LdR16Imm(Reg16::BC, 0x1234), 3, fixed(12)))
+        );
+        assert_eq!(
+            OP::from_bytes(&[0x08, 0x00, 0xC0]),
+            Ok((OP::LdImmSP(0xC000), 3, fixed(20)))
+        );
+        assert_eq!(
+            OP::from_bytes(&[0x18, 0xFE]),
+            Ok((OP::Jr(0xFFFE), 2, fixed(12)))
+        );
+    }
+
+    #[test]
+    fn decodes_every_cb_opcode() {
+        for opcode in 0..=0xFF_u8 {
+            let (op, size, timing) = OP::from_bytes(&[0xCB, opcode]).unwrap();
+            assert_eq!(size, 2);
+
+            let expected = match (opcode & 0x07, opcode >> 6) {
+                (6, 1) => 12,
+                (6, _) => 16,
+                _ => 8,
+            };
+            assert_eq!(timing, fixed(expected), "timing of CB {:#04x}", opcode);
+
+            let bit = (opcode >> 3) & 0x07;
+            match (opcode, op) {
+                (0x00..=0x07, OP::RlcR8(_) | OP::RlcHL)
+                | (0x08..=0x0F, OP::RrcR8(_) | OP::RrcHL)
+                | (0x10..=0x17, OP::RlR8(_) | OP::RlHL)
+                | (0x18..=0x1F, OP::RrR8(_) | OP::RrHL)
+                | (0x20..=0x27, OP::SlaR8(_) | OP::SlaHL)
+                | (0x28..=0x2F, OP::SrAR8(_) | OP::SrAHL)
+                | (0x30..=0x37, OP::SwapR8(_) | OP::SwapHL)
+                | (0x38..=0x3F, OP::SrlR8(_) | OP::SrlHL) => {}
+                (0x40..=0x7F, OP::BitBR8(b, _) | OP::BitBHL(b))
+                | (0x80..=0xBF, OP::ResBR8(b, _) | OP::ResBHL(b))
+                | (0xC0..=0xFF, OP::SetBR8(b, _) | OP::SetBHL(b)) => assert_eq!(b, bit),
+                (opcode, op) => panic!("CB {:#04x} decoded as {:?}", opcode, op),
+            }
+        }
+
+        assert_eq!(
+            OP::from_bytes(&[0xCB, 0x7E]),
+            Ok((OP::BitBHL(7), 2, fixed(12)))
+        );
+        assert_eq!(
+            OP::from_bytes(&[0xCB, 0x37]),
+            Ok((OP::SwapR8(Reg8::A), 2, fixed(8)))
+        );
+    }
 }
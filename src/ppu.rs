@@ -0,0 +1,833 @@
+use crate::interrupts::Interrupt;
+
+pub const SCREEN_WIDTH: usize = 160;
+pub const SCREEN_HEIGHT: usize = 144;
+
+const DOTS_PER_LINE: u32 = 456;
+const DOTS_PER_FRAME: u32 = 70224;
+const OAM_SCAN_DOTS: u32 = 80;
+const DRAWING_DOTS: u32 = 172;
+const VBLANK_START: u8 = 144;
+const LINES_PER_FRAME: u8 = 154;
+
+// DMG shades from lightest to darkest, in minifb's 0RGB layout
+const SHADES: [u32; 4] = [0xE0F8D0, 0x88C070, 0x346856, 0x081820];
+
+// LCDC bits
+const LCD_ENABLE: u8 = 0x80;
+const WINDOW_TILE_MAP: u8 = 0x40;
+const WINDOW_ENABLE: u8 = 0x20;
+const TILE_DATA: u8 = 0x10;
+const BG_TILE_MAP: u8 = 0x08;
+const OBJ_SIZE: u8 = 0x04;
+const OBJ_ENABLE: u8 = 0x02;
+const BG_ENABLE: u8 = 0x01;
+
+// OAM attribute bits
+const BG_PRIORITY: u8 = 0x80;
+const Y_FLIP: u8 = 0x40;
+const X_FLIP: u8 = 0x20;
+const PALETTE: u8 = 0x10;
+
+const MAX_SPRITES_PER_LINE: usize = 10;
+
+// STAT interrupt select bits
+const LYC_SELECT: u8 = 0x40;
+const OAM_SCAN_SELECT: u8 = 0x20;
+const VBLANK_SELECT: u8 = 0x10;
+const HBLANK_SELECT: u8 = 0x08;
+
+struct Sprite {
+    y: u8,
+    x: u8,
+    tile: u8,
+    attributes: u8,
+    index: usize,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Mode {
+    HBlank,
+    VBlank,
+    OamScan,
+    Drawing,
+}
+
+impl Mode {
+    fn bits(self) -> u8 {
+        match self {
+            Mode::HBlank => 0,
+            Mode::VBlank => 1,
+            Mode::OamScan => 2,
+            Mode::Drawing => 3,
+        }
+    }
+}
+
+pub struct Ppu {
+    vram: [u8; 0x2000],
+    oam: [u8; 0xA0],
+    lcdc: u8,
+    // Only the interrupt select bits; mode and LYC=LY are derived on read
+    stat: u8,
+    scy: u8,
+    scx: u8,
+    ly: u8,
+    lyc: u8,
+    bgp: u8,
+    obp0: u8,
+    obp1: u8,
+    wy: u8,
+    wx: u8,
+    mode: Mode,
+    // Dots elapsed on the current line (or the current frame while the LCD
+    // is off)
+    dots: u32,
+    // The window keeps its own line counter, which only advances on lines
+    // where the window was actually drawn.
+    window_line: u8,
+    // Set once LY has matched WY during the current frame
+    window_triggered: bool,
+    // STAT interrupts fire on the rising edge of the OR of all sources
+    stat_line: bool,
+    framebuffer: Vec<u32>,
+    frame_ready: bool,
+}
+
+impl Ppu {
+    pub fn new() -> Ppu {
+        Ppu {
+            vram: [0; 0x2000],
+            oam: [0; 0xA0],
+            lcdc: 0x91,
+            stat: 0x00,
+            scy: 0,
+            scx: 0,
+            ly: 0,
+            lyc: 0,
+            bgp: 0xFC,
+            obp0: 0xFF,
+            obp1: 0xFF,
+            wy: 0,
+            wx: 0,
+            mode: Mode::OamScan,
+            dots: 0,
+            window_line: 0,
+            window_triggered: false,
+            stat_line: false,
+            framebuffer: vec![SHADES[0]; SCREEN_WIDTH * SCREEN_HEIGHT],
+            frame_ready: false,
+        }
+    }
+
+    pub fn framebuffer(&self) -> &[u32] {
+        &self.framebuffer
+    }
+
+    /// Returns true once per completed frame.
+    pub fn take_frame_ready(&mut self) -> bool {
+        std::mem::take(&mut self.frame_ready)
+    }
+
+    fn lcd_enabled(&self) -> bool {
+        self.lcdc & LCD_ENABLE != 0
+    }
+
+    pub fn read_vram(&self, address: u16) -> u8 {
+        if self.lcd_enabled() && self.mode == Mode::Drawing {
+            return 0xFF;
+        }
+        self.vram[address as usize - 0x8000]
+    }
+
+    pub fn write_vram(&mut self, address: u16, value: u8) {
+        if self.lcd_enabled() && self.mode == Mode::Drawing {
+            return;
+        }
+        self.vram[address as usize - 0x8000] = value;
+    }
+
+    fn oam_blocked(&self) -> bool {
+        self.lcd_enabled() && matches!(self.mode, Mode::OamScan | Mode::Drawing)
+    }
+
+    pub fn read_oam(&self, address: u16) -> u8 {
+        if self.oam_blocked() {
+            return 0xFF;
+        }
+        self.oam[address as usize - 0xFE00]
+    }
+
+    pub fn write_oam(&mut self, address: u16, value: u8) {
+        if self.oam_blocked() {
+            return;
+        }
+        self.oam[address as usize - 0xFE00] = value;
+    }
+
+    /// OAM DMA writes bypass the mode restrictions the CPU is subject to.
+    pub fn write_oam_dma(&mut self, index: u8, value: u8) {
+        self.oam[index as usize] = value;
+    }
+
+    pub fn read_register(&self, address: u16) -> u8 {
+        match address {
+            0xFF40 => self.lcdc,
+            0xFF41 => {
+                let coincidence = if self.ly == self.lyc { 0x04 } else { 0x00 };
+                let mode = if self.lcd_enabled() {
+                    self.mode.bits()
+                } else {
+                    0
+                };
+                0x80 | self.stat | coincidence | mode
+            }
+            0xFF42 => self.scy,
+            0xFF43 => self.scx,
+            0xFF44 => self.ly,
+            0xFF45 => self.lyc,
+            0xFF47 => self.bgp,
+            0xFF48 => self.obp0,
+            0xFF49 => self.obp1,
+            0xFF4A => self.wy,
+            0xFF4B => self.wx,
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write_register(&mut self, address: u16, value: u8) {
+        match address {
+            0xFF40 => {
+                let was_enabled = self.lcd_enabled();
+                self.lcdc = value;
+                if was_enabled && !self.lcd_enabled() {
+                    self.ly = 0;
+                    self.dots = 0;
+                    self.mode = Mode::HBlank;
+                    self.framebuffer.fill(SHADES[0]);
+                } else if !was_enabled && self.lcd_enabled() {
+                    self.ly = 0;
+                    self.dots = 0;
+                    self.window_line = 0;
+                    self.window_triggered = false;
+                    self.start_line();
+                }
+            }
+            0xFF41 => self.stat = value & 0x78,
+            0xFF42 => self.scy = value,
+            0xFF43 => self.scx = value,
+            // LY is read-only
+            0xFF44 => {}
+            0xFF45 => self.lyc = value,
+            0xFF47 => self.bgp = value,
+            0xFF48 => self.obp0 = value,
+            0xFF49 => self.obp1 = value,
+            0xFF4A => self.wy = value,
+            0xFF4B => self.wx = value,
+            _ => {}
+        }
+    }
+
+    /// Advances the PPU by `cycles` dots and returns the interrupts it
+    /// requested along the way.
+    pub fn tick(&mut self, cycles: u32) -> u8 {
+        if !self.lcd_enabled() {
+            // Keep handing out frames so the frontend stays paced
+            self.dots += cycles;
+            if self.dots >= DOTS_PER_FRAME {
+                self.dots -= DOTS_PER_FRAME;
+                self.frame_ready = true;
+            }
+            return 0;
+        }
+
+        let mut interrupts = 0;
+        for _ in 0..cycles {
+            interrupts |= self.step();
+        }
+        interrupts
+    }
+
+    fn step(&mut self) -> u8 {
+        let mut interrupts = 0;
+        self.dots += 1;
+
+        match self.mode {
+            Mode::OamScan => {
+                if self.dots == OAM_SCAN_DOTS {
+                    self.mode = Mode::Drawing;
+                }
+            }
+            Mode::Drawing => {
+                if self.dots == OAM_SCAN_DOTS + DRAWING_DOTS {
+                    self.render_line();
+                    self.mode = Mode::HBlank;
+                }
+            }
+            Mode::HBlank => {
+                if self.dots == DOTS_PER_LINE {
+                    self.dots = 0;
+                    self.ly += 1;
+                    if self.ly == VBLANK_START {
+                        self.mode = Mode::VBlank;
+                        self.frame_ready = true;
+                        interrupts |= Interrupt::VBlank.bit();
+                    } else {
+                        self.start_line();
+                    }
+                }
+            }
+            Mode::VBlank => {
+                if self.dots == DOTS_PER_LINE {
+                    self.dots = 0;
+                    self.ly += 1;
+                    if self.ly == LINES_PER_FRAME {
+                        self.ly = 0;
+                        self.window_line = 0;
+                        self.window_triggered = false;
+                        self.start_line();
+                    }
+                }
+            }
+        }
+
+        let stat_line = self.stat_line();
+        if stat_line && !self.stat_line {
+            interrupts |= Interrupt::Stat.bit();
+        }
+        self.stat_line = stat_line;
+
+        interrupts
+    }
+
+    fn start_line(&mut self) {
+        self.mode = Mode::OamScan;
+        if self.ly == self.wy {
+            self.window_triggered = true;
+        }
+    }
+
+    fn stat_line(&self) -> bool {
+        (self.stat & LYC_SELECT != 0 && self.ly == self.lyc)
+            || match self.mode {
+                Mode::HBlank => self.stat & HBLANK_SELECT != 0,
+                Mode::VBlank => self.stat & VBLANK_SELECT != 0,
+                Mode::OamScan => self.stat & OAM_SCAN_SELECT != 0,
+                Mode::Drawing => false,
+            }
+    }
+
+    fn render_line(&mut self) {
+        let ly = self.ly;
+        let window_visible =
+            self.lcdc & WINDOW_ENABLE != 0 && self.window_triggered && self.wx <= 166;
+        let mut window_drawn = false;
+
+        // Background/window color indices, before BGP is applied. Sprites
+        // need these to resolve BG-over-OBJ priority.
+        let mut bg_colors = [0u8; SCREEN_WIDTH];
+
+        for (x, bg_color) in bg_colors.iter_mut().enumerate() {
+            *bg_color = if self.lcdc & BG_ENABLE == 0 {
+                0
+            } else if window_visible && x + 7 >= self.wx as usize {
+                window_drawn = true;
+                let map = if self.lcdc & WINDOW_TILE_MAP != 0 {
+                    0x1C00
+                } else {
+                    0x1800
+                };
+                self.tile_pixel(map, (x + 7 - self.wx as usize) as u8, self.window_line)
+            } else {
+                let map = if self.lcdc & BG_TILE_MAP != 0 {
+                    0x1C00
+                } else {
+                    0x1800
+                };
+                self.tile_pixel(
+                    map,
+                    self.scx.wrapping_add(x as u8),
+                    self.scy.wrapping_add(ly),
+                )
+            };
+
+            let shade = (self.bgp >> (*bg_color * 2)) & 0x03;
+            self.framebuffer[ly as usize * SCREEN_WIDTH + x] = SHADES[shade as usize];
+        }
+
+        if window_drawn {
+            self.window_line += 1;
+        }
+
+        if self.lcdc & OBJ_ENABLE != 0 {
+            self.render_sprites(&bg_colors);
+        }
+    }
+
+    /// Picks the sprites on the current line: the first 10 in OAM order
+    /// whose rows overlap LY. Their X position doesn't matter, so sprites
+    /// parked off-screen still use up a slot.
+    fn line_sprites(&self) -> Vec<Sprite> {
+        let height = self.sprite_height();
+
+        let mut sprites: Vec<Sprite> = self
+            .oam
+            .chunks_exact(4)
+            .enumerate()
+            .map(|(index, entry)| Sprite {
+                y: entry[0],
+                x: entry[1],
+                tile: entry[2],
+                attributes: entry[3],
+                index,
+            })
+            .filter(|sprite| {
+                let top = sprite.y as i16 - 16;
+                (top..top + height as i16).contains(&(self.ly as i16))
+            })
+            .take(MAX_SPRITES_PER_LINE)
+            .collect();
+
+        // On DMG the sprite with the smaller X wins; ties go to the one
+        // earlier in OAM. Drawing in reverse priority order lets the winner
+        // overwrite everyone else.
+        sprites.sort_by_key(|sprite| (sprite.x, sprite.index));
+        sprites.reverse();
+        sprites
+    }
+
+    fn sprite_height(&self) -> u8 {
+        if self.lcdc & OBJ_SIZE != 0 {
+            16
+        } else {
+            8
+        }
+    }
+
+    fn render_sprites(&mut self, bg_colors: &[u8; SCREEN_WIDTH]) {
+        let ly = self.ly;
+        let height = self.sprite_height();
+        // Color index and attributes of the winning sprite at each pixel
+        let mut line = [None; SCREEN_WIDTH];
+
+        for sprite in self.line_sprites() {
+            let mut row = ly + 16 - sprite.y;
+            if sprite.attributes & Y_FLIP != 0 {
+                row = height - 1 - row;
+            }
+
+            // 8x16 sprites ignore bit 0 of the tile number
+            let tile = if height == 16 {
+                sprite.tile & 0xFE
+            } else {
+                sprite.tile
+            };
+            let address = tile as usize * 16 + row as usize * 2;
+            let low = self.vram[address];
+            let high = self.vram[address + 1];
+
+            for column in 0..8u8 {
+                let x = sprite.x as i16 - 8 + column as i16;
+                if !(0..SCREEN_WIDTH as i16).contains(&x) {
+                    continue;
+                }
+
+                let bit = if sprite.attributes & X_FLIP != 0 {
+                    column
+                } else {
+                    7 - column
+                };
+                let color = ((high >> bit) & 0x01) << 1 | (low >> bit) & 0x01;
+
+                // Color 0 is transparent and doesn't hide lower-priority
+                // sprites underneath
+                if color != 0 {
+                    line[x as usize] = Some((color, sprite.attributes));
+                }
+            }
+        }
+
+        for (x, pixel) in line.iter().enumerate() {
+            let Some((color, attributes)) = *pixel else {
+                continue;
+            };
+            if attributes & BG_PRIORITY != 0 && bg_colors[x] != 0 {
+                continue;
+            }
+
+            let palette = if attributes & PALETTE != 0 {
+                self.obp1
+            } else {
+                self.obp0
+            };
+            let shade = (palette >> (color * 2)) & 0x03;
+            self.framebuffer[ly as usize * SCREEN_WIDTH + x] = SHADES[shade as usize];
+        }
+    }
+
+    /// Looks up the 2-bit color index at (`x`, `y`) of the 256x256 map
+    /// starting at `map` (an offset into VRAM).
+    fn tile_pixel(&self, map: usize, x: u8, y: u8) -> u8 {
+        let tile = self.vram[map + (y as usize / 8) * 32 + x as usize / 8];
+
+        // 0x8000 addressing uses unsigned tile numbers; 0x8800 addressing
+        // uses signed ones relative to 0x9000.
+        let tile_address = if self.lcdc & TILE_DATA != 0 {
+            tile as usize * 16
+        } else {
+            (0x1000 + tile as i8 as isize * 16) as usize
+        };
+
+        let row = tile_address + (y as usize % 8) * 2;
+        let low = self.vram[row];
+        let high = self.vram[row + 1];
+        let bit = 7 - x % 8;
+
+        ((high >> bit) & 0x01) << 1 | (low >> bit) & 0x01
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A PPU with the LCD off, so VRAM and OAM can be filled freely before
+    /// `enable` turns it on.
+    fn ppu() -> Ppu {
+        let mut ppu = Ppu::new();
+        ppu.write_register(0xFF40, 0x00);
+        ppu.write_register(0xFF47, 0xE4);
+        ppu
+    }
+
+    fn enable(ppu: &mut Ppu, lcdc: u8) {
+        ppu.write_register(0xFF40, lcdc | LCD_ENABLE);
+    }
+
+    /// Fills the 8x8 tile at `address` with a single color index.
+    fn solid_tile(ppu: &mut Ppu, address: u16, color: u8) {
+        let low = if color & 1 != 0 { 0xFF } else { 0x00 };
+        let high = if color & 2 != 0 { 0xFF } else { 0x00 };
+        for row in 0..8 {
+            ppu.write_vram(address + row * 2, low);
+            ppu.write_vram(address + row * 2 + 1, high);
+        }
+    }
+
+    fn shade_at(ppu: &Ppu, x: usize, y: usize) -> usize {
+        let pixel = ppu.framebuffer()[y * SCREEN_WIDTH + x];
+        SHADES.iter().position(|&shade| shade == pixel).unwrap()
+    }
+
+    fn mode(ppu: &Ppu) -> u8 {
+        ppu.read_register(0xFF41) & 0x03
+    }
+
+    #[test]
+    fn modes_follow_the_line_timing() {
+        let mut ppu = ppu();
+        enable(&mut ppu, 0x11);
+
+        for line in 0..VBLANK_START {
+            assert_eq!(ppu.read_register(0xFF44), line);
+            assert_eq!(mode(&ppu), 2);
+            ppu.tick(OAM_SCAN_DOTS - 1);
+            assert_eq!(mode(&ppu), 2);
+            ppu.tick(1);
+            assert_eq!(mode(&ppu), 3);
+            ppu.tick(DRAWING_DOTS - 1);
+            assert_eq!(mode(&ppu), 3);
+            ppu.tick(1);
+            assert_eq!(mode(&ppu), 0);
+            ppu.tick(DOTS_PER_LINE - OAM_SCAN_DOTS - DRAWING_DOTS - 1);
+            assert_eq!(mode(&ppu), 0);
+            ppu.tick(1);
+        }
+
+        for line in VBLANK_START..LINES_PER_FRAME {
+            assert_eq!(ppu.read_register(0xFF44), line);
+            assert_eq!(mode(&ppu), 1);
+            ppu.tick(DOTS_PER_LINE);
+        }
+        assert_eq!(ppu.read_register(0xFF44), 0);
+        assert_eq!(mode(&ppu), 2);
+        assert!(ppu.take_frame_ready());
+    }
+
+    #[test]
+    fn lyc_match_raises_stat_once() {
+        let mut ppu = ppu();
+        ppu.write_register(0xFF45, 5);
+        ppu.write_register(0xFF41, LYC_SELECT);
+        enable(&mut ppu, 0x11);
+
+        let mut stat_lines = Vec::new();
+        let mut vblanks = 0;
+        for line in 0..LINES_PER_FRAME {
+            let coincidence = ppu.read_register(0xFF41) & 0x04 != 0;
+            assert_eq!(coincidence, line == 5, "line {}", line);
+
+            let interrupts = ppu.tick(DOTS_PER_LINE);
+            if interrupts & Interrupt::Stat.bit() != 0 {
+                stat_lines.push(line);
+            }
+            if interrupts & Interrupt::VBlank.bit() != 0 {
+                vblanks += 1;
+            }
+        }
+        // LY becomes 5 on the last dot of line 4
+        assert_eq!(stat_lines, [4]);
+        assert_eq!(vblanks, 1);
+    }
+
+    #[test]
+    fn mode_sources_raise_stat_on_each_rising_edge() {
+        let mut ppu = ppu();
+        ppu.write_register(0xFF41, HBLANK_SELECT);
+        enable(&mut ppu, 0x11);
+
+        let mut count = 0;
+        for _ in 0..LINES_PER_FRAME {
+            if ppu.tick(DOTS_PER_LINE) & Interrupt::Stat.bit() != 0 {
+                count += 1;
+            }
+        }
+        assert_eq!(count, VBLANK_START);
+    }
+
+    #[test]
+    fn background_scroll_wraps_around_the_map() {
+        let mut ppu = ppu();
+        solid_tile(&mut ppu, 0x8010, 3);
+        // Bottom-right corner of the 32x32 map at 0x9800
+        ppu.write_vram(0x9800 + 31 * 32 + 31, 1);
+        ppu.write_register(0xFF42, 248);
+        ppu.write_register(0xFF43, 248);
+        enable(&mut ppu, 0x11);
+        ppu.tick(DOTS_PER_FRAME);
+
+        assert_eq!(shade_at(&ppu, 0, 0), 3);
+        assert_eq!(shade_at(&ppu, 7, 7), 3);
+        assert_eq!(shade_at(&ppu, 8, 0), 0);
+        assert_eq!(shade_at(&ppu, 0, 8), 0);
+    }
+
+    #[test]
+    fn signed_tile_addressing_reads_below_0x9000() {
+        let mut ppu = ppu();
+        solid_tile(&mut ppu, 0x8FF0, 2);
+        solid_tile(&mut ppu, 0x9000, 1);
+        ppu.write_vram(0x9800, 0xFF);
+        enable(&mut ppu, 0x01);
+        ppu.tick(DOTS_PER_FRAME);
+
+        assert_eq!(shade_at(&ppu, 0, 0), 2);
+        assert_eq!(shade_at(&ppu, 8, 0), 1);
+    }
+
+    #[test]
+    fn window_starts_at_wy_and_keeps_its_own_line_counter() {
+        let mut ppu = ppu();
+        solid_tile(&mut ppu, 0x8010, 1);
+        solid_tile(&mut ppu, 0x8020, 2);
+        // Window map at 0x9C00: a row of tile 1 above a row of tile 2
+        for column in 0..32 {
+            ppu.write_vram(0x9C00 + column, 1);
+            ppu.write_vram(0x9C20 + column, 2);
+        }
+        ppu.write_register(0xFF4A, 2);
+        ppu.write_register(0xFF4B, 7 + 16);
+        enable(&mut ppu, 0x71);
+
+        ppu.tick(DOTS_PER_LINE * 6);
+        // Hide the window for lines 6-13; its counter must stay put
+        ppu.write_register(0xFF4B, 200);
+        ppu.tick(DOTS_PER_LINE * 8);
+        ppu.write_register(0xFF4B, 7 + 16);
+        ppu.tick(DOTS_PER_FRAME - DOTS_PER_LINE * 14);
+
+        // Nothing above WY, and nothing left of WX
+        assert_eq!(shade_at(&ppu, 20, 1), 0);
+        assert_eq!(shade_at(&ppu, 15, 2), 0);
+        assert_eq!(shade_at(&ppu, 16, 2), 1);
+        assert_eq!(shade_at(&ppu, 16, 6), 0);
+        // Window rows 4-7 land on lines 14-17, row 8 on line 18
+        assert_eq!(shade_at(&ppu, 16, 14), 1);
+        assert_eq!(shade_at(&ppu, 16, 17), 1);
+        assert_eq!(shade_at(&ppu, 16, 18), 2);
+    }
+
+    #[test]
+    fn bgp_maps_color_indices_to_shades() {
+        let mut ppu = ppu();
+        for color in 0..4u16 {
+            solid_tile(&mut ppu, 0x8000 + color * 16, color as u8);
+            ppu.write_vram(0x9800 + color, color as u8);
+        }
+        ppu.write_register(0xFF47, 0b00_01_11_10);
+        enable(&mut ppu, 0x11);
+        ppu.tick(DOTS_PER_FRAME);
+
+        let shades: Vec<_> = (0..4).map(|tile| shade_at(&ppu, tile * 8, 0)).collect();
+        assert_eq!(shades, [2, 3, 1, 0]);
+
+        // With the background off every pixel is color 0, through BGP
+        ppu.write_register(0xFF40, 0x90);
+        ppu.tick(DOTS_PER_FRAME);
+        assert_eq!(shade_at(&ppu, 24, 0), 2);
+    }
+
+    /// Places OAM entry `index` so its top-left pixel lands on (`x`, `y`).
+    fn sprite(ppu: &mut Ppu, index: u16, x: u8, y: u8, tile: u8, attributes: u8) {
+        let address = 0xFE00 + index * 4;
+        ppu.write_oam(address, y + 16);
+        ppu.write_oam(address + 1, x + 8);
+        ppu.write_oam(address + 2, tile);
+        ppu.write_oam(address + 3, attributes);
+    }
+
+    fn sprite_ppu() -> Ppu {
+        let mut ppu = ppu();
+        ppu.write_register(0xFF48, 0xE4);
+        ppu.write_register(0xFF49, 0xE4);
+        for color in 1..4u16 {
+            solid_tile(&mut ppu, 0x8000 + color * 16, color as u8);
+        }
+        ppu
+    }
+
+    fn frame(ppu: &mut Ppu, lcdc: u8) {
+        enable(ppu, lcdc);
+        ppu.tick(DOTS_PER_FRAME);
+    }
+
+    #[test]
+    fn only_ten_sprites_per_line_in_oam_order() {
+        let mut ppu = sprite_ppu();
+        // An off-screen sprite still takes the first slot
+        ppu.write_oam(0xFE00, 16 + 20);
+        ppu.write_oam(0xFE01, 0);
+        ppu.write_oam(0xFE02, 1);
+        for index in 1..12 {
+            sprite(&mut ppu, index, index as u8 * 10, 20, 1, 0);
+        }
+        frame(&mut ppu, 0x93);
+
+        for index in 1..10 {
+            assert_eq!(shade_at(&ppu, index * 10, 20), 1, "sprite {}", index);
+        }
+        assert_eq!(shade_at(&ppu, 100, 20), 0);
+        assert_eq!(shade_at(&ppu, 110, 20), 0);
+        // Lines the sprites don't cover are unaffected by the limit
+        assert_eq!(shade_at(&ppu, 10, 19), 0);
+    }
+
+    #[test]
+    fn smaller_x_wins_then_oam_order() {
+        let mut ppu = sprite_ppu();
+        sprite(&mut ppu, 0, 20, 0, 1, 0);
+        sprite(&mut ppu, 1, 16, 0, 2, 0);
+        sprite(&mut ppu, 2, 40, 10, 3, 0);
+        sprite(&mut ppu, 3, 40, 10, 2, 0);
+        frame(&mut ppu, 0x93);
+
+        // Sprite 1 sits further left, so it covers sprite 0 on 20-23
+        assert_eq!(shade_at(&ppu, 16, 0), 2);
+        assert_eq!(shade_at(&ppu, 23, 0), 2);
+        assert_eq!(shade_at(&ppu, 24, 0), 1);
+        // Same X: the earlier OAM entry wins
+        assert_eq!(shade_at(&ppu, 40, 10), 3);
+    }
+
+    #[test]
+    fn transparent_sprite_pixels_show_what_is_beneath() {
+        let mut ppu = sprite_ppu();
+        // Tile 4: only the left half is opaque
+        for row in 0..8 {
+            ppu.write_vram(0x8040 + row * 2, 0xF0);
+        }
+        sprite(&mut ppu, 0, 0, 0, 4, 0);
+        sprite(&mut ppu, 1, 2, 0, 2, 0);
+        frame(&mut ppu, 0x93);
+
+        assert_eq!(shade_at(&ppu, 3, 0), 1);
+        assert_eq!(shade_at(&ppu, 4, 0), 2);
+        assert_eq!(shade_at(&ppu, 9, 0), 2);
+    }
+
+    #[test]
+    fn tall_sprites_ignore_tile_bit_0() {
+        let mut ppu = sprite_ppu();
+        sprite(&mut ppu, 0, 0, 0, 3, 0);
+        frame(&mut ppu, 0x97);
+
+        // Tile 3 draws as tile 2 on top and tile 3 below
+        assert_eq!(shade_at(&ppu, 0, 0), 2);
+        assert_eq!(shade_at(&ppu, 0, 7), 2);
+        assert_eq!(shade_at(&ppu, 0, 8), 3);
+        assert_eq!(shade_at(&ppu, 0, 15), 3);
+        assert_eq!(shade_at(&ppu, 0, 16), 0);
+    }
+
+    #[test]
+    fn flips_mirror_the_tile() {
+        let mut ppu = sprite_ppu();
+        // Tile 4 has a single color 3 pixel in its top-left corner, tile 5
+        // is blank so a tall sprite's lower half is empty
+        ppu.write_vram(0x8040, 0x80);
+        ppu.write_vram(0x8041, 0x80);
+        let corners = [
+            (0, 0, 0),
+            (20, 0, X_FLIP),
+            (40, 0, Y_FLIP),
+            (60, 0, X_FLIP | Y_FLIP),
+        ];
+        for (index, &(x, y, attributes)) in corners.iter().enumerate() {
+            sprite(&mut ppu, index as u16, x, y, 4, attributes);
+        }
+        frame(&mut ppu, 0x93);
+
+        assert_eq!(shade_at(&ppu, 0, 0), 3);
+        assert_eq!(shade_at(&ppu, 27, 0), 3);
+        assert_eq!(shade_at(&ppu, 40, 7), 3);
+        assert_eq!(shade_at(&ppu, 67, 7), 3);
+        assert_eq!(shade_at(&ppu, 20, 0), 0);
+        assert_eq!(shade_at(&ppu, 40, 0), 0);
+
+        // A tall sprite flips across all 16 rows
+        ppu.write_register(0xFF40, 0x00);
+        sprite(&mut ppu, 0, 80, 0, 5, Y_FLIP);
+        frame(&mut ppu, 0x97);
+        assert_eq!(shade_at(&ppu, 80, 15), 3);
+        assert_eq!(shade_at(&ppu, 80, 7), 0);
+    }
+
+    #[test]
+    fn bg_priority_hides_sprites_behind_non_zero_background() {
+        let mut ppu = sprite_ppu();
+        // Background tile 1 (color 1) at map column 1 only
+        ppu.write_vram(0x9801, 1);
+        ppu.write_register(0xFF47, 0xE4);
+        sprite(&mut ppu, 0, 4, 0, 3, BG_PRIORITY);
+        sprite(&mut ppu, 1, 4, 8, 3, 0);
+        frame(&mut ppu, 0x93);
+
+        // Over color 0 the sprite still shows, over color 1 it doesn't
+        assert_eq!(shade_at(&ppu, 7, 0), 3);
+        assert_eq!(shade_at(&ppu, 8, 0), 1);
+        // Without the flag it covers everything
+        assert_eq!(shade_at(&ppu, 8, 8), 3);
+    }
+
+    #[test]
+    fn palette_bit_selects_obp1() {
+        let mut ppu = sprite_ppu();
+        ppu.write_register(0xFF49, 0x00);
+        sprite(&mut ppu, 0, 0, 0, 3, PALETTE);
+        sprite(&mut ppu, 1, 8, 0, 3, 0);
+        frame(&mut ppu, 0x93);
+
+        assert_eq!(shade_at(&ppu, 0, 0), 0);
+        assert_eq!(shade_at(&ppu, 8, 0), 3);
+    }
+}
@@ -0,0 +1,394 @@
+//! The Game Boy Printer. The game drives every transfer and sends packets of
+//! `88 33 command compression length data... checksum`, both 16-bit fields
+//! little-endian, followed by two more bytes to which the printer answers
+//! 0x81 and then its status. Tile data collected by DATA packets is laid
+//! out on paper by the next PRINT.
+
+use crate::serial::SerialDevice;
+use std::{cell::RefCell, rc::Rc};
+
+const MAGIC: [u8; 2] = [0x88, 0x33];
+const ALIVE: u8 = 0x81;
+
+// Commands
+const INIT: u8 = 0x01;
+const PRINT: u8 = 0x02;
+const DATA: u8 = 0x04;
+const STATUS: u8 = 0x0F;
+
+// Status bits
+const CHECKSUM_ERROR: u8 = 0x01;
+const PRINTING: u8 = 0x02;
+const DATA_FULL: u8 = 0x04;
+const UNPROCESSED: u8 = 0x08;
+const PACKET_ERROR: u8 = 0x10;
+
+const PAPER_WIDTH: usize = 160;
+const TILES_PER_ROW: usize = PAPER_WIDTH / 8;
+const BYTES_PER_TILE_ROW: usize = TILES_PER_ROW * 16;
+// The printer's RAM holds nine 16 pixel bands
+const BAND_HEIGHT: usize = 16;
+const BUFFER_SIZE: usize = 9 * 2 * BYTES_PER_TILE_ROW;
+
+// How many status replies report the print head as busy after a PRINT
+const PRINT_POLLS: u8 = 4;
+
+// Paper colour for each shade, white to black
+const SHADES: [u8; 4] = [0xFF, 0xAA, 0x55, 0x00];
+
+/// A finished print job as 8-bit greyscale, one byte per pixel.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Page {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+impl Page {
+    /// Encodes the page as a binary PGM.
+    pub fn to_pgm(&self) -> Vec<u8> {
+        let mut pgm = format!("P5\n{} {}\n255\n", self.width, self.height).into_bytes();
+        pgm.extend_from_slice(&self.pixels);
+        pgm
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum State {
+    Magic(usize),
+    Command,
+    Compression,
+    LengthLow,
+    LengthHigh,
+    Data,
+    ChecksumLow,
+    ChecksumHigh,
+    Alive,
+    Status,
+}
+
+/// A printer with its paper roll. Each PRINT adds a page to a roll that
+/// every clone of the printer shares, so the frontend can hand one clone to
+/// the link port and still tear pages off with `take_pages`.
+#[derive(Clone)]
+pub struct Printer {
+    state: State,
+    command: u8,
+    compressed: bool,
+    length: u16,
+    data: Vec<u8>,
+    checksum: u16,
+    // Decompressed tile data waiting for a PRINT
+    buffer: Vec<u8>,
+    status: u8,
+    busy_polls: u8,
+    pages: Rc<RefCell<Vec<Page>>>,
+}
+
+impl Printer {
+    pub fn new() -> Printer {
+        Printer {
+            state: State::Magic(0),
+            command: 0,
+            compressed: false,
+            length: 0,
+            data: Vec::new(),
+            checksum: 0,
+            buffer: Vec::new(),
+            status: 0,
+            busy_polls: 0,
+            pages: Rc::default(),
+        }
+    }
+
+    /// Every page printed since the last call.
+    pub fn take_pages(&self) -> Vec<Page> {
+        self.pages.take()
+    }
+
+    fn receive(&mut self, byte: u8) -> u8 {
+        match self.state {
+            State::Magic(matched) => {
+                self.state = if byte != MAGIC[matched] {
+                    // Resynchronise on a stray 0x88
+                    State::Magic((byte == MAGIC[0]) as usize)
+                } else if matched + 1 == MAGIC.len() {
+                    State::Command
+                } else {
+                    State::Magic(matched + 1)
+                };
+            }
+            State::Command => {
+                self.command = byte;
+                self.checksum = byte as u16;
+                self.state = State::Compression;
+            }
+            State::Compression => {
+                self.compressed = byte & 0x01 != 0;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                self.state = State::LengthLow;
+            }
+            State::LengthLow => {
+                self.length = byte as u16;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                self.state = State::LengthHigh;
+            }
+            State::LengthHigh => {
+                self.length |= (byte as u16) << 8;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                self.data.clear();
+                self.state = if self.length == 0 {
+                    State::ChecksumLow
+                } else {
+                    State::Data
+                };
+            }
+            State::Data => {
+                self.data.push(byte);
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                if self.data.len() == self.length as usize {
+                    self.state = State::ChecksumLow;
+                }
+            }
+            State::ChecksumLow => {
+                // Subtracting the expected sum leaves zero when it matches
+                self.checksum = self.checksum.wrapping_sub(byte as u16);
+                self.state = State::ChecksumHigh;
+            }
+            State::ChecksumHigh => {
+                self.checksum = self.checksum.wrapping_sub((byte as u16) << 8);
+                if self.checksum == 0 {
+                    self.status &= !CHECKSUM_ERROR;
+                    self.run_command();
+                } else {
+                    self.status |= CHECKSUM_ERROR;
+                }
+                self.state = State::Alive;
+            }
+            State::Alive => {
+                self.state = State::Status;
+                return ALIVE;
+            }
+            State::Status => {
+                self.state = State::Magic(0);
+                return self.report_status();
+            }
+        }
+        0x00
+    }
+
+    fn run_command(&mut self) {
+        self.status &= !PACKET_ERROR;
+        match self.command {
+            INIT => {
+                self.buffer.clear();
+                self.status = 0;
+                self.busy_polls = 0;
+            }
+            PRINT if self.data.len() == 4 => self.print(),
+            DATA => {
+                // An empty DATA packet only marks the end of the image
+                let data = if self.compressed {
+                    decompress(&self.data)
+                } else {
+                    self.data.clone()
+                };
+                self.buffer.extend(data);
+                self.buffer.truncate(BUFFER_SIZE);
+                if !self.buffer.is_empty() {
+                    self.status |= UNPROCESSED;
+                }
+                if self.buffer.len() == BUFFER_SIZE {
+                    self.status |= DATA_FULL;
+                }
+            }
+            STATUS => {}
+            _ => self.status |= PACKET_ERROR,
+        }
+    }
+
+    fn print(&mut self) {
+        // Sheet count and exposure only matter to real paper
+        let margins = self.data[1];
+        let palette = match self.data[2] {
+            // Zero is taken to mean the default palette
+            0x00 => 0xE4,
+            palette => palette,
+        };
+
+        let feed = |units: u8| units as usize * BAND_HEIGHT;
+        let before = feed(margins >> 4);
+        let after = feed(margins & 0x0F);
+        let image_height = self.buffer.len() / BYTES_PER_TILE_ROW * 8;
+
+        let mut pixels = vec![SHADES[0]; PAPER_WIDTH * (before + image_height + after)];
+        for y in 0..image_height {
+            for x in 0..PAPER_WIDTH {
+                let tile = (y / 8) * TILES_PER_ROW + x / 8;
+                let row = tile * 16 + (y % 8) * 2;
+                let bit = 7 - (x % 8);
+                let color = (self.buffer[row] >> bit & 1) | (self.buffer[row + 1] >> bit & 1) << 1;
+                let shade = palette >> (color * 2) & 0x03;
+                pixels[(before + y) * PAPER_WIDTH + x] = SHADES[shade as usize];
+            }
+        }
+
+        self.pages.borrow_mut().push(Page {
+            width: PAPER_WIDTH,
+            height: pixels.len() / PAPER_WIDTH,
+            pixels,
+        });
+
+        self.buffer.clear();
+        self.status &= !(UNPROCESSED | DATA_FULL);
+        self.status |= PRINTING;
+        self.busy_polls = PRINT_POLLS;
+    }
+
+    fn report_status(&mut self) -> u8 {
+        let status = self.status;
+        if self.busy_polls > 0 {
+            self.busy_polls -= 1;
+            if self.busy_polls == 0 {
+                self.status &= !PRINTING;
+            }
+        }
+        status
+    }
+}
+
+impl SerialDevice for Printer {
+    fn exchange(&mut self, outgoing: u8) -> u8 {
+        self.receive(outgoing)
+    }
+}
+
+/// Expands the printer's run-length encoding: a control byte with bit 7 set
+/// repeats the next byte (control & 0x7F) + 2 times, otherwise the next
+/// control + 1 bytes are copied as they are.
+fn decompress(data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::new();
+    let mut bytes = data.iter().copied();
+    while let Some(control) = bytes.next() {
+        if control & 0x80 != 0 {
+            let count = (control & 0x7F) as usize + 2;
+            if let Some(value) = bytes.next() {
+                output.extend(std::iter::repeat(value).take(count));
+            }
+        } else {
+            output.extend(bytes.by_ref().take(control as usize + 1));
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet(command: u8, compressed: bool, data: &[u8]) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        let length = data.len() as u16;
+        let mut body = vec![command, compressed as u8, length as u8, (length >> 8) as u8];
+        body.extend_from_slice(data);
+        let checksum = body.iter().fold(0u16, |sum, &b| sum.wrapping_add(b as u16));
+        bytes.extend(body);
+        bytes.extend([checksum as u8, (checksum >> 8) as u8, 0x00, 0x00]);
+        bytes
+    }
+
+    /// Sends a packet, returning the alive and status replies.
+    fn send(printer: &mut Printer, packet: &[u8]) -> (u8, u8) {
+        let replies: Vec<u8> = packet.iter().map(|&b| printer.exchange(b)).collect();
+        assert!(replies[..replies.len() - 2].iter().all(|&r| r == 0x00));
+        (replies[replies.len() - 2], replies[replies.len() - 1])
+    }
+
+    // One band: a row of tiles in colour 1 over a row in colour 3
+    fn band() -> Vec<u8> {
+        let mut data = [0xFF, 0x00].repeat(BYTES_PER_TILE_ROW / 2);
+        data.extend([0xFF, 0xFF].repeat(BYTES_PER_TILE_ROW / 2));
+        data
+    }
+
+    #[test]
+    fn prints_a_band_with_margins_and_palette() {
+        let mut printer = Printer::new();
+        assert_eq!(send(&mut printer, &packet(INIT, false, &[])), (ALIVE, 0x00));
+        assert_eq!(
+            send(&mut printer, &packet(DATA, false, &band())),
+            (ALIVE, UNPROCESSED)
+        );
+        send(&mut printer, &packet(DATA, false, &[]));
+
+        // One sheet, one unit of margin after, palette mapping 1 -> 2, 3 -> 1
+        let (_, status) = send(&mut printer, &packet(PRINT, false, &[1, 0x01, 0x68, 0x40]));
+        assert_eq!(status, PRINTING);
+
+        let pages = printer.take_pages();
+        assert_eq!(pages.len(), 1);
+        let page = &pages[0];
+        assert_eq!((page.width, page.height), (160, 16 + BAND_HEIGHT));
+        assert_eq!(page.pixels[0], SHADES[2]);
+        assert_eq!(page.pixels[8 * 160], SHADES[1]);
+        assert_eq!(page.pixels[16 * 160], SHADES[0]);
+        assert!(page.to_pgm().starts_with(b"P5\n160 32\n255\n"));
+
+        // Busy for a few polls, then idle
+        let statuses: Vec<u8> = (0..PRINT_POLLS)
+            .map(|_| send(&mut printer, &packet(STATUS, false, &[])).1)
+            .collect();
+        assert_eq!(statuses, [PRINTING, PRINTING, PRINTING, 0x00]);
+    }
+
+    #[test]
+    fn compressed_data_matches_uncompressed() {
+        let mut data = Vec::new();
+        for half in band().chunks(BYTES_PER_TILE_ROW) {
+            for run in half.chunks(2) {
+                data.extend([0x01, run[0], run[1]]);
+            }
+        }
+        // Long runs of a single byte
+        let mut runs = Vec::new();
+        for chunk in band().chunks(64) {
+            if chunk.iter().all(|&b| b == chunk[0]) {
+                runs.extend([0x80 | 62, chunk[0]]);
+            } else {
+                runs.push(chunk.len() as u8 - 1);
+                runs.extend_from_slice(chunk);
+            }
+        }
+        assert_eq!(decompress(&data), band());
+        assert_eq!(decompress(&runs), band());
+
+        let mut printer = Printer::new();
+        send(&mut printer, &packet(DATA, true, &runs));
+        send(&mut printer, &packet(PRINT, false, &[1, 0x00, 0xE4, 0x40]));
+        assert_eq!(printer.take_pages()[0].pixels[8 * 160], SHADES[3]);
+    }
+
+    #[test]
+    fn bad_checksum_is_reported_and_ignored() {
+        let mut printer = Printer::new();
+        let mut bad = packet(DATA, false, &band());
+        let checksum = bad.len() - 4;
+        bad[checksum] ^= 0x01;
+
+        assert_eq!(send(&mut printer, &bad), (ALIVE, CHECKSUM_ERROR));
+        assert!(printer.buffer.is_empty());
+        assert_eq!(send(&mut printer, &packet(STATUS, false, &[])).1, 0x00);
+    }
+
+    #[test]
+    fn full_buffer_sets_status() {
+        let mut printer = Printer::new();
+        for _ in 0..9 {
+            send(&mut printer, &packet(DATA, false, &band()));
+        }
+        assert_eq!(
+            send(&mut printer, &packet(STATUS, false, &[])).1,
+            UNPROCESSED | DATA_FULL
+        );
+    }
+}
@@ -16,14 +16,11 @@ pub enum Reg16 {
     DE,
     HL,
     SP,
-    PC,
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum Flag {
     Z,
-    N,
-    H,
     C,
     NZ,
     NC,
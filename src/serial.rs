@@ -0,0 +1,217 @@
+use std::{cell::RefCell, rc::Rc};
+
+// SC bits
+const TRANSFER_START: u8 = 0x80;
+const INTERNAL_CLOCK: u8 = 0x01;
+
+// 8192 Hz, one bit every 128 M-cycles
+const CYCLES_PER_BIT: u32 = 128;
+
+/// Whatever sits on the other end of the link port.
+pub trait SerialDevice {
+    /// Swaps a byte with the device: `outgoing` is what the Game Boy shifted
+    /// out, the returned byte is what it shifted in.
+    fn exchange(&mut self, outgoing: u8) -> u8;
+
+    /// Polled every M-cycle while a transfer waits on the external clock
+    /// with `outgoing` in SB. Returns the byte shifted in once the partner
+    /// has clocked a whole transfer.
+    fn external_clock(&mut self, _outgoing: u8) -> Option<u8> {
+        None
+    }
+
+    /// Whether the device is still plugged in. Devices on the far side of
+    /// a connection report false once it has closed.
+    fn connected(&self) -> bool {
+        true
+    }
+}
+
+/// Collects every byte the game sends, answering like an unconnected port.
+/// Clones share the same buffer, so the host keeps one to read from while
+/// the other is plugged in.
+#[derive(Clone, Default)]
+pub struct SerialSink {
+    output: Rc<RefCell<Vec<u8>>>,
+}
+
+impl SerialSink {
+    pub fn new() -> SerialSink {
+        SerialSink::default()
+    }
+
+    /// Everything received since the last call, as UTF-8 text. Bytes that
+    /// don't decode come out as U+FFFD.
+    pub fn take(&self) -> String {
+        String::from_utf8_lossy(&self.output.take()).into_owned()
+    }
+}
+
+impl SerialDevice for SerialSink {
+    fn exchange(&mut self, outgoing: u8) -> u8 {
+        self.output.borrow_mut().push(outgoing);
+        0xFF
+    }
+}
+
+pub struct Serial {
+    sb: u8,
+    sc: u8,
+    device: Option<Box<dyn SerialDevice>>,
+    // Byte being sent by the transfer in progress
+    outgoing: u8,
+    bits_left: u8,
+    cycles: u32,
+}
+
+impl Serial {
+    pub fn new() -> Serial {
+        Serial {
+            sb: 0x00,
+            sc: 0x00,
+            device: None,
+            outgoing: 0x00,
+            bits_left: 0,
+            cycles: 0,
+        }
+    }
+
+    pub fn connect(&mut self, device: Box<dyn SerialDevice>) {
+        self.device = Some(device);
+    }
+
+    pub fn read_register(&self, address: u16) -> u8 {
+        match address {
+            0xFF01 => self.sb,
+            0xFF02 => 0x7E | self.sc,
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write_register(&mut self, address: u16, value: u8) {
+        match address {
+            0xFF01 => self.sb = value,
+            0xFF02 => {
+                self.sc = value & (TRANSFER_START | INTERNAL_CLOCK);
+                if self.sc & TRANSFER_START != 0 {
+                    self.outgoing = self.sb;
+                    self.bits_left = 8;
+                    self.cycles = 0;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Advances by one M-cycle, returning whether a transfer finished and
+    /// requests the serial interrupt.
+    pub fn step(&mut self) -> bool {
+        if self.sc & TRANSFER_START == 0 {
+            return false;
+        }
+
+        // With the external clock selected the partner drives the transfer,
+        // and nothing ever does while one isn't connected and clocking
+        if self.sc & INTERNAL_CLOCK == 0 {
+            let incoming = self
+                .device
+                .as_mut()
+                .and_then(|device| device.external_clock(self.outgoing));
+            return match incoming {
+                Some(incoming) => self.finish(incoming),
+                None => false,
+            };
+        }
+
+        self.cycles += 1;
+        if self.cycles < CYCLES_PER_BIT {
+            return false;
+        }
+        self.cycles = 0;
+
+        // With nobody on the other end the input line floats high
+        self.sb = self.sb << 1 | 1;
+        self.bits_left -= 1;
+        if self.bits_left > 0 {
+            return false;
+        }
+
+        let incoming = match self.device.as_mut() {
+            Some(device) => device.exchange(self.outgoing),
+            None => self.sb,
+        };
+        self.finish(incoming)
+    }
+
+    fn finish(&mut self, incoming: u8) -> bool {
+        self.sb = incoming;
+        self.sc &= !TRANSFER_START;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transfer(serial: &mut Serial, value: u8) -> u32 {
+        serial.write_register(0xFF01, value);
+        serial.write_register(0xFF02, TRANSFER_START | INTERNAL_CLOCK);
+        let mut cycles = 1;
+        while !serial.step() {
+            cycles += 1;
+        }
+        cycles
+    }
+
+    #[test]
+    fn internal_clock_transfer_takes_eight_bits_at_8192_hz() {
+        let mut serial = Serial::new();
+        assert_eq!(transfer(&mut serial, 0x42), 8 * CYCLES_PER_BIT);
+        assert_eq!(serial.read_register(0xFF02), 0x7F);
+    }
+
+    #[test]
+    fn unconnected_port_receives_ff() {
+        let mut serial = Serial::new();
+        transfer(&mut serial, 0x42);
+        assert_eq!(serial.read_register(0xFF01), 0xFF);
+    }
+
+    #[test]
+    fn sink_collects_sent_bytes() {
+        let sink = SerialSink::new();
+        let mut serial = Serial::new();
+        serial.connect(Box::new(sink.clone()));
+
+        for byte in b"Passed" {
+            transfer(&mut serial, *byte);
+        }
+
+        assert_eq!(sink.take(), "Passed");
+        assert_eq!(sink.take(), "");
+    }
+
+    #[test]
+    fn sink_decodes_utf8_and_replaces_stray_bytes() {
+        let sink = SerialSink::new();
+        let mut serial = Serial::new();
+        serial.connect(Box::new(sink.clone()));
+
+        for byte in "caf\u{e9} ".bytes().chain([0x80, 0xFF]) {
+            transfer(&mut serial, byte);
+        }
+
+        assert_eq!(sink.take(), "caf\u{e9} \u{FFFD}\u{FFFD}");
+    }
+
+    #[test]
+    fn external_clock_waits_for_partner() {
+        let mut serial = Serial::new();
+        serial.write_register(0xFF02, TRANSFER_START);
+        for _ in 0..10_000 {
+            assert!(!serial.step());
+        }
+        assert_eq!(serial.read_register(0xFF02), 0xFE);
+    }
+}
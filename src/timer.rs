@@ -0,0 +1,204 @@
+// Divider bit whose falling edge clocks TIMA, indexed by TAC bits 0-1
+// (4096, 262144, 65536 and 16384 Hz)
+const TAC_BITS: [u16; 4] = [1 << 9, 1 << 3, 1 << 5, 1 << 7];
+const TAC_ENABLE: u8 = 0x04;
+
+pub struct Timer {
+    // DIV is the upper byte of this counter, which runs at 4 MHz
+    divider: u16,
+    tima: u8,
+    tma: u8,
+    tac: u8,
+    // TIMA overflowed and reads 0; it is reloaded on the next M-cycle
+    overflow: bool,
+    // TIMA was reloaded from TMA this M-cycle
+    reloading: bool,
+}
+
+impl Timer {
+    pub fn new() -> Timer {
+        Timer {
+            // Where the boot ROM leaves it on DMG
+            divider: 0xABCC,
+            tima: 0,
+            tma: 0,
+            tac: 0,
+            overflow: false,
+            reloading: false,
+        }
+    }
+
+    pub fn read_register(&self, address: u16) -> u8 {
+        match address {
+            0xFF04 => (self.divider >> 8) as u8,
+            0xFF05 => self.tima,
+            0xFF06 => self.tma,
+            0xFF07 => 0xF8 | self.tac,
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write_register(&mut self, address: u16, value: u8) {
+        match address {
+            0xFF04 => self.reset_divider(),
+            0xFF05 => {
+                // A write in the overflow cycle cancels the reload; one in
+                // the reload cycle loses against TMA
+                if !self.reloading {
+                    self.tima = value;
+                    self.overflow = false;
+                }
+            }
+            0xFF06 => {
+                self.tma = value;
+                if self.reloading {
+                    self.tima = value;
+                }
+            }
+            0xFF07 => {
+                // Disabling the timer or switching to a bit that is low
+                // looks like a falling edge and bumps TIMA on DMG
+                let high = self.input();
+                self.tac = value & 0x07;
+                self.detect_edge(high);
+            }
+            _ => {}
+        }
+    }
+
+    /// The internal 16-bit counter DIV is the top half of.
+    pub fn divider(&self) -> u16 {
+        self.divider
+    }
+
+    /// Clears the internal counter, which increments TIMA if the selected
+    /// bit was set.
+    pub fn reset_divider(&mut self) {
+        let high = self.input();
+        self.divider = 0;
+        self.detect_edge(high);
+    }
+
+    /// Advances the timer by one M-cycle, returning whether a timer
+    /// interrupt is requested.
+    pub fn step(&mut self) -> bool {
+        self.reloading = false;
+
+        let mut interrupt = false;
+        if self.overflow {
+            self.overflow = false;
+            self.reloading = true;
+            self.tima = self.tma;
+            interrupt = true;
+        }
+
+        let high = self.input();
+        self.divider = self.divider.wrapping_add(4);
+        self.detect_edge(high);
+
+        interrupt
+    }
+
+    /// The selected divider bit ANDed with the enable bit, which is what
+    /// TIMA's falling edge detector watches.
+    fn input(&self) -> bool {
+        self.tac & TAC_ENABLE != 0 && self.divider & TAC_BITS[(self.tac & 0x03) as usize] != 0
+    }
+
+    fn detect_edge(&mut self, was_high: bool) {
+        if !was_high || self.input() {
+            return;
+        }
+
+        let (tima, overflow) = self.tima.overflowing_add(1);
+        self.tima = tima;
+        if overflow {
+            self.overflow = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timer(tac: u8) -> Timer {
+        let mut timer = Timer::new();
+        timer.write_register(0xFF04, 0);
+        timer.write_register(0xFF07, tac);
+        timer
+    }
+
+    #[test]
+    fn tima_counts_at_selected_rate() {
+        // 262144 Hz: one increment every 4 M-cycles
+        let mut timer = timer(0x05);
+        for _ in 0..40 {
+            timer.step();
+        }
+        assert_eq!(timer.read_register(0xFF05), 10);
+    }
+
+    #[test]
+    fn overflow_reloads_one_cycle_late_and_interrupts() {
+        let mut timer = timer(0x05);
+        timer.write_register(0xFF06, 0x80);
+        timer.write_register(0xFF05, 0xFF);
+
+        for _ in 0..4 {
+            assert!(!timer.step());
+        }
+        assert_eq!(timer.read_register(0xFF05), 0x00);
+        assert!(timer.step());
+        assert_eq!(timer.read_register(0xFF05), 0x80);
+    }
+
+    #[test]
+    fn tima_write_during_overflow_cancels_reload() {
+        let mut timer = timer(0x05);
+        timer.write_register(0xFF06, 0x80);
+        timer.write_register(0xFF05, 0xFF);
+        for _ in 0..4 {
+            timer.step();
+        }
+
+        timer.write_register(0xFF05, 0x12);
+        assert!(!timer.step());
+        assert_eq!(timer.read_register(0xFF05), 0x12);
+    }
+
+    #[test]
+    fn writes_during_reload_cycle_follow_tma() {
+        let mut timer = timer(0x05);
+        timer.write_register(0xFF06, 0x80);
+        timer.write_register(0xFF05, 0xFF);
+        for _ in 0..5 {
+            timer.step();
+        }
+
+        timer.write_register(0xFF05, 0x12);
+        assert_eq!(timer.read_register(0xFF05), 0x80);
+        timer.write_register(0xFF06, 0x34);
+        assert_eq!(timer.read_register(0xFF05), 0x34);
+    }
+
+    #[test]
+    fn div_reset_with_selected_bit_high_increments_tima() {
+        let mut timer = timer(0x05);
+        // Bit 3 of the counter is set after two M-cycles
+        timer.step();
+        timer.step();
+        timer.write_register(0xFF04, 0);
+        assert_eq!(timer.read_register(0xFF05), 1);
+        assert_eq!(timer.read_register(0xFF04), 0);
+    }
+
+    #[test]
+    fn disabling_with_selected_bit_high_increments_tima() {
+        let mut timer = timer(0x05);
+        timer.step();
+        timer.step();
+        timer.write_register(0xFF07, 0x01);
+        assert_eq!(timer.read_register(0xFF05), 1);
+    }
+}